@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use calloop::{
+    timer::{TimeoutAction, Timer},
+    EventLoop, RegistrationToken,
+};
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+const TIMERS: u64 = 10_000;
+
+fn insert_timers(event_loop: &EventLoop<'static, ()>) -> Vec<RegistrationToken> {
+    let handle = event_loop.handle();
+    (0..TIMERS)
+        .map(|i| {
+            // Spread the deadlines so that the timers are not inserted in order.
+            let timeout = Duration::from_secs(3600) + Duration::from_millis((i * 7919) % TIMERS);
+            handle
+                .insert_source(Timer::from_duration(timeout), |_, _, _| TimeoutAction::Drop)
+                .unwrap()
+        })
+        .collect()
+}
+
+fn insert(c: &mut Criterion) {
+    c.bench_function("insert 10k timers", |b| {
+        b.iter_batched(
+            || EventLoop::<()>::try_new().unwrap(),
+            |event_loop| {
+                insert_timers(&event_loop);
+                event_loop
+            },
+            BatchSize::PerIteration,
+        );
+    });
+}
+
+fn cancel(c: &mut Criterion) {
+    c.bench_function("cancel 10k timers", |b| {
+        b.iter_batched(
+            || {
+                let event_loop = EventLoop::<()>::try_new().unwrap();
+                let tokens = insert_timers(&event_loop);
+                (event_loop, tokens)
+            },
+            |(event_loop, tokens)| {
+                let handle = event_loop.handle();
+                for token in tokens {
+                    handle.remove(token);
+                }
+                event_loop
+            },
+            BatchSize::PerIteration,
+        );
+    });
+}
+
+fn expire(c: &mut Criterion) {
+    c.bench_function("expire 10k timers", |b| {
+        b.iter_batched(
+            || {
+                let event_loop = EventLoop::<()>::try_new().unwrap();
+                let handle = event_loop.handle();
+                for _ in 0..TIMERS {
+                    handle
+                        .insert_source(Timer::immediate(), |_, _, _| TimeoutAction::Drop)
+                        .unwrap();
+                }
+                event_loop
+            },
+            |mut event_loop| {
+                event_loop.dispatch(Duration::ZERO, &mut ()).unwrap();
+                event_loop
+            },
+            BatchSize::PerIteration,
+        );
+    });
+}
+
+criterion_group!(benches, insert, cancel, expire);
+criterion_main!(benches);
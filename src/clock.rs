@@ -0,0 +1,230 @@
+//! Clocks driving the timers of an event loop
+//!
+//! By default, the deadlines of [`Timer`](crate::timer::Timer)s and
+//! [`TimeoutFuture`](crate::timer::TimeoutFuture)s are measured against the system monotonic
+//! clock, through [`Instant::now()`]. An event loop created with
+//! [`EventLoop::with_clock()`](crate::EventLoop::with_clock) uses the given [`LoopClock`]
+//! instead.
+//!
+//! The [`ManualClock`] is a clock that only advances when told to, which makes it possible to test
+//! timer-heavy code deterministically and without waiting:
+//!
+//! ```
+//! use std::time::Duration;
+//!
+//! use calloop::{
+//!     clock::ManualClock,
+//!     timer::{TimeoutAction, Timer},
+//!     EventLoop,
+//! };
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let clock = ManualClock::new();
+//! let mut event_loop = EventLoop::<bool>::with_clock(clock.clone())?;
+//!
+//! event_loop.handle().insert_source(
+//!     Timer::from_duration(Duration::from_secs(3600)),
+//!     |_, _, fired| {
+//!         *fired = true;
+//!         TimeoutAction::Drop
+//!     },
+//! )?;
+//!
+//! let mut fired = false;
+//! event_loop.dispatch(Duration::ZERO, &mut fired)?;
+//! assert!(!fired);
+//!
+//! // An hour passes, instantly.
+//! clock.advance(Duration::from_secs(3600));
+//! event_loop.dispatch(Duration::ZERO, &mut fired)?;
+//! assert!(fired);
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// A source of time for the timers of an event loop
+pub trait LoopClock {
+    /// The current time of this clock
+    fn now(&self) -> Instant;
+
+    /// How long the event loop should wait for this clock to reach `deadline`
+    ///
+    /// This is only called with deadlines later than [`now()`](Self::now), and the returned
+    /// duration is real time, used to bound how long the event loop blocks while waiting for
+    /// events. `None` means that the clock does not reach the deadline on its own, in which case
+    /// the event loop does not wake up for it.
+    ///
+    /// The default implementation assumes the clock follows real time.
+    fn duration_until(&self, deadline: Instant) -> Option<Duration> {
+        Some(deadline.saturating_duration_since(self.now()))
+    }
+}
+
+/// The system monotonic clock, used by default
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl LoopClock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only advances when told to
+///
+/// Clones of a `ManualClock` share the same time, so you can keep one to advance the clock of the
+/// event loop you gave another one to.
+///
+/// Since this clock never advances on its own, an event loop using it never waits for its timers:
+/// advancing the clock does not wake up the loop, but the timers that became due fire during the
+/// next dispatch.
+#[derive(Clone, Debug)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl ManualClock {
+    /// Create a clock, starting at the current time of the system monotonic clock
+    pub fn new() -> ManualClock {
+        ManualClock::starting_at(Instant::now())
+    }
+
+    /// Create a clock, starting at the given instant
+    pub fn starting_at(now: Instant) -> ManualClock {
+        ManualClock {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Advance the clock by the given duration
+    pub fn advance(&self, duration: Duration) {
+        let mut now = self.now.lock().unwrap();
+        *now += duration;
+    }
+
+    /// Advance the clock up to the given instant
+    ///
+    /// Does nothing if the clock is already past `instant`: the clock never goes backwards.
+    pub fn advance_to(&self, instant: Instant) {
+        let mut now = self.now.lock().unwrap();
+        *now = std::cmp::max(*now, instant);
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> ManualClock {
+        ManualClock::new()
+    }
+}
+
+impl LoopClock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn duration_until(&self, _: Instant) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::Future,
+        pin::Pin,
+        sync::atomic::{AtomicBool, Ordering},
+        task,
+    };
+
+    use super::*;
+    use crate::{
+        timer::{TimeoutAction, TimeoutFuture, Timer},
+        EventLoop,
+    };
+
+    #[test]
+    fn manual_clock_timers() {
+        let clock = ManualClock::new();
+        let mut event_loop = EventLoop::<Vec<u32>>::with_clock(clock.clone()).unwrap();
+        let handle = event_loop.handle();
+
+        handle
+            .insert_source(
+                Timer::from_duration(Duration::from_secs(10)),
+                |deadline, _, fired| {
+                    fired.push(10);
+                    // Deadlines are expressed in the time of the loop clock.
+                    TimeoutAction::ToInstant(deadline + Duration::from_secs(10))
+                },
+            )
+            .unwrap();
+        handle
+            .insert_source(
+                Timer::from_deadline(handle.now() + Duration::from_secs(15)),
+                |_, _, fired| {
+                    fired.push(15);
+                    TimeoutAction::Drop
+                },
+            )
+            .unwrap();
+
+        let mut fired = Vec::new();
+
+        // No timer is due: this does not wait for them.
+        let start = Instant::now();
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut fired)
+            .unwrap();
+        assert!(fired.is_empty());
+        assert_eq!(event_loop.next_timeout(), None);
+
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(event_loop.next_timeout(), Some(Duration::ZERO));
+        event_loop.dispatch(None, &mut fired).unwrap();
+        assert_eq!(fired, [10]);
+
+        clock.advance(Duration::from_secs(10));
+        event_loop.dispatch(None, &mut fired).unwrap();
+        fired.sort_unstable();
+        assert_eq!(fired, [10, 10, 15]);
+
+        // Only the timeout of the first dispatch was actually waited for.
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn manual_clock_timeout_future() {
+        struct Flag(AtomicBool);
+        impl task::Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let clock = ManualClock::new();
+        let mut event_loop = EventLoop::<()>::with_clock(clock.clone()).unwrap();
+
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = task::Waker::from(flag.clone());
+        let mut cx = task::Context::from_waker(&waker);
+
+        let mut future =
+            TimeoutFuture::from_duration(&event_loop.handle(), Duration::from_secs(60));
+        assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+
+        clock.advance(Duration::from_secs(59));
+        event_loop.dispatch(Duration::ZERO, &mut ()).unwrap();
+        assert!(!flag.0.load(Ordering::SeqCst));
+        assert!(Pin::new(&mut future).poll(&mut cx).is_pending());
+
+        clock.advance(Duration::from_secs(1));
+        event_loop.dispatch(Duration::ZERO, &mut ()).unwrap();
+        assert!(flag.0.load(Ordering::SeqCst));
+        assert!(Pin::new(&mut future).poll(&mut cx).is_ready());
+    }
+}
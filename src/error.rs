@@ -13,10 +13,16 @@
 //!   event source cannot be added to the loop and needs to be given back to the
 //!   caller
 //!
+//! Errors of event sources generated while the event loop dispatches their
+//! events are reported as a [`SourceError`], which identifies the failing
+//! source.
+//!
 //! [`insert_source()`]: crate::LoopHandle::insert_source()
 
 use std::fmt::{self, Debug, Formatter};
 
+use crate::RegistrationToken;
+
 /// The primary error type used by Calloop covering internal errors and I/O
 /// errors that arise during loop operations such as source registration or
 /// event dispatching.
@@ -25,9 +31,18 @@ pub enum Error {
     /// When an event source is registered (or re- or un-registered) with the
     /// event loop, this error variant will occur if the token Calloop uses to
     /// keep track of the event source is not valid.
+    ///
+    /// This is also the case of a [`RegistrationToken`]
+    /// whose source was removed from the event loop, even if another source
+    /// was inserted since then.
     #[error("invalid token provided to internal function")]
     InvalidToken,
 
+    /// An event source could not be accessed because it is in use, for example from within its
+    /// own callback.
+    #[error("the event source is in use")]
+    SourceBusy,
+
     /// This variant wraps a [`std::io::Error`], which might arise from
     /// Calloop's internal operations.
     #[error("underlying IO error")]
@@ -39,6 +54,10 @@ pub enum Error {
     /// [`EventSource::process_events()`]: crate::EventSource::process_events()
     #[error("other error during loop operation")]
     OtherError(#[from] Box<dyn std::error::Error + Sync + Send>),
+
+    /// An event source failed to process its events, see [`SourceError`].
+    #[error(transparent)]
+    SourceError(#[from] SourceError),
 }
 
 impl From<nix::errno::Errno> for Error {
@@ -56,7 +75,40 @@ impl From<Error> for std::io::Error {
         match err {
             Error::IoError(source) => source,
             Error::InvalidToken => Self::new(std::io::ErrorKind::InvalidInput, err.to_string()),
+            Error::SourceBusy => Self::new(std::io::ErrorKind::Other, err.to_string()),
             Error::OtherError(source) => Self::new(std::io::ErrorKind::Other, source),
+            Error::SourceError(source) => Self::new(std::io::ErrorKind::Other, source),
+        }
+    }
+}
+
+/// An error generated by an event source while the event loop dispatched its events
+///
+/// This is what [`EventLoop::dispatch()`](crate::EventLoop::dispatch) returns when
+/// [`EventSource::process_events()`](crate::EventSource::process_events) fails, depending on the
+/// [`ErrorPolicy`](crate::ErrorPolicy) of the loop.
+#[derive(thiserror::Error, Debug)]
+pub struct SourceError {
+    /// The token of the failing source
+    pub token: RegistrationToken,
+    /// The name of the failing source, if it was given one with
+    /// [`LoopHandle::set_name()`](crate::LoopHandle::set_name)
+    pub name: Option<String>,
+    /// The error generated by the source
+    #[source]
+    pub error: Box<dyn std::error::Error + Sync + Send>,
+}
+
+impl fmt::Display for SourceError {
+    #[cfg_attr(feature = "nightly_coverage", no_coverage)]
+    fn fmt(&self, formatter: &mut Formatter) -> fmt::Result {
+        match &self.name {
+            Some(name) => write!(formatter, "error processing events of source {:?}", name),
+            None => write!(
+                formatter,
+                "error processing events of source {:?}",
+                self.token
+            ),
         }
     }
 }
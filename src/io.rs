@@ -19,7 +19,7 @@ use nix::fcntl::{fcntl, FcntlArg, OFlag};
 use futures_io::{AsyncRead, AsyncWrite, IoSlice, IoSliceMut};
 
 use crate::{
-    loop_logic::{LoopInner, MAX_SOURCES_MASK},
+    loop_logic::{LoopInner, DEFAULT_PRIORITY},
     sources::EventDispatcher,
     Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory,
 };
@@ -63,8 +63,12 @@ impl<'l, F: AsFd> Async<'l, F> {
             interest: Interest::EMPTY,
             last_readiness: Readiness::EMPTY,
         }));
-        let key = inner.sources.borrow_mut().insert(dispatcher.clone());
-        dispatcher.borrow_mut().token = Some(Token { key });
+        let token = inner.insert_entry(
+            &mut inner.sources.borrow_mut(),
+            dispatcher.clone(),
+            DEFAULT_PRIORITY,
+        );
+        dispatcher.borrow_mut().token = Some(Token { key: token.id() });
         inner.register(&dispatcher)?;
 
         // Straightforward casting would require us to add the bound `Data: 'l` but we don't actually need it
@@ -195,17 +199,14 @@ impl<'l, Data> IoLoopInner for LoopInner<'l, Data> {
     }
 
     fn kill(&self, dispatcher: &RefCell<IoDispatcher>) {
-        let key = dispatcher
+        let id = dispatcher
             .borrow()
             .token
             .expect("No token for IO dispatcher")
-            .key
-            & MAX_SOURCES_MASK;
-        let _source = self
-            .sources
-            .borrow_mut()
-            .try_remove(key)
-            .expect("Attempting to remove a non-existent source?!");
+            .key;
+        if let Some(token) = self.token_of_id(id) {
+            let _source = self.take_source(&token);
+        }
     }
 }
 
@@ -230,6 +231,7 @@ impl<Data> EventDispatcher<Data> for RefCell<IoDispatcher> {
         readiness: Readiness,
         _token: Token,
         _data: &mut Data,
+        #[cfg(feature = "metrics")] _counters: &mut crate::metrics::SourceCounters,
     ) -> crate::Result<PostAction> {
         let mut disp = self.borrow_mut();
         disp.last_readiness = readiness;
@@ -263,6 +265,20 @@ impl<Data> EventDispatcher<Data> for RefCell<IoDispatcher> {
     fn post_run(&self, _data: &mut Data) -> crate::Result<()> {
         Ok(())
     }
+
+    fn set_event_budget(&self, _budget: Option<std::num::NonZeroUsize>) {}
+
+    fn has_pending_events(&self) -> bool {
+        false
+    }
+
+    fn on_shutdown(&self) -> bool {
+        true
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<IoDispatcher>()
+    }
 }
 
 /*
@@ -139,6 +139,11 @@
 //!
 //! Those platforms *should* work based on the fact that they have the same polling mechanism as
 //! tested platforms, but some subtle bugs might still occur.
+//!
+//! On Linux, activating the `io_uring` cargo feature makes the [`Poll`] use io_uring instead of
+//! epoll to wait for events, batching the registration changes with the wait into a single system
+//! call. The event loop falls back to epoll if the running kernel does not support it (io_uring is
+//! used from Linux 5.11 onwards).
 
 #![warn(missing_docs, missing_debug_implementations)]
 #![allow(clippy::needless_doctest_main)]
@@ -149,13 +154,20 @@ mod sys;
 
 pub use sys::{Interest, Mode, Poll, Readiness, Token, TokenFactory};
 
-pub use self::loop_logic::{EventLoop, LoopHandle, LoopSignal, RegistrationToken};
+pub use self::loop_logic::{
+    ErrorPolicy, EventLoop, GroupGuard, GroupToken, LoopHandle, LoopSignal, PanicAction,
+    RegistrationToken, SourceGuard, SourceHandle, SourceInfo, SourcePanic,
+};
 pub use self::sources::*;
 
+pub mod clock;
 pub mod error;
-pub use error::{Error, InsertError, Result};
+pub use error::{Error, InsertError, Result, SourceError};
 
 pub mod io;
 mod loop_logic;
 mod macros;
+#[cfg(feature = "metrics")]
+#[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+pub mod metrics;
 mod sources;
@@ -1,9 +1,12 @@
-use std::cell::{Cell, RefCell};
+use std::any::Any;
+use std::cell::{Cell, Ref, RefCell, RefMut};
 use std::fmt::Debug;
 use std::io;
+use std::num::NonZeroUsize;
+use std::panic::{self, AssertUnwindSafe};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 #[cfg(feature = "block_on")]
@@ -12,9 +15,10 @@ use std::future::Future;
 use io_lifetimes::AsFd;
 use slab::Slab;
 
+use crate::clock::{LoopClock, SystemClock};
 use crate::sources::{Dispatcher, EventSource, Idle, IdleDispatcher};
-use crate::sys::Notifier;
-use crate::{EventDispatcher, InsertError, Poll, PostAction, TokenFactory};
+use crate::sys::{Notifier, PollEvent};
+use crate::{EventDispatcher, InsertError, Poll, PostAction, Readiness, SourceError, TokenFactory};
 
 type IdleCallback<'i, Data> = Rc<RefCell<dyn IdleDispatcher<Data> + 'i>>;
 
@@ -42,22 +46,391 @@ pub(crate) const MAX_SOURCES_TOTAL: usize = 1 << MAX_SOURCES;
 pub(crate) const MAX_SUBSOURCES_TOTAL: usize = 1 << MAX_SUBSOURCES;
 pub(crate) const MAX_SOURCES_MASK: usize = MAX_SOURCES_TOTAL - 1;
 
+// The number of bits of the source ID used to store the key of the source.
+//
+// The remaining bits of the source ID store the lowest bits of the generation of the source, so
+// that the events polled for a source removed during the same dispatch are not given to the source
+// that reuses its key.
+#[cfg(target_pointer_width = "64")]
+pub(crate) const SOURCE_KEY_BITS: u32 = 28;
+#[cfg(target_pointer_width = "32")]
+pub(crate) const SOURCE_KEY_BITS: u32 = 19;
+#[cfg(target_pointer_width = "16")]
+pub(crate) const SOURCE_KEY_BITS: u32 = 7;
+
+pub(crate) const SOURCE_KEY_MASK: usize = (1 << SOURCE_KEY_BITS) - 1;
+pub(crate) const GENERATION_MASK: usize = MAX_SOURCES_MASK >> SOURCE_KEY_BITS;
+
 /// A token representing a registration in the [`EventLoop`].
 ///
 /// This token is given to you by the [`EventLoop`] when an [`EventSource`] is inserted or
 /// a [`Dispatcher`] is registered. You can use it to [disable](LoopHandle#method.disable),
 /// [enable](LoopHandle#method.enable), [update`](LoopHandle#method.update),
 /// [remove](LoopHandle#method.remove) or [kill](LoopHandle#method.kill) it.
+///
+/// Dropping the token does not remove the source, see [`SourceGuard`] for that.
+///
+/// Once its source is removed, the token is invalid, even if another source is inserted in its
+/// place: using it fails with [`Error::InvalidToken`](crate::Error::InvalidToken).
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct RegistrationToken {
     key: usize,
+    generation: u32,
+}
+
+impl RegistrationToken {
+    // The ID of the source in the tokens of the polling system, made of its key and of the lowest
+    // bits of its generation.
+    pub(crate) fn id(&self) -> usize {
+        self.key | ((self.generation as usize & GENERATION_MASK) << SOURCE_KEY_BITS)
+    }
+}
+
+/// A source removed from the event loop when dropped
+///
+/// This guard is given by [`LoopHandle::guard_source()`]. Unlike a
+/// [`RegistrationToken`], it cannot be copied: the source is removed once its owner drops it,
+/// unless it is released with [`forget()`](Self::forget) or [`into_token()`](Self::into_token).
+pub struct SourceGuard<'l, Data> {
+    handle: LoopHandle<'l, Data>,
+    token: Option<RegistrationToken>,
+}
+
+impl<'l, Data> SourceGuard<'l, Data> {
+    /// The token of the source
+    pub fn token(&self) -> RegistrationToken {
+        self.token.expect("the token is only taken on drop")
+    }
+
+    /// Release the source, leaving it in the event loop
+    pub fn forget(self) {
+        self.into_token();
+    }
+
+    /// Release the source, leaving it in the event loop, and get its token
+    pub fn into_token(mut self) -> RegistrationToken {
+        self.token.take().expect("the token is only taken on drop")
+    }
+}
+
+impl<'l, Data> std::fmt::Debug for SourceGuard<'l, Data> {
+    #[cfg_attr(feature = "nightly_coverage", no_coverage)]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SourceGuard")
+            .field("token", &self.token)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'l, Data> Drop for SourceGuard<'l, Data> {
+    fn drop(&mut self) {
+        if let Some(token) = self.token.take() {
+            self.handle.remove(token);
+        }
+    }
+}
+
+/// A typed handle to a source inserted in the event loop
+///
+/// This handle is given by [`LoopHandle::insert_source_with_handle()`], or by
+/// [`LoopHandle::source_handle()`] for a registered [`Dispatcher`]. It gives access to the
+/// source with [`with_source()`](Self::with_source), which updates the registration of the source
+/// afterwards, so that changes like [`Timer::set_deadline()`](crate::timer::Timer::set_deadline)
+/// take effect.
+///
+/// Like a [`RegistrationToken`], dropping the handle does not remove the source from the loop.
+pub struct SourceHandle<'l, S, Data> {
+    handle: LoopHandle<'l, Data>,
+    dispatcher: Dispatcher<'l, S, Data>,
+    token: RegistrationToken,
+}
+
+impl<'l, S, Data> SourceHandle<'l, S, Data>
+where
+    S: EventSource + 'l,
+{
+    /// The token of the source
+    pub fn token(&self) -> RegistrationToken {
+        self.token
+    }
+
+    /// Access the source, then update its registration
+    ///
+    /// This can be called from the callbacks of other sources. The registration of a disabled
+    /// source is not updated, it will be when the source is enabled again.
+    ///
+    /// Fails without calling `f` with [`Error::InvalidToken`](crate::Error::InvalidToken) if the
+    /// source was removed from the loop, and with [`Error::SourceBusy`](crate::Error::SourceBusy)
+    /// if the source is in use: it is borrowed while its events are dispatched, so this is the
+    /// case from within its own callback.
+    pub fn with_source<R, F>(&self, f: F) -> crate::Result<R>
+    where
+        F: FnOnce(&mut S) -> R,
+    {
+        let enabled = self.handle.inner.source(&self.token)?.enabled.get();
+        let mut source = self
+            .dispatcher
+            .try_as_source_mut()
+            .ok_or(crate::Error::SourceBusy)?;
+        let ret = f(&mut source);
+        drop(source);
+        if enabled {
+            self.handle.update(&self.token)?;
+        }
+        Ok(ret)
+    }
+}
+
+impl<'l, S, Data> std::fmt::Debug for SourceHandle<'l, S, Data> {
+    #[cfg_attr(feature = "nightly_coverage", no_coverage)]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SourceHandle")
+            .field("token", &self.token)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A token representing a group of sources in the [`EventLoop`].
+///
+/// Groups are created with [`LoopHandle::create_group()`], and let you
+/// [disable](LoopHandle#method.disable_group), [enable](LoopHandle#method.enable_group) or
+/// [remove](LoopHandle#method.remove_group) all the sources added to them at once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct GroupToken {
+    id: u64,
+}
+
+/// A group of sources removed from the event loop when dropped
+///
+/// See [`LoopHandle::guard_group()`].
+pub struct GroupGuard<'l, Data> {
+    handle: LoopHandle<'l, Data>,
+    group: Option<GroupToken>,
+}
+
+impl<'l, Data> GroupGuard<'l, Data> {
+    /// The token of the group
+    pub fn token(&self) -> GroupToken {
+        self.group.expect("the group is only taken on drop")
+    }
+
+    /// Release the group, without removing its sources
+    pub fn into_token(mut self) -> GroupToken {
+        self.group.take().expect("the group is only taken on drop")
+    }
+}
+
+impl<'l, Data> std::fmt::Debug for GroupGuard<'l, Data> {
+    #[cfg_attr(feature = "nightly_coverage", no_coverage)]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GroupGuard")
+            .field("group", &self.group)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'l, Data> Drop for GroupGuard<'l, Data> {
+    fn drop(&mut self) {
+        if let Some(group) = self.group.take() {
+            self.handle.remove_group(group);
+        }
+    }
+}
+
+// The priority of sources inserted without an explicit priority.
+pub(crate) const DEFAULT_PRIORITY: i32 = 0;
+
+pub(crate) struct SourceEntry<'l, Data> {
+    pub(crate) dispatcher: Rc<dyn EventDispatcher<Data> + 'l>,
+    pub(crate) priority: i32,
+    pub(crate) name: Option<String>,
+    pub(crate) enabled: Cell<bool>,
+    pub(crate) group: Option<GroupToken>,
+    pub(crate) generation: u32,
+    #[cfg(feature = "metrics")]
+    pub(crate) metrics: RefCell<crate::metrics::SourceCounters>,
+}
+
+/// Information about a source inserted in an event loop
+///
+/// See [`LoopHandle::sources()`].
+#[derive(Clone, Debug)]
+pub struct SourceInfo {
+    /// The token of the source
+    pub token: RegistrationToken,
+    /// The name of the source, if it was given one with [`LoopHandle::set_name()`]
+    pub name: Option<String>,
+    /// The name of the type of the source
+    ///
+    /// This is given by [`std::any::type_name`], and is only meant for diagnostics.
+    pub type_name: &'static str,
+    /// Whether the source is enabled
+    pub enabled: bool,
+    /// The group of the source, if it was added to one
+    pub group: Option<GroupToken>,
+    /// The number of file descriptors the source has registered in the polling system
+    pub fds: usize,
+    /// The number of timers the source has pending
+    pub timers: usize,
+}
+
+/// What the event loop does when an event source fails to process its events
+///
+/// See [`EventLoop::set_error_policy()`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Return the error from the dispatching method
+    ///
+    /// The events of the other sources are still processed, and the error is returned once they
+    /// have been. If several sources fail, the first error is returned and the others are logged.
+    Propagate,
+    /// Log the error, and keep dispatching the events of the source
+    LogAndContinue,
+    /// Log the error, and remove the source from the event loop
+    RemoveSource,
+}
+
+/// What the event loop does with a source whose callback panicked
+///
+/// See [`EventLoop::catch_panics()`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PanicAction {
+    /// Remove the source from the event loop
+    Remove,
+    /// Disable the source, it can be enabled again with [`LoopHandle::enable()`]
+    Disable,
+}
+
+/// A panic caught while a source was processing its events
+///
+/// See [`EventLoop::catch_panics()`].
+#[derive(Debug)]
+pub struct SourcePanic {
+    /// The token of the source that panicked
+    pub token: RegistrationToken,
+    /// The name of the source, if it was given one with [`LoopHandle::set_name()`]
+    pub name: Option<String>,
+    /// The payload of the panic
+    pub payload: Box<dyn Any + Send>,
+}
+
+impl SourcePanic {
+    /// The message of the panic, if it has one
+    pub fn message(&self) -> Option<&str> {
+        if let Some(message) = self.payload.downcast_ref::<&str>() {
+            Some(message)
+        } else {
+            self.payload.downcast_ref::<String>().map(String::as_str)
+        }
+    }
+}
+
+struct PanicHandler<'l> {
+    action: PanicAction,
+    callback: Box<dyn FnMut(SourcePanic) + 'l>,
 }
 
 pub(crate) struct LoopInner<'l, Data> {
     pub(crate) poll: RefCell<Poll>,
-    pub(crate) sources: RefCell<Slab<Rc<dyn EventDispatcher<Data> + 'l>>>,
+    pub(crate) sources: RefCell<Slab<SourceEntry<'l, Data>>>,
     idles: RefCell<Vec<IdleCallback<'l, Data>>>,
     pending_action: Cell<PostAction>,
+    event_budget: Cell<Option<NonZeroUsize>>,
+    error_policy: Cell<ErrorPolicy>,
+    // The identifier of the next group, identifiers are never reused.
+    next_group: Cell<u64>,
+    // The generation of the last source inserted with each key, kept after its removal.
+    generations: RefCell<Vec<u32>>,
+    // Events of sources that reported leftover work, to be processed again on the next dispatch.
+    pending_events: RefCell<Vec<PollEvent>>,
+}
+
+impl<'l, Data> LoopInner<'l, Data> {
+    fn forget_pending_events(&self, token: &RegistrationToken) {
+        self.pending_events
+            .borrow_mut()
+            .retain(|event| event.token.key & MAX_SOURCES_MASK != token.id());
+    }
+
+    // Insert a source, giving it the next generation of its key.
+    pub(crate) fn insert_entry(
+        &self,
+        sources: &mut Slab<SourceEntry<'l, Data>>,
+        dispatcher: Rc<dyn EventDispatcher<Data> + 'l>,
+        priority: i32,
+    ) -> RegistrationToken {
+        let mut generations = self.generations.borrow_mut();
+        let key = sources.vacant_key();
+        let generation = match generations.get_mut(key) {
+            Some(generation) => {
+                *generation = generation.wrapping_add(1);
+                *generation
+            }
+            None => {
+                generations.push(0);
+                0
+            }
+        };
+        sources.insert(SourceEntry {
+            dispatcher,
+            priority,
+            name: None,
+            enabled: Cell::new(true),
+            group: None,
+            generation,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+        });
+        RegistrationToken { key, generation }
+    }
+
+    // The token of the source of this ID, unless it was removed.
+    pub(crate) fn token_of_id(&self, id: usize) -> Option<RegistrationToken> {
+        let key = id & SOURCE_KEY_MASK;
+        let source = self.sources.borrow();
+        let source = source.get(key)?;
+        let token = RegistrationToken {
+            key,
+            generation: source.generation,
+        };
+        (token.id() == id & MAX_SOURCES_MASK).then_some(token)
+    }
+
+    // Whether the source of this token is still in the loop.
+    fn contains(&self, token: &RegistrationToken) -> bool {
+        self.source(token).is_ok()
+    }
+
+    // The source of this token, unless it was removed.
+    fn source(&self, token: &RegistrationToken) -> crate::Result<Ref<'_, SourceEntry<'l, Data>>> {
+        Ref::filter_map(self.sources.borrow(), |sources| {
+            sources
+                .get(token.key)
+                .filter(|source| source.generation == token.generation)
+        })
+        .map_err(|_| crate::Error::InvalidToken)
+    }
+
+    fn source_mut(
+        &self,
+        token: &RegistrationToken,
+    ) -> crate::Result<RefMut<'_, SourceEntry<'l, Data>>> {
+        RefMut::filter_map(self.sources.borrow_mut(), |sources| {
+            sources
+                .get_mut(token.key)
+                .filter(|source| source.generation == token.generation)
+        })
+        .map_err(|_| crate::Error::InvalidToken)
+    }
+
+    // Remove the source of this token from the list of sources, unless it was already removed.
+    pub(crate) fn take_source(&self, token: &RegistrationToken) -> Option<SourceEntry<'l, Data>> {
+        if !self.contains(token) {
+            return None;
+        }
+        self.forget_pending_events(token);
+        Some(self.sources.borrow_mut().remove(token.key))
+    }
 }
 
 /// An handle to an event loop
@@ -93,17 +466,71 @@ impl<'l, Data> LoopHandle<'l, Data> {
     ///
     /// This function takes ownership of the event source. Use `register_dispatcher`
     /// if you need access to the event source after this call.
+    ///
+    /// The source is inserted with a priority of `0`, see `insert_source_with_priority`
+    /// to change it.
     pub fn insert_source<S, F>(
         &self,
         source: S,
         callback: F,
     ) -> Result<RegistrationToken, InsertError<S>>
+    where
+        S: EventSource + 'l,
+        F: FnMut(S::Event, &mut S::Metadata, &mut Data) -> S::Ret + 'l,
+    {
+        self.insert_source_with_priority(source, DEFAULT_PRIORITY, callback)
+    }
+
+    /// Inserts a new event source in the loop, and returns a typed handle to it
+    ///
+    /// The [`SourceHandle`] gives access to the source after its insertion, without keeping a
+    /// [`Dispatcher`] and a token around. See `source_handle` to get a handle to a source
+    /// registered through a `Dispatcher`, for example with a priority.
+    ///
+    /// See `insert_source` for details.
+    pub fn insert_source_with_handle<S, F>(
+        &self,
+        source: S,
+        callback: F,
+    ) -> Result<SourceHandle<'l, S, Data>, InsertError<S>>
+    where
+        S: EventSource + 'l,
+        F: FnMut(S::Event, &mut S::Metadata, &mut Data) -> S::Ret + 'l,
+    {
+        let dispatcher = Dispatcher::new(source, callback);
+        match self.register_dispatcher(dispatcher.clone()) {
+            Ok(token) => Ok(SourceHandle {
+                handle: self.clone(),
+                dispatcher,
+                token,
+            }),
+            Err(error) => Err(InsertError {
+                error,
+                inserted: dispatcher.into_source_inner(),
+            }),
+        }
+    }
+
+    /// Inserts a new event source in the loop with a given priority.
+    ///
+    /// Within a single dispatching cycle, the events of sources with a higher
+    /// priority are always processed before the events of sources with a lower
+    /// priority. Sources with the same priority are processed in the order the
+    /// polling system reported their events.
+    ///
+    /// See `insert_source` for details.
+    pub fn insert_source_with_priority<S, F>(
+        &self,
+        source: S,
+        priority: i32,
+        callback: F,
+    ) -> Result<RegistrationToken, InsertError<S>>
     where
         S: EventSource + 'l,
         F: FnMut(S::Event, &mut S::Metadata, &mut Data) -> S::Ret + 'l,
     {
         let dispatcher = Dispatcher::new(source, callback);
-        self.register_dispatcher(dispatcher.clone())
+        self.register_dispatcher_with_priority(dispatcher.clone(), priority)
             .map_err(|error| InsertError {
                 error,
                 inserted: dispatcher.into_source_inner(),
@@ -114,12 +541,53 @@ impl<'l, Data> LoopHandle<'l, Data> {
     ///
     /// Use this function if you need access to the event source after its insertion in the loop.
     ///
+    /// The dispatcher is registered with a priority of `0`.
+    ///
     /// See also `insert_source`.
-    #[cfg_attr(feature = "nightly_coverage", no_coverage)] // Contains a branch we can't hit w/o OOM
     pub fn register_dispatcher<S>(
         &self,
         dispatcher: Dispatcher<'l, S, Data>,
     ) -> crate::Result<RegistrationToken>
+    where
+        S: EventSource + 'l,
+    {
+        self.register_dispatcher_with_priority(dispatcher, DEFAULT_PRIORITY)
+    }
+
+    /// Wraps a registered `Dispatcher` into a typed handle to its source
+    ///
+    /// The [`SourceHandle`] gives access to the source, and then updates its registration.
+    ///
+    /// Fails with [`Error::InvalidToken`](crate::Error::InvalidToken) if `token` is not the token
+    /// the dispatcher was registered with.
+    pub fn source_handle<S>(
+        &self,
+        token: RegistrationToken,
+        dispatcher: Dispatcher<'l, S, Data>,
+    ) -> crate::Result<SourceHandle<'l, S, Data>>
+    where
+        S: EventSource + 'l,
+    {
+        if !dispatcher.is(&self.inner.source(&token)?.dispatcher) {
+            return Err(crate::Error::InvalidToken);
+        }
+        Ok(SourceHandle {
+            handle: self.clone(),
+            dispatcher,
+            token,
+        })
+    }
+
+    /// Registers a `Dispatcher` in the loop with a given priority.
+    ///
+    /// See `insert_source_with_priority` for how priorities affect the
+    /// dispatching order.
+    #[cfg_attr(feature = "nightly_coverage", no_coverage)] // Contains a branch we can't hit w/o OOM
+    pub fn register_dispatcher_with_priority<S>(
+        &self,
+        dispatcher: Dispatcher<'l, S, Data>,
+        priority: i32,
+    ) -> crate::Result<RegistrationToken>
     where
         S: EventSource + 'l,
     {
@@ -127,25 +595,34 @@ impl<'l, Data> LoopHandle<'l, Data> {
         let mut poll = self.inner.poll.borrow_mut();
 
         // Make sure we won't overflow the token.
-        if sources.vacant_key() >= MAX_SOURCES_TOTAL {
+        if sources.vacant_key() > SOURCE_KEY_MASK {
             return Err(crate::Error::IoError(std::io::Error::new(
                 std::io::ErrorKind::Other,
                 "Too many sources",
             )));
         }
 
-        let key = sources.insert(dispatcher.clone_as_event_dispatcher());
-        let ret = sources
-            .get(key)
-            .unwrap()
-            .register(&mut poll, &mut TokenFactory::new(key));
+        let token = self.inner.insert_entry(
+            &mut sources,
+            dispatcher.clone_as_event_dispatcher(),
+            priority,
+        );
+        let source = sources.get(token.key).unwrap();
+        source
+            .dispatcher
+            .set_event_budget(self.inner.event_budget.get());
+        let ret = source
+            .dispatcher
+            .register(&mut poll, &mut TokenFactory::new(token.id()));
 
         if let Err(error) = ret {
-            sources.try_remove(key).expect("Source was just inserted?!");
+            sources
+                .try_remove(token.key)
+                .expect("Source was just inserted?!");
             return Err(error);
         }
 
-        Ok(RegistrationToken { key })
+        Ok(token)
     }
 
     /// Inserts an idle callback.
@@ -169,12 +646,12 @@ impl<'l, Data> LoopHandle<'l, Data> {
     ///
     /// **Note:** this cannot be done from within the source callback.
     pub fn enable(&self, token: &RegistrationToken) -> crate::Result<()> {
-        if let Some(source) = self.inner.sources.borrow().get(token.key) {
-            source.register(
-                &mut self.inner.poll.borrow_mut(),
-                &mut TokenFactory::new(token.key),
-            )?;
-        }
+        let source = self.inner.source(token)?;
+        source.dispatcher.register(
+            &mut self.inner.poll.borrow_mut(),
+            &mut TokenFactory::new(token.id()),
+        )?;
+        source.enabled.set(true);
         Ok(())
     }
 
@@ -183,14 +660,13 @@ impl<'l, Data> LoopHandle<'l, Data> {
     /// If after accessing the source you changed its parameters in a way that requires
     /// updating its registration.
     pub fn update(&self, token: &RegistrationToken) -> crate::Result<()> {
-        if let Some(source) = self.inner.sources.borrow().get(token.key) {
-            if !source.reregister(
-                &mut self.inner.poll.borrow_mut(),
-                &mut TokenFactory::new(token.key),
-            )? {
-                // we are in a callback, store for later processing
-                self.inner.pending_action.set(PostAction::Reregister);
-            }
+        let source = self.inner.source(token)?;
+        if !source.dispatcher.reregister(
+            &mut self.inner.poll.borrow_mut(),
+            &mut TokenFactory::new(token.id()),
+        )? {
+            // we are in a callback, store for later processing
+            self.inner.pending_action.set(PostAction::Reregister);
         }
         Ok(())
     }
@@ -199,19 +675,70 @@ impl<'l, Data> LoopHandle<'l, Data> {
     ///
     /// The source remains in the event loop, but it'll no longer generate events
     pub fn disable(&self, token: &RegistrationToken) -> crate::Result<()> {
-        if let Some(source) = self.inner.sources.borrow().get(token.key) {
-            if !source.unregister(&mut self.inner.poll.borrow_mut())? {
-                // we are in a callback, store for later processing
-                self.inner.pending_action.set(PostAction::Disable);
-            }
+        let source = self.inner.source(token)?;
+        if !source
+            .dispatcher
+            .unregister(&mut self.inner.poll.borrow_mut())?
+        {
+            // we are in a callback, store for later processing
+            self.inner.pending_action.set(PostAction::Disable);
         }
+        source.enabled.set(false);
+        self.inner.forget_pending_events(token);
+        Ok(())
+    }
+
+    /// Gives a name to this source.
+    ///
+    /// The name identifies the source in the errors it generates, see
+    /// [`SourceError`](crate::SourceError), and in the list given by [`sources()`](Self::sources).
+    pub fn set_name(
+        &self,
+        token: &RegistrationToken,
+        name: impl Into<String>,
+    ) -> crate::Result<()> {
+        self.inner.source_mut(token)?.name = Some(name.into());
         Ok(())
     }
 
+    /// Lists the sources inserted in the event loop
+    ///
+    /// This is meant for diagnostics, like dumping the state of the loop or checking that no
+    /// source was leaked.
+    pub fn sources(&self) -> Vec<SourceInfo> {
+        let poll = self.inner.poll.borrow();
+        let timers = poll.timers.borrow();
+        self.inner
+            .sources
+            .borrow()
+            .iter()
+            .map(|(key, source)| {
+                let token = RegistrationToken {
+                    key,
+                    generation: source.generation,
+                };
+                SourceInfo {
+                    token,
+                    name: source.name.clone(),
+                    type_name: source.dispatcher.type_name(),
+                    enabled: source.enabled.get(),
+                    group: source.group,
+                    fds: poll.registered_fds(token.id()),
+                    timers: timers.count_for(token.id()),
+                }
+            })
+            .collect()
+    }
+
     /// Removes this source from the event loop.
+    ///
+    /// Nothing happens if the source was already removed.
     pub fn remove(&self, token: RegistrationToken) {
-        if let Some(source) = self.inner.sources.borrow_mut().try_remove(token.key) {
-            if let Err(e) = source.unregister(&mut self.inner.poll.borrow_mut()) {
+        if let Some(source) = self.inner.take_source(&token) {
+            if let Err(e) = source
+                .dispatcher
+                .unregister(&mut self.inner.poll.borrow_mut())
+            {
                 log::warn!(
                     "[calloop] Failed to unregister source from the polling system: {:?}",
                     e
@@ -220,6 +747,93 @@ impl<'l, Data> LoopHandle<'l, Data> {
         }
     }
 
+    /// Creates a new, empty, group of sources
+    pub fn create_group(&self) -> GroupToken {
+        let id = self.inner.next_group.get();
+        self.inner.next_group.set(id + 1);
+        GroupToken { id }
+    }
+
+    /// Wraps a group into a guard, which removes its sources from the loop when dropped
+    pub fn guard_group(&self, group: GroupToken) -> GroupGuard<'l, Data> {
+        GroupGuard {
+            handle: self.clone(),
+            group: Some(group),
+        }
+    }
+
+    /// Wraps a source into a guard, which removes it from the loop when dropped
+    pub fn guard_source(&self, token: RegistrationToken) -> SourceGuard<'l, Data> {
+        SourceGuard {
+            handle: self.clone(),
+            token: Some(token),
+        }
+    }
+
+    /// Adds a source to a group
+    ///
+    /// A source belongs to at most one group: if it already was in another group, it is moved
+    /// to this one.
+    pub fn add_to_group(&self, group: &GroupToken, token: &RegistrationToken) -> crate::Result<()> {
+        self.inner.source_mut(token)?.group = Some(*group);
+        Ok(())
+    }
+
+    /// The sources currently in a group
+    pub fn group_members(&self, group: &GroupToken) -> Vec<RegistrationToken> {
+        self.inner
+            .sources
+            .borrow()
+            .iter()
+            .filter(|(_, source)| source.group == Some(*group))
+            .map(|(key, source)| RegistrationToken {
+                key,
+                generation: source.generation,
+            })
+            .collect()
+    }
+
+    /// Enables all the sources of a group
+    ///
+    /// Every source is enabled even if some of them fail to, in which case the first error is
+    /// returned. See `enable` for details.
+    pub fn enable_group(&self, group: &GroupToken) -> crate::Result<()> {
+        self.group_members(group)
+            .iter()
+            .map(|token| self.enable(token))
+            .fold(Ok(()), Result::and)
+    }
+
+    /// Disables all the sources of a group
+    ///
+    /// Every source is disabled even if some of them fail to, in which case the first error is
+    /// returned. See `disable` for details.
+    pub fn disable_group(&self, group: &GroupToken) -> crate::Result<()> {
+        self.group_members(group)
+            .iter()
+            .map(|token| self.disable(token))
+            .fold(Ok(()), Result::and)
+    }
+
+    /// Removes all the sources of a group from the event loop
+    pub fn remove_group(&self, group: GroupToken) {
+        for token in self.group_members(&group) {
+            self.remove(token);
+        }
+    }
+
+    /// The current time of the clock of the event loop
+    ///
+    /// This is the time the deadlines of timers are measured against, see
+    /// [`EventLoop::with_clock()`].
+    pub fn now(&self) -> Instant {
+        self.inner.poll.borrow().clock.now()
+    }
+
+    pub(crate) fn clock(&self) -> Rc<dyn LoopClock> {
+        self.inner.poll.borrow().clock.clone()
+    }
+
     /// Wrap an IO object into an async adapter
     ///
     /// This adapter turns the IO object into an async-aware one that can be used in futures.
@@ -238,6 +852,11 @@ impl<'l, Data> LoopHandle<'l, Data> {
 pub struct EventLoop<'l, Data> {
     handle: LoopHandle<'l, Data>,
     signals: Arc<Signals>,
+    /// Deadline of the graceful shutdown in progress, on the clock of the loop.
+    shutdown_deadline: Option<Instant>,
+    panic_handler: Option<PanicHandler<'l>>,
+    #[cfg(feature = "metrics")]
+    metrics: crate::metrics::LoopCounters,
 }
 
 impl<'l, Data> std::fmt::Debug for EventLoop<'l, Data> {
@@ -252,6 +871,9 @@ struct Signals {
     /// Signal to stop the event loop.
     stop: AtomicBool,
 
+    /// Timeout of the graceful shutdown of the event loop, if it was requested.
+    shutdown: Mutex<Option<Duration>>,
+
     /// Signal that the future is ready.
     #[cfg(feature = "block_on")]
     future_ready: AtomicBool,
@@ -262,13 +884,30 @@ impl<'l, Data> EventLoop<'l, Data> {
     ///
     /// Fails if the initialization of the polling system failed.
     pub fn try_new() -> crate::Result<Self> {
-        let poll = Poll::new()?;
+        Self::with_clock(SystemClock)
+    }
+
+    /// Create a new event loop whose timers follow the given clock
+    ///
+    /// The deadlines of the [`Timer`](crate::timer::Timer)s and
+    /// [`TimeoutFuture`](crate::timer::TimeoutFuture)s of this loop are measured against `clock`
+    /// instead of the system monotonic clock. See the [`clock`](crate::clock) module for details.
+    ///
+    /// Fails if the initialization of the polling system failed.
+    pub fn with_clock(clock: impl LoopClock + 'static) -> crate::Result<Self> {
+        let mut poll = Poll::new()?;
+        poll.clock = Rc::new(clock);
         let handle = LoopHandle {
             inner: Rc::new(LoopInner {
                 poll: RefCell::new(poll),
                 sources: RefCell::new(Slab::new()),
                 idles: RefCell::new(Vec::new()),
                 pending_action: Cell::new(PostAction::Continue),
+                event_budget: Cell::new(None),
+                error_policy: Cell::new(ErrorPolicy::Propagate),
+                next_group: Cell::new(0),
+                generations: RefCell::new(Vec::new()),
+                pending_events: RefCell::new(Vec::new()),
             }),
         };
 
@@ -276,9 +915,14 @@ impl<'l, Data> EventLoop<'l, Data> {
             handle,
             signals: Arc::new(Signals {
                 stop: AtomicBool::new(false),
+                shutdown: Mutex::new(None),
                 #[cfg(feature = "block_on")]
                 future_ready: AtomicBool::new(false),
             }),
+            shutdown_deadline: None,
+            panic_handler: None,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
         })
     }
 
@@ -287,25 +931,175 @@ impl<'l, Data> EventLoop<'l, Data> {
         self.handle.clone()
     }
 
-    fn dispatch_events(
-        &mut self,
-        mut timeout: Option<Duration>,
-        data: &mut Data,
-    ) -> crate::Result<()> {
-        let now = Instant::now();
-        let events = {
-            let poll = self.handle.inner.poll.borrow();
-            loop {
-                let result = poll.poll(timeout);
+    /// Set the maximum number of events each source may generate per dispatch
+    ///
+    /// Some sources, like [`Channel`](crate::channel::Channel), process every event they have
+    /// available each time they are woken up. With a lot of incoming traffic, this can starve
+    /// the other sources of the loop. With a budget set, such sources stop after generating
+    /// `budget` events, and the rest of their events are carried over to the next dispatching
+    /// cycle, which will then not wait for new events.
+    ///
+    /// The budget applies to every source inserted in this loop, including the ones inserted
+    /// later. `None`, the default, means there is no limit. See
+    /// [`EventSource::set_event_budget`] for how sources implement it.
+    pub fn set_event_budget(&mut self, budget: Option<NonZeroUsize>) {
+        self.handle.inner.event_budget.set(budget);
+        for (_, source) in self.handle.inner.sources.borrow().iter() {
+            source.dispatcher.set_event_budget(budget);
+        }
+    }
 
-                match result {
-                    Ok(events) => break events,
-                    Err(crate::Error::IoError(err)) if err.kind() == io::ErrorKind::Interrupted => {
-                        // Interrupted by a signal. Update timeout and retry.
-                        if let Some(to) = timeout {
-                            let elapsed = now.elapsed();
-                            if elapsed >= to {
-                                return Ok(());
+    /// The current per-source event budget, see [`set_event_budget`](Self::set_event_budget)
+    pub fn event_budget(&self) -> Option<NonZeroUsize> {
+        self.handle.inner.event_budget.get()
+    }
+
+    /// Set what the loop does when an event source fails to process its events
+    ///
+    /// The errors of the sources are reported as a [`SourceError`](crate::SourceError), which
+    /// identifies the failing source. The default is [`ErrorPolicy::Propagate`].
+    pub fn set_error_policy(&mut self, policy: ErrorPolicy) {
+        self.handle.inner.error_policy.set(policy);
+    }
+
+    /// The current error policy, see [`set_error_policy`](Self::set_error_policy)
+    pub fn error_policy(&self) -> ErrorPolicy {
+        self.handle.inner.error_policy.get()
+    }
+
+    /// Take a snapshot of the metrics of the loop and its sources
+    ///
+    /// The metrics are counted from the creation of the loop, or the last call to
+    /// [`reset_metrics()`](Self::reset_metrics). See the [`metrics`](crate::metrics) module.
+    #[cfg(feature = "metrics")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+    pub fn metrics(&self) -> crate::metrics::LoopMetrics {
+        let sources = self
+            .handle
+            .inner
+            .sources
+            .borrow()
+            .iter()
+            .map(|(key, source)| {
+                source.metrics.borrow().snapshot(
+                    RegistrationToken {
+                        key,
+                        generation: source.generation,
+                    },
+                    source.name.clone(),
+                    source.dispatcher.type_name(),
+                )
+            })
+            .collect();
+        crate::metrics::LoopMetrics {
+            dispatches: self.metrics.dispatches,
+            poll_wait: self.metrics.poll_wait,
+            busy: self.metrics.busy,
+            sources,
+        }
+    }
+
+    /// Reset the metrics of the loop and its sources
+    #[cfg(feature = "metrics")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "metrics")))]
+    pub fn reset_metrics(&mut self) {
+        self.metrics = Default::default();
+        for (_, source) in self.handle.inner.sources.borrow().iter() {
+            *source.metrics.borrow_mut() = Default::default();
+        }
+    }
+
+    /// Catch the panics of the sources while they process their events
+    ///
+    /// By default, a panic in the callback of a source unwinds through the dispatching methods
+    /// of the loop. Once this is called, the panic is instead caught, the source is removed or
+    /// disabled depending on `action`, and the panic is given to `handler`. The events of the
+    /// other sources are dispatched as usual.
+    ///
+    /// The panic hook still runs when a panic is caught, so it is still printed by default. This
+    /// has no effect if panics abort the process.
+    pub fn catch_panics<F>(&mut self, action: PanicAction, handler: F)
+    where
+        F: FnMut(SourcePanic) + 'l,
+    {
+        self.panic_handler = Some(PanicHandler {
+            action,
+            callback: Box::new(handler),
+        });
+    }
+
+    /// The maximum time to wait before this loop needs to be dispatched again
+    ///
+    /// This is meant for integrating this loop into another one through its file descriptor (see
+    /// its `AsFd` implementation): timers do not make this file descriptor readable, so the other
+    /// loop should not wait longer than this before calling
+    /// [`dispatch_pending()`](Self::dispatch_pending).
+    ///
+    /// Returns `Some(Duration::ZERO)` if this loop has pending work, like idle callbacks, the
+    /// time until the next timer expires or the graceful shutdown in progress times out if there
+    /// is one, and `None` otherwise. With a [`ManualClock`](crate::clock::ManualClock), timers
+    /// only count once they are due.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        let inner = &self.handle.inner;
+        if !inner.idles.borrow().is_empty() || !inner.pending_events.borrow().is_empty() {
+            return Some(Duration::ZERO);
+        }
+
+        let poll = inner.poll.borrow();
+        let until_timer = poll.timers.borrow().next_deadline().and_then(|deadline| {
+            if deadline <= poll.clock.now() {
+                Some(Duration::ZERO)
+            } else {
+                poll.clock.duration_until(deadline)
+            }
+        });
+        drop(poll);
+        match (until_timer, self.until_shutdown_deadline()) {
+            (Some(until_timer), Some(until_shutdown)) => Some(until_timer.min(until_shutdown)),
+            (until_timer, until_shutdown) => until_timer.or(until_shutdown),
+        }
+    }
+
+    /// The time left until the deadline of the graceful shutdown in progress, if there is one
+    ///
+    /// When it cannot be measured, like with a [`ManualClock`](crate::clock::ManualClock), the
+    /// loop must not wait for I/O that may never come: this is then `Duration::ZERO`.
+    fn until_shutdown_deadline(&self) -> Option<Duration> {
+        let deadline = self.shutdown_deadline?;
+        let clock = &self.handle.inner.poll.borrow().clock;
+        if clock.now() >= deadline {
+            return Some(Duration::ZERO);
+        }
+        Some(clock.duration_until(deadline).unwrap_or(Duration::ZERO))
+    }
+
+    fn dispatch_events(
+        &mut self,
+        mut timeout: Option<Duration>,
+        data: &mut Data,
+    ) -> crate::Result<()> {
+        let pending_events = std::mem::take(&mut *self.handle.inner.pending_events.borrow_mut());
+        if !pending_events.is_empty() {
+            // Some sources have leftover work, don't wait for new events.
+            timeout = Some(Duration::ZERO);
+        }
+
+        let now = Instant::now();
+        let mut events = {
+            #[cfg(feature = "tracing")]
+            let _span = tracing::trace_span!("poll", ?timeout).entered();
+            let poll = self.handle.inner.poll.borrow();
+            loop {
+                let result = poll.poll(timeout);
+
+                match result {
+                    Ok(events) => break events,
+                    Err(crate::Error::IoError(err)) if err.kind() == io::ErrorKind::Interrupted => {
+                        // Interrupted by a signal. Update timeout and retry.
+                        if let Some(to) = timeout {
+                            let elapsed = now.elapsed();
+                            if elapsed >= to {
+                                return Ok(());
                             } else {
                                 timeout = Some(to - elapsed);
                             }
@@ -315,21 +1109,73 @@ impl<'l, Data> EventLoop<'l, Data> {
                 };
             }
         };
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.poll_wait += now.elapsed();
+        }
 
-        for event in events {
-            // Get the registration token associated with the event.
-            let registroken_token = event.token.key & MAX_SOURCES_MASK;
+        // Sources that received new events will process their leftovers along with them.
+        for pending in pending_events {
+            if !events.iter().any(|event| event.token == pending.token) {
+                events.push(pending);
+            }
+        }
 
-            let opt_disp = self
-                .handle
-                .inner
-                .sources
-                .borrow()
-                .get(registroken_token)
-                .cloned();
+        if events.len() > 1 {
+            // Process the events of higher priority sources first. The sort is stable, so
+            // sources of equal priority keep the order reported by the polling system.
+            let sources = self.handle.inner.sources.borrow();
+            events.sort_by_cached_key(|event| {
+                let priority = sources
+                    .get(event.token.key & SOURCE_KEY_MASK)
+                    .map_or(DEFAULT_PRIORITY, |source| source.priority);
+                std::cmp::Reverse(priority)
+            });
+        }
 
-            if let Some(disp) = opt_disp {
-                let mut ret = disp.process_events(event.readiness, event.token, data)?;
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("events", count = events.len()).entered();
+        let mut first_error = None;
+        for event in events {
+            // Get the registration token associated with the event. The event is dropped if its
+            // source was removed, even if another source took its key.
+            let registroken_token = self.handle.inner.token_of_id(event.token.key);
+            let opt_disp = registroken_token.and_then(|token| {
+                let source = self.handle.inner.source(&token).ok()?;
+                Some((token, source.dispatcher.clone()))
+            });
+
+            if let Some((registroken_token, disp)) = opt_disp {
+                #[cfg(feature = "tracing")]
+                let _span = self.process_events_span(&registroken_token).entered();
+                #[cfg(feature = "metrics")]
+                let mut counters = crate::metrics::SourceCounters::default();
+                let mut process_events = || {
+                    disp.process_events(
+                        event.readiness,
+                        event.token,
+                        data,
+                        #[cfg(feature = "metrics")]
+                        &mut counters,
+                    )
+                };
+                let result = if self.panic_handler.is_none() {
+                    Ok(process_events())
+                } else {
+                    panic::catch_unwind(AssertUnwindSafe(process_events))
+                };
+                #[cfg(feature = "metrics")]
+                if let Ok(source) = self.handle.inner.source(&registroken_token) {
+                    source.metrics.borrow_mut().record_wakeup(&counters);
+                }
+                let mut ret = match result {
+                    Ok(Ok(ret)) => ret,
+                    Err(payload) => self.source_panicked(registroken_token, payload),
+                    Ok(Err(error)) => {
+                        let error = self.source_error(registroken_token, error);
+                        self.apply_error_policy(error, &mut first_error)
+                    }
+                };
 
                 // if the returned PostAction is Continue, it may be overwritten by an user-specified pending action
                 let pending_action = self
@@ -341,34 +1187,37 @@ impl<'l, Data> EventLoop<'l, Data> {
                     ret = pending_action;
                 }
 
-                match ret {
-                    PostAction::Reregister => {
-                        disp.reregister(
+                let result = match ret {
+                    PostAction::Reregister => disp
+                        .reregister(
                             &mut self.handle.inner.poll.borrow_mut(),
-                            &mut TokenFactory::new(event.token.key),
-                        )?;
-                    }
-                    PostAction::Disable => {
-                        disp.unregister(&mut self.handle.inner.poll.borrow_mut())?;
-                    }
+                            &mut TokenFactory::new(registroken_token.id()),
+                        )
+                        .map(drop),
+                    PostAction::Disable => disp
+                        .unregister(&mut self.handle.inner.poll.borrow_mut())
+                        .map(|_| {
+                            if let Ok(source) = self.handle.inner.source(&registroken_token) {
+                                source.enabled.set(false);
+                            }
+                        }),
                     PostAction::Remove => {
                         // delete the source from the list, it'll be cleaned up with the if just below
-                        self.handle
-                            .inner
-                            .sources
-                            .borrow_mut()
-                            .remove(event.token.key);
+                        self.handle.inner.take_source(&registroken_token);
+                        Ok(())
+                    }
+                    PostAction::Continue => Ok(()),
+                };
+                if let Err(error) = result {
+                    // The source failed to update its registration, handle it like any other
+                    // error of the source so that the remaining events are still processed.
+                    let error = self.source_error(registroken_token, error);
+                    if self.apply_error_policy(error, &mut first_error) == PostAction::Remove {
+                        self.handle.inner.take_source(&registroken_token);
                     }
-                    PostAction::Continue => {}
                 }
 
-                if !self
-                    .handle
-                    .inner
-                    .sources
-                    .borrow()
-                    .contains(registroken_token)
-                {
+                if !self.handle.inner.contains(&registroken_token) {
                     // the source has been removed from within its callback, unregister it
                     let mut poll = self.handle.inner.poll.borrow_mut();
                     if let Err(e) = disp.unregister(&mut poll) {
@@ -377,33 +1226,145 @@ impl<'l, Data> EventLoop<'l, Data> {
                             e
                         );
                     }
+                } else if ret != PostAction::Disable && disp.has_pending_events() {
+                    // the source stopped early, process it again on the next dispatch
+                    self.handle
+                        .inner
+                        .pending_events
+                        .borrow_mut()
+                        .push(PollEvent {
+                            readiness: Readiness::EMPTY,
+                            token: event.token,
+                        });
                 }
             } else {
                 log::warn!(
                     "[calloop] Received an event for non-existence source: {:?}",
-                    event.token.key
+                    event.token.key & MAX_SOURCES_MASK
                 );
             }
         }
 
-        Ok(())
+        // The events have been processed, the polling system can watch their sources again.
+        self.handle.inner.poll.borrow().flush()?;
+
+        match first_error {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    fn process_events_span(&self, token: &RegistrationToken) -> tracing::Span {
+        let span = tracing::trace_span!(
+            "process_events",
+            token = token.key,
+            name = tracing::field::Empty
+        );
+        if !span.is_disabled() {
+            if let Some(name) = self.source_name(token) {
+                span.record("name", name.as_str());
+            }
+        }
+        span
+    }
+
+    fn source_name(&self, token: &RegistrationToken) -> Option<String> {
+        self.handle.inner.source(token).ok()?.name.clone()
+    }
+
+    fn source_error(&self, token: RegistrationToken, error: crate::Error) -> SourceError {
+        SourceError {
+            token,
+            name: self.source_name(&token),
+            error: match error {
+                crate::Error::OtherError(error) => error,
+                error => Box::new(error),
+            },
+        }
+    }
+
+    /// Handle an error of a source according to the error policy, returning what to do with it.
+    fn apply_error_policy(
+        &self,
+        error: SourceError,
+        first_error: &mut Option<SourceError>,
+    ) -> PostAction {
+        match self.handle.inner.error_policy.get() {
+            ErrorPolicy::Propagate if first_error.is_none() => {
+                *first_error = Some(error);
+                PostAction::Continue
+            }
+            ErrorPolicy::Propagate | ErrorPolicy::LogAndContinue => {
+                log::warn!("[calloop] {}: {}", error, error.error);
+                PostAction::Continue
+            }
+            ErrorPolicy::RemoveSource => {
+                log::warn!("[calloop] {}, removing it: {}", error, error.error);
+                PostAction::Remove
+            }
+        }
+    }
+
+    fn source_panicked(
+        &mut self,
+        token: RegistrationToken,
+        payload: Box<dyn Any + Send>,
+    ) -> PostAction {
+        let panic = SourcePanic {
+            token,
+            name: self.source_name(&token),
+            payload,
+        };
+        let handler = self
+            .panic_handler
+            .as_mut()
+            .expect("panics are only caught with a handler");
+        (handler.callback)(panic);
+        match handler.action {
+            PanicAction::Remove => PostAction::Remove,
+            PanicAction::Disable => PostAction::Disable,
+        }
     }
 
     fn dispatch_idles(&mut self, data: &mut Data) {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("idles").entered();
         let idles = std::mem::take(&mut *self.handle.inner.idles.borrow_mut());
         for idle in idles {
             idle.borrow_mut().dispatch(data);
         }
     }
 
+    // Notify the sources of the shutdown, returns whether all of them are done.
+    fn invoke_on_shutdown(&self) -> bool {
+        let sources = self
+            .handle
+            .inner
+            .sources
+            .borrow()
+            .iter()
+            .map(|(_, source)| source.dispatcher.clone())
+            .collect::<Vec<_>>();
+
+        // every source is notified, even once one of them is known not to be done
+        let mut done = true;
+        for source in sources {
+            done &= source.on_shutdown();
+        }
+        done
+    }
+
     fn invoke_pre_run(&self, data: &mut Data) -> crate::Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("pre_run").entered();
         let sources = self
             .handle
             .inner
             .sources
             .borrow()
             .iter()
-            .map(|(_, source)| source.clone())
+            .map(|(_, source)| source.dispatcher.clone())
             .collect::<Vec<_>>();
 
         for source in sources {
@@ -414,13 +1375,15 @@ impl<'l, Data> EventLoop<'l, Data> {
     }
 
     fn invoke_post_run(&self, data: &mut Data) -> crate::Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!("post_run").entered();
         let sources = self
             .handle
             .inner
             .sources
             .borrow()
             .iter()
-            .map(|(_, source)| source.clone())
+            .map(|(_, source)| source.dispatcher.clone())
             .collect::<Vec<_>>();
 
         for source in sources {
@@ -438,19 +1401,41 @@ impl<'l, Data> EventLoop<'l, Data> {
     ///
     /// Once pending events have been processed or the timeout is reached, all pending
     /// idle callbacks will be fired before this method returns.
+    ///
+    /// If an event source fails to process its events, what happens depends on the
+    /// [error policy](Self::set_error_policy) of the loop. By default, the other sources are
+    /// still dispatched and the error is returned as a [`SourceError`](crate::SourceError)
+    /// afterwards.
     pub fn dispatch<D: Into<Option<Duration>>>(
         &mut self,
         timeout: D,
         data: &mut Data,
     ) -> crate::Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("dispatch").entered();
+        #[cfg(feature = "metrics")]
+        let (start, poll_wait) = (Instant::now(), self.metrics.poll_wait);
+
         self.invoke_pre_run(data)?;
         self.dispatch_events(timeout.into(), data)?;
         self.dispatch_idles(data);
         self.invoke_post_run(data)?;
 
+        #[cfg(feature = "metrics")]
+        self.metrics.record_dispatch(start.elapsed(), poll_wait);
+
         Ok(())
     }
 
+    /// Dispatch the events that are ready now, without waiting
+    ///
+    /// This is equivalent to calling [`dispatch()`](Self::dispatch) with a zero timeout, and is
+    /// meant to be called when the file descriptor of this loop becomes readable, when it is
+    /// integrated into another loop.
+    pub fn dispatch_pending(&mut self, data: &mut Data) -> crate::Result<()> {
+        self.dispatch(Duration::ZERO, data)
+    }
+
     /// Get a signal to stop this event loop from running
     ///
     /// To be used in conjunction with the `run()` method.
@@ -469,7 +1454,7 @@ impl<'l, Data> EventLoop<'l, Data> {
     /// Between each dispatch wait, your provided callback will be called.
     ///
     /// You can use the `get_signal()` method to retrieve a way to stop or wakeup
-    /// the event loop from anywhere.
+    /// the event loop from anywhere, or to [shut it down](LoopSignal::shutdown) gracefully.
     pub fn run<F, D: Into<Option<Duration>>>(
         &mut self,
         timeout: D,
@@ -483,21 +1468,64 @@ impl<'l, Data> EventLoop<'l, Data> {
         self.signals.stop.store(false, Ordering::Release);
         self.invoke_pre_run(data)?;
         while !self.signals.stop.load(Ordering::Acquire) {
+            let mut timeout = timeout;
+            if self.advance_shutdown(&mut timeout) {
+                break;
+            }
+
+            #[cfg(feature = "tracing")]
+            let _span = tracing::debug_span!("dispatch").entered();
+            #[cfg(feature = "metrics")]
+            let (start, poll_wait) = (Instant::now(), self.metrics.poll_wait);
+
             self.dispatch_events(timeout, data)?;
             self.dispatch_idles(data);
+
+            #[cfg(feature = "metrics")]
+            self.metrics.record_dispatch(start.elapsed(), poll_wait);
+
             cb(data);
         }
+        *self.signals.shutdown.lock().unwrap() = None;
+        self.shutdown_deadline = None;
         self.invoke_post_run(data)?;
         Ok(())
     }
 
+    /// Advance the graceful shutdown of the loop, if it was requested
+    ///
+    /// Returns whether the loop should stop. Otherwise, `timeout` is shortened so that the next
+    /// dispatching cycle ends by the deadline of the shutdown.
+    fn advance_shutdown(&mut self, timeout: &mut Option<Duration>) -> bool {
+        if let Some(requested) = self.signals.shutdown.lock().unwrap().take() {
+            // The deadline is only set once the loop notices the request, so that it is
+            // measured with the clock of the loop.
+            let now = self.handle.inner.poll.borrow().clock.now();
+            self.shutdown_deadline = Some(now + requested);
+        }
+        let deadline = match self.shutdown_deadline {
+            Some(deadline) => deadline,
+            None => return false,
+        };
+        if self.handle.inner.poll.borrow().clock.now() >= deadline {
+            return true;
+        }
+        if self.invoke_on_shutdown() {
+            return true;
+        }
+        if let Some(remaining) = self.until_shutdown_deadline() {
+            *timeout = Some(timeout.map_or(remaining, |timeout| timeout.min(remaining)));
+        }
+        false
+    }
+
     /// Block a future on this event loop.
     ///
     /// This will run the provided future on this event loop, blocking until it is
     /// resolved.
     ///
-    /// If [`LoopSignal::stop()`] is called before the future is resolved, this function returns
-    /// `None`.
+    /// If [`LoopSignal::stop()`] is called before the future is resolved, or if the loop is
+    /// [shut down](LoopSignal::shutdown) before it, this function returns `None`.
     #[cfg(feature = "block_on")]
     pub fn block_on<R>(
         &mut self,
@@ -553,17 +1581,44 @@ impl<'l, Data> EventLoop<'l, Data> {
                 }
             }
 
+            let mut timeout = None;
+            if self.advance_shutdown(&mut timeout) {
+                break;
+            }
+
             // Otherwise, block on the event loop.
-            self.dispatch_events(None, data)?;
+            self.dispatch_events(timeout, data)?;
             self.dispatch_idles(data);
             cb(data);
         }
 
+        *self.signals.shutdown.lock().unwrap() = None;
+        self.shutdown_deadline = None;
         self.invoke_post_run(data)?;
         Ok(output)
     }
 }
 
+/// The file descriptor of the polling system of the loop
+///
+/// It becomes readable when some sources of the loop have events ready, or when the loop is
+/// woken up through a [`LoopSignal`]. This allows driving the loop from another event loop,
+/// by calling [`EventLoop::dispatch_pending()`] when this file descriptor becomes readable.
+///
+/// Timers do not make this file descriptor readable: the other loop also needs to dispatch
+/// this one after at most [`EventLoop::next_timeout()`].
+///
+/// To insert an event loop into another calloop event loop, see
+/// [`NestedLoop`](crate::nested::NestedLoop).
+#[cfg(unix)]
+impl<'l, Data> AsFd for EventLoop<'l, Data> {
+    fn as_fd(&self) -> io_lifetimes::BorrowedFd<'_> {
+        let raw = self.handle.inner.poll.borrow().as_raw_fd();
+        // Safety: the polling system lives as long as the loop, and is never replaced.
+        unsafe { io_lifetimes::BorrowedFd::borrow_raw(raw) }
+    }
+}
+
 /// A signal that can be shared between thread to stop or wakeup a running
 /// event loop
 #[derive(Clone)]
@@ -599,6 +1654,25 @@ impl LoopSignal {
     pub fn wakeup(&self) {
         self.notifier.notify().ok();
     }
+
+    /// Shut the event loop down gracefully
+    ///
+    /// The `EventLoop::run()` method then lets its sources finish their work before returning:
+    /// each of its dispatching cycles starts by notifying the sources with
+    /// [`EventSource::on_shutdown`](crate::EventSource::on_shutdown), and it returns once all of
+    /// them report they are done, or once `timeout` has elapsed. The post-run hooks of the
+    /// sources are invoked as usual before it returns. `EventLoop::block_on()` shuts down the
+    /// same way, and then returns `None` if its future is not resolved yet.
+    ///
+    /// The timeout starts when the loop notices the shutdown, and is measured with the clock of
+    /// the event loop. When this clock cannot tell how long that is, like a
+    /// [`ManualClock`](crate::clock::ManualClock), the loop does not wait for events during the
+    /// shutdown. This also wakes the event loop up. If the loop is not running yet, the shutdown
+    /// starts as soon as it is run.
+    pub fn shutdown(&self, timeout: Duration) {
+        *self.signal.shutdown.lock().unwrap() = Some(timeout);
+        self.wakeup();
+    }
 }
 
 #[cfg(test)]
@@ -610,7 +1684,7 @@ mod tests {
         RegistrationToken, Token, TokenFactory,
     };
 
-    use super::EventLoop;
+    use super::{ErrorPolicy, EventLoop, PanicAction, GENERATION_MASK};
 
     #[test]
     fn dispatch_idle() {
@@ -854,6 +1928,35 @@ mod tests {
         assert_eq!(dispatched, 3);
     }
 
+    #[test]
+    fn dispatch_priority() {
+        let mut event_loop = EventLoop::<Vec<u32>>::try_new().unwrap();
+        let handle = event_loop.handle();
+
+        let (ping_low, source_low) = make_ping().unwrap();
+        let (ping_default, source_default) = make_ping().unwrap();
+        let (ping_high, source_high) = make_ping().unwrap();
+
+        handle
+            .insert_source_with_priority(source_low, -10, |(), &mut (), order| order.push(0))
+            .unwrap();
+        handle
+            .insert_source(source_default, |(), &mut (), order| order.push(1))
+            .unwrap();
+        handle
+            .insert_source_with_priority(source_high, 10, |(), &mut (), order| order.push(2))
+            .unwrap();
+
+        // ping in increasing priority order, the dispatch must reverse it
+        ping_low.ping();
+        ping_default.ping();
+        ping_high.ping();
+
+        let mut order = Vec::new();
+        event_loop.dispatch(Duration::ZERO, &mut order).unwrap();
+        assert_eq!(order, vec![2, 1, 0]);
+    }
+
     #[test]
     fn change_interests() {
         use nix::sys::socket::{recv, socketpair, AddressFamily, MsgFlags, SockFlag, SockType};
@@ -1059,6 +2162,1058 @@ mod tests {
         assert_eq!(data, 22);
     }
 
+    // A source whose callback fails while its socket has data to read
+    fn failing_source(
+        event_loop: &EventLoop<'_, usize>,
+    ) -> (std::os::unix::net::UnixStream, RegistrationToken) {
+        use std::io::Write;
+
+        let (mut sender, receiver) = std::os::unix::net::UnixStream::pair().unwrap();
+        sender.write_all(b"x").unwrap();
+        let token = event_loop
+            .handle()
+            .insert_source(
+                Generic::new(receiver, Interest::READ, Mode::Level),
+                |_, _, count| {
+                    *count += 1;
+                    Err(std::io::Error::new(std::io::ErrorKind::Other, "boom"))
+                },
+            )
+            .unwrap();
+        (sender, token)
+    }
+
+    #[test]
+    fn source_error_propagate() {
+        let mut event_loop = EventLoop::try_new().unwrap();
+        let (_sender, token) = failing_source(&event_loop);
+        event_loop.handle().set_name(&token, "failing").unwrap();
+
+        let (ping, ping_source) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(ping_source, |(), &mut (), count| *count += 10)
+            .unwrap();
+        ping.ping();
+
+        let mut count = 0;
+        let err = event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap_err();
+        // the other source was still dispatched
+        assert_eq!(count, 11);
+
+        match err {
+            crate::Error::SourceError(err) => {
+                assert_eq!(err.token, token);
+                assert_eq!(err.name.as_deref(), Some("failing"));
+                assert_eq!(err.error.to_string(), "boom");
+                assert_eq!(
+                    err.to_string(),
+                    "error processing events of source \"failing\""
+                );
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+
+        // the source is kept
+        assert!(event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .is_err());
+        assert_eq!(count, 12);
+    }
+
+    #[test]
+    fn source_error_log_and_continue() {
+        let mut event_loop = EventLoop::try_new().unwrap();
+        let (_sender, _token) = failing_source(&event_loop);
+        event_loop.set_error_policy(ErrorPolicy::LogAndContinue);
+        assert_eq!(event_loop.error_policy(), ErrorPolicy::LogAndContinue);
+
+        let mut count = 0;
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap();
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn source_error_remove_source() {
+        let mut event_loop = EventLoop::try_new().unwrap();
+        let (_sender, token) = failing_source(&event_loop);
+        event_loop.set_error_policy(ErrorPolicy::RemoveSource);
+
+        let mut count = 0;
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap();
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap();
+        assert_eq!(count, 1);
+        assert!(event_loop
+            .handle()
+            .inner
+            .sources
+            .borrow()
+            .get(token.key)
+            .is_none());
+    }
+
+    /// A ping source failing to reregister itself after each event.
+    struct FailingReregister(PingSource);
+
+    impl crate::EventSource for FailingReregister {
+        type Event = ();
+        type Metadata = ();
+        type Ret = ();
+        type Error = crate::sources::ping::PingError;
+
+        fn process_events<F>(
+            &mut self,
+            readiness: Readiness,
+            token: Token,
+            callback: F,
+        ) -> Result<PostAction, Self::Error>
+        where
+            F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+        {
+            self.0.process_events(readiness, token, callback)?;
+            Ok(PostAction::Reregister)
+        }
+
+        fn register(
+            &mut self,
+            poll: &mut Poll,
+            token_factory: &mut TokenFactory,
+        ) -> crate::Result<()> {
+            self.0.register(poll, token_factory)
+        }
+
+        fn reregister(&mut self, _: &mut Poll, _: &mut TokenFactory) -> crate::Result<()> {
+            Err(crate::Error::OtherError("reregister failed".into()))
+        }
+
+        fn unregister(&mut self, poll: &mut Poll) -> crate::Result<()> {
+            self.0.unregister(poll)
+        }
+    }
+
+    #[test]
+    fn reregister_error() {
+        let mut event_loop = EventLoop::<usize>::try_new().unwrap();
+        let (ping1, source1) = make_ping().unwrap();
+        let token = event_loop
+            .handle()
+            .insert_source_with_priority(FailingReregister(source1), 1, |(), &mut (), count| {
+                *count += 1
+            })
+            .unwrap();
+        let (ping2, source2) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source2, |(), &mut (), count| *count += 10)
+            .unwrap();
+        ping1.ping();
+        ping2.ping();
+
+        // The error is propagated once the other source was dispatched.
+        let mut count = 0;
+        let err = event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap_err();
+        assert_eq!(count, 11);
+        match err {
+            crate::Error::SourceError(err) => {
+                assert_eq!(err.token, token);
+                assert_eq!(err.error.to_string(), "reregister failed");
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+
+        // It is also subject to the other policies.
+        event_loop.set_error_policy(ErrorPolicy::RemoveSource);
+        ping1.ping();
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap();
+        assert_eq!(count, 12);
+        assert!(!event_loop.handle().inner.contains(&token));
+    }
+
+    #[test]
+    fn catch_panics_remove() {
+        let mut event_loop = EventLoop::<usize>::try_new().unwrap();
+        let panics = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let panics2 = panics.clone();
+        event_loop.catch_panics(PanicAction::Remove, move |panic| {
+            panics2.borrow_mut().push((
+                panic.token,
+                panic.name.clone(),
+                panic.message().map(str::to_owned),
+            ));
+        });
+
+        let (ping1, source1) = make_ping().unwrap();
+        let token = event_loop
+            .handle()
+            .insert_source(source1, |(), &mut (), _| panic!("source failure"))
+            .unwrap();
+        event_loop.handle().set_name(&token, "faulty").unwrap();
+        let (ping2, source2) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source2, |(), &mut (), count| *count += 1)
+            .unwrap();
+
+        ping1.ping();
+        ping2.ping();
+        let mut count = 0;
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            *panics.borrow(),
+            [(
+                token,
+                Some("faulty".to_owned()),
+                Some("source failure".to_owned())
+            )]
+        );
+
+        // the source was removed, and the loop still works
+        assert!(!event_loop
+            .handle()
+            .inner
+            .sources
+            .borrow()
+            .contains(token.key));
+        ping2.ping();
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn catch_panics_disable() {
+        let mut event_loop = EventLoop::<usize>::try_new().unwrap();
+        let panics = std::rc::Rc::new(std::cell::Cell::new(0));
+        let panics2 = panics.clone();
+        event_loop.catch_panics(PanicAction::Disable, move |panic| {
+            assert_eq!(panic.message(), Some("panic number 1"));
+            panics2.set(panics2.get() + 1);
+        });
+
+        let (ping, source) = make_ping().unwrap();
+        let token = event_loop
+            .handle()
+            .insert_source(source, |(), &mut (), count| {
+                *count += 1;
+                if *count == 1 {
+                    panic!("panic number {}", count);
+                }
+            })
+            .unwrap();
+
+        let mut count = 0;
+        ping.ping();
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap();
+        assert_eq!((count, panics.get()), (1, 1));
+
+        // the source is disabled
+        ping.ping();
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap();
+        assert_eq!(count, 1);
+
+        // and works again once enabled
+        event_loop.handle().enable(&token).unwrap();
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap();
+        assert_eq!((count, panics.get()), (2, 1));
+    }
+
+    #[test]
+    fn list_sources() {
+        use crate::timer::{TimeoutAction, Timer};
+
+        let event_loop = EventLoop::<()>::try_new().unwrap();
+        let handle = event_loop.handle();
+        assert!(handle.sources().is_empty());
+
+        let (_ping, ping_source) = make_ping().unwrap();
+        let ping_token = handle.insert_source(ping_source, |_, _, _| {}).unwrap();
+        handle.set_name(&ping_token, "ping").unwrap();
+        let timer_token = handle
+            .insert_source(Timer::from_duration(Duration::from_secs(10)), |_, _, _| {
+                TimeoutAction::Drop
+            })
+            .unwrap();
+        handle.disable(&timer_token).unwrap();
+
+        let sources = handle.sources();
+        assert_eq!(sources.len(), 2);
+        let ping = sources.iter().find(|s| s.token == ping_token).unwrap();
+        assert_eq!(ping.name.as_deref(), Some("ping"));
+        assert!(ping.type_name.ends_with("PingSource"));
+        assert!(ping.enabled);
+        assert_eq!((ping.fds, ping.timers), (1, 0));
+        let timer = sources.iter().find(|s| s.token == timer_token).unwrap();
+        assert_eq!(timer.name, None);
+        assert!(timer.type_name.ends_with("Timer"));
+        assert!(!timer.enabled);
+        assert_eq!((timer.fds, timer.timers), (0, 0));
+
+        handle.enable(&timer_token).unwrap();
+        let sources = handle.sources();
+        let timer = sources.iter().find(|s| s.token == timer_token).unwrap();
+        assert!(timer.enabled);
+        assert_eq!((timer.fds, timer.timers), (0, 1));
+
+        handle.remove(ping_token);
+        handle.remove(timer_token);
+        assert!(handle.sources().is_empty());
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn dispatch_metrics() {
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let (ping, ping_source) = make_ping().unwrap();
+        let token = event_loop
+            .handle()
+            .insert_source(ping_source, |(), &mut (), &mut ()| {
+                std::thread::sleep(Duration::from_millis(2));
+            })
+            .unwrap();
+        event_loop.handle().set_name(&token, "slow").unwrap();
+
+        ping.ping();
+        event_loop.dispatch(Some(Duration::ZERO), &mut ()).unwrap();
+        event_loop
+            .dispatch(Some(Duration::from_millis(1)), &mut ())
+            .unwrap();
+
+        let metrics = event_loop.metrics();
+        assert_eq!(metrics.dispatches, 2);
+        assert!(metrics.busy >= Duration::from_millis(2));
+        assert!(metrics.poll_wait >= Duration::from_millis(1));
+        assert_eq!(metrics.sources.len(), 1);
+        let source = &metrics.sources[0];
+        assert_eq!(source.token, token);
+        assert_eq!(source.name.as_deref(), Some("slow"));
+        assert_eq!((source.wakeups, source.invocations), (1, 1));
+        assert!(source.max_time >= Duration::from_millis(2));
+        assert_eq!(source.average_time(), Some(source.total_time));
+        assert_eq!(source.events_per_wakeup(), 1.0);
+        assert_eq!(source.histogram.count(), 1);
+
+        event_loop.reset_metrics();
+        let metrics = event_loop.metrics();
+        assert_eq!(metrics.dispatches, 0);
+        assert_eq!(metrics.sources[0].invocations, 0);
+        assert_eq!(metrics.sources[0].average_time(), None);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn dispatch_spans() {
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+
+        // Records the name of each new span, and the `name` fields recorded afterwards
+        #[derive(Clone, Default)]
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        impl Visit for Recorder {
+            fn record_debug(&mut self, _: &Field, _: &dyn std::fmt::Debug) {}
+
+            fn record_str(&mut self, field: &Field, value: &str) {
+                if field.name() == "name" {
+                    self.0.lock().unwrap().push(format!("name={}", value));
+                }
+            }
+        }
+
+        impl tracing::Subscriber for Recorder {
+            fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut spans = self.0.lock().unwrap();
+                spans.push(span.metadata().name().to_owned());
+                Id::from_u64(spans.len() as u64)
+            }
+            fn record(&self, _: &Id, values: &Record<'_>) {
+                values.record(&mut self.clone());
+            }
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &tracing::Event<'_>) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let (ping, ping_source) = make_ping().unwrap();
+        let token = event_loop
+            .handle()
+            .insert_source(ping_source, |_, _, _| {})
+            .unwrap();
+        event_loop.handle().set_name(&token, "pinger").unwrap();
+        ping.ping();
+
+        let recorder = Recorder::default();
+        tracing::subscriber::with_default(recorder.clone(), || {
+            event_loop.dispatch(Some(Duration::ZERO), &mut ()).unwrap();
+        });
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                "dispatch",
+                "pre_run",
+                "poll",
+                "events",
+                "process_events",
+                "name=pinger",
+                "idles",
+                "post_run"
+            ]
+        );
+    }
+
+    // A source needing a number of dispatching cycles to finish its work on shutdown
+    struct DrainingSource {
+        ping: Ping,
+        source: PingSource,
+        remaining: usize,
+        // whether its work wakes the loop up
+        wakes_up: bool,
+    }
+
+    impl crate::EventSource for DrainingSource {
+        type Event = &'static str;
+        type Metadata = ();
+        type Ret = ();
+        type Error = PingError;
+
+        fn process_events<F>(
+            &mut self,
+            readiness: Readiness,
+            token: Token,
+            mut callback: F,
+        ) -> Result<PostAction, Self::Error>
+        where
+            F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+        {
+            self.source
+                .process_events(readiness, token, |(), &mut ()| callback("drain", &mut ()))
+        }
+
+        fn register(&mut self, poll: &mut Poll, factory: &mut TokenFactory) -> crate::Result<()> {
+            self.source.register(poll, factory)
+        }
+
+        fn reregister(&mut self, poll: &mut Poll, factory: &mut TokenFactory) -> crate::Result<()> {
+            self.source.reregister(poll, factory)
+        }
+
+        fn unregister(&mut self, poll: &mut Poll) -> crate::Result<()> {
+            self.source.unregister(poll)
+        }
+
+        fn post_run<F>(&mut self, mut callback: F) -> crate::Result<()>
+        where
+            F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+        {
+            callback("post_run", &mut ());
+            Ok(())
+        }
+
+        fn on_shutdown(&mut self) -> bool {
+            if self.remaining == 0 {
+                return true;
+            }
+            self.remaining -= 1;
+            // make progress on the next dispatching cycle
+            if self.wakes_up {
+                self.ping.ping();
+            }
+            false
+        }
+    }
+
+    fn draining_source(remaining: usize) -> DrainingSource {
+        let (ping, source) = make_ping().unwrap();
+        DrainingSource {
+            ping,
+            source,
+            remaining,
+            wakes_up: true,
+        }
+    }
+
+    #[test]
+    fn graceful_shutdown() {
+        let mut event_loop = EventLoop::<Vec<&'static str>>::try_new().unwrap();
+        let handle = event_loop.handle();
+        handle
+            .insert_source(draining_source(3), |event, &mut (), log| log.push(event))
+            .unwrap();
+        handle
+            .insert_source(draining_source(1), |event, &mut (), log| log.push(event))
+            .unwrap();
+
+        let signal = event_loop.get_signal();
+        signal.shutdown(Duration::from_secs(10));
+
+        let mut log = Vec::new();
+        event_loop.run(None, &mut log, |_| {}).unwrap();
+        assert_eq!(
+            log,
+            ["drain", "drain", "drain", "drain", "post_run", "post_run"]
+        );
+    }
+
+    #[cfg(feature = "block_on")]
+    #[test]
+    fn graceful_shutdown_block_on() {
+        let mut event_loop = EventLoop::<Vec<&'static str>>::try_new().unwrap();
+        event_loop
+            .handle()
+            .insert_source(draining_source(2), |event, &mut (), log| log.push(event))
+            .unwrap();
+
+        event_loop.get_signal().shutdown(Duration::from_secs(10));
+
+        let mut log = Vec::new();
+        let result = event_loop
+            .block_on(std::future::pending::<()>(), &mut log, |_| {})
+            .unwrap();
+        assert_eq!(result, None);
+        assert_eq!(log, ["drain", "drain", "post_run"]);
+    }
+
+    #[test]
+    fn graceful_shutdown_manual_clock() {
+        let clock = crate::clock::ManualClock::new();
+        let mut event_loop = EventLoop::<Vec<&'static str>>::with_clock(clock.clone()).unwrap();
+        event_loop
+            .handle()
+            .insert_source(draining_source(usize::MAX), |event, &mut (), log| {
+                log.push(event)
+            })
+            .unwrap();
+
+        event_loop.get_signal().shutdown(Duration::from_secs(1));
+
+        // The timeout elapses with the clock of the loop, after ten dispatching cycles.
+        let mut log = Vec::new();
+        event_loop
+            .run(None, &mut log, |_| {
+                clock.advance(Duration::from_millis(100))
+            })
+            .unwrap();
+        assert_eq!(log.len(), 11);
+        assert_eq!(log.last(), Some(&"post_run"));
+    }
+
+    #[test]
+    fn graceful_shutdown_manual_clock_without_events() {
+        let clock = crate::clock::ManualClock::new();
+        let mut event_loop = EventLoop::<Vec<&'static str>>::with_clock(clock.clone()).unwrap();
+        let source = DrainingSource {
+            wakes_up: false,
+            ..draining_source(usize::MAX)
+        };
+        event_loop
+            .handle()
+            .insert_source(source, |event, &mut (), log| log.push(event))
+            .unwrap();
+
+        event_loop.get_signal().shutdown(Duration::from_secs(1));
+
+        // The time left cannot be measured with this clock, the loop must not wait for events
+        // that never come.
+        let timeout = Duration::from_secs(5);
+        let start = std::time::Instant::now();
+        let mut log = Vec::new();
+        event_loop
+            .run(timeout, &mut log, |_| {
+                clock.advance(Duration::from_millis(100))
+            })
+            .unwrap();
+        assert!(start.elapsed() < timeout);
+        assert_eq!(log, ["post_run"]);
+    }
+
+    #[test]
+    fn next_timeout_during_shutdown() {
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let source = DrainingSource {
+            wakes_up: false,
+            ..draining_source(usize::MAX)
+        };
+        event_loop
+            .handle()
+            .insert_source(source, |_, &mut (), _| {})
+            .unwrap();
+        assert_eq!(event_loop.next_timeout(), None);
+
+        event_loop.get_signal().shutdown(Duration::from_secs(10));
+        assert!(!event_loop.advance_shutdown(&mut None));
+
+        // The loop must be dispatched again by the deadline of the shutdown.
+        let timeout = event_loop.next_timeout().unwrap();
+        assert!(timeout > Duration::ZERO);
+        assert!(timeout <= Duration::from_secs(10));
+    }
+
+    #[test]
+    fn graceful_shutdown_deadline() {
+        let mut event_loop = EventLoop::<Vec<&'static str>>::try_new().unwrap();
+        event_loop
+            .handle()
+            .insert_source(draining_source(usize::MAX), |event, &mut (), log| {
+                log.push(event)
+            })
+            .unwrap();
+        // an idle source is done right away
+        let (_ping, ping_source) = make_ping().unwrap();
+        event_loop
+            .handle()
+            .insert_source(ping_source, |_, _, _| {})
+            .unwrap();
+
+        let signal = event_loop.get_signal();
+        let start = std::time::Instant::now();
+        signal.shutdown(Duration::from_millis(50));
+
+        let mut log = Vec::new();
+        event_loop.run(None, &mut log, |_| {}).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(log.last(), Some(&"post_run"));
+        assert!(log.len() > 2);
+    }
+
+    #[test]
+    fn source_groups() {
+        let mut event_loop = EventLoop::<Vec<u32>>::try_new().unwrap();
+        let handle = event_loop.handle();
+        let group = handle.create_group();
+        assert_ne!(group, handle.create_group());
+
+        let mut pings = Vec::new();
+        for i in 0..3 {
+            let (ping, source) = make_ping().unwrap();
+            let token = handle
+                .insert_source(source, move |(), &mut (), fired| fired.push(i))
+                .unwrap();
+            if i > 0 {
+                handle.add_to_group(&group, &token).unwrap();
+            }
+            pings.push(ping);
+        }
+        assert_eq!(handle.group_members(&group).len(), 2);
+        let ping_all = |pings: &[Ping]| pings.iter().for_each(Ping::ping);
+
+        let mut fired = Vec::new();
+        handle.disable_group(&group).unwrap();
+        ping_all(&pings);
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut fired)
+            .unwrap();
+        assert_eq!(fired, [0]);
+
+        fired.clear();
+        handle.enable_group(&group).unwrap();
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut fired)
+            .unwrap();
+        fired.sort_unstable();
+        assert_eq!(fired, [1, 2]);
+
+        // dropping the guard removes the members of the group
+        let guard = handle.guard_group(group);
+        let (ping, source) = make_ping().unwrap();
+        let token = handle
+            .insert_source(source, |(), &mut (), fired| fired.push(3))
+            .unwrap();
+        handle.add_to_group(&guard.token(), &token).unwrap();
+        pings.push(ping);
+        drop(guard);
+        assert!(handle.group_members(&group).is_empty());
+        assert_eq!(handle.sources().len(), 1);
+
+        fired.clear();
+        ping_all(&pings);
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut fired)
+            .unwrap();
+        assert_eq!(fired, [0]);
+    }
+
+    #[test]
+    fn group_guard_into_token() {
+        let event_loop = EventLoop::<()>::try_new().unwrap();
+        let handle = event_loop.handle();
+        let guard = handle.guard_group(handle.create_group());
+        let (_ping, source) = make_ping().unwrap();
+        let token = handle.insert_source(source, |_, _, _| {}).unwrap();
+        handle.add_to_group(&guard.token(), &token).unwrap();
+
+        let group = guard.into_token();
+        assert_eq!(handle.group_members(&group), [token]);
+        assert_eq!(handle.sources()[0].group, Some(group));
+        handle.remove_group(group);
+        assert!(handle.sources().is_empty());
+    }
+
+    #[test]
+    fn source_guard() {
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+        let handle = event_loop.handle();
+
+        let (ping1, source1) = make_ping().unwrap();
+        let guard = handle.guard_source(
+            handle
+                .insert_source(source1, |(), &mut (), count| *count += 1)
+                .unwrap(),
+        );
+        let (ping2, source2) = make_ping().unwrap();
+        let token = handle
+            .insert_source(source2, |(), &mut (), count| *count += 10)
+            .unwrap();
+        assert_eq!(handle.guard_source(token).into_token(), token);
+        let (_ping3, source3) = make_ping().unwrap();
+        handle
+            .guard_source(handle.insert_source(source3, |_, _, _| {}).unwrap())
+            .forget();
+        assert_eq!(handle.sources().len(), 3);
+
+        let mut count = 0;
+        ping1.ping();
+        ping2.ping();
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap();
+        assert_eq!(count, 11);
+
+        let removed = guard.token();
+        drop(guard);
+        let sources = handle.sources();
+        assert_eq!(sources.len(), 2);
+        assert!(sources.iter().all(|source| source.token != removed));
+        assert!(sources.iter().any(|source| source.token == token));
+
+        ping1.ping();
+        ping2.ping();
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap();
+        assert_eq!(count, 21);
+    }
+
+    #[test]
+    fn stale_token() {
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+        let handle = event_loop.handle();
+
+        let (_ping1, source1) = make_ping().unwrap();
+        let old = handle.insert_source(source1, |(), &mut (), _| {}).unwrap();
+        handle.remove(old);
+
+        // the new source reuses the slot of the removed one
+        let (ping2, source2) = make_ping().unwrap();
+        let new = handle
+            .insert_source(source2, |(), &mut (), count| *count += 1)
+            .unwrap();
+        assert_eq!(old.key, new.key);
+        assert_ne!(old, new);
+
+        assert!(matches!(
+            handle.disable(&old),
+            Err(crate::Error::InvalidToken)
+        ));
+        assert!(matches!(
+            handle.enable(&old),
+            Err(crate::Error::InvalidToken)
+        ));
+        assert!(matches!(
+            handle.update(&old),
+            Err(crate::Error::InvalidToken)
+        ));
+        assert!(matches!(
+            handle.set_name(&old, "stale"),
+            Err(crate::Error::InvalidToken)
+        ));
+
+        // removing through the stale token leaves the new source alone
+        handle.remove(old);
+        ping2.ping();
+        let mut count = 0;
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut count)
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn source_handle() {
+        use crate::timer::{TimeoutAction, Timer};
+        use std::time::Instant;
+
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+        let handle = event_loop.handle();
+
+        let timer = handle
+            .insert_source_with_handle(
+                Timer::from_duration(Duration::from_secs(3600)),
+                |_, &mut (), fired| {
+                    *fired += 1;
+                    TimeoutAction::Drop
+                },
+            )
+            .unwrap();
+        let token = timer.token();
+
+        // move the deadline of the timer from the callback of another source
+        let (ping, ping_source) = make_ping().unwrap();
+        handle
+            .insert_source(ping_source, move |(), &mut (), _| {
+                timer
+                    .with_source(|timer| timer.set_deadline(Instant::now()))
+                    .unwrap();
+            })
+            .unwrap();
+
+        ping.ping();
+        let mut fired = 0;
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut fired)
+            .unwrap();
+        event_loop
+            .dispatch(Some(Duration::from_millis(100)), &mut fired)
+            .unwrap();
+        assert_eq!(fired, 1);
+        assert!(!handle.inner.contains(&token));
+    }
+
+    #[test]
+    fn source_handle_wrong_token() {
+        let event_loop = EventLoop::<()>::try_new().unwrap();
+        let handle = event_loop.handle();
+
+        let (_ping1, source1) = make_ping().unwrap();
+        let dispatcher = Dispatcher::new(source1, |(), &mut (), _| {});
+        let token = handle.register_dispatcher(dispatcher.clone()).unwrap();
+        let (_ping2, source2) = make_ping().unwrap();
+        let other = handle.insert_source(source2, |(), &mut (), _| {}).unwrap();
+
+        assert!(matches!(
+            handle.source_handle(other, dispatcher.clone()),
+            Err(crate::Error::InvalidToken)
+        ));
+        handle.remove(token);
+        assert!(matches!(
+            handle.source_handle(token, dispatcher),
+            Err(crate::Error::InvalidToken)
+        ));
+    }
+
+    #[test]
+    fn source_handle_own_callback() {
+        use super::SourceHandle;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut event_loop = EventLoop::<Option<bool>>::try_new().unwrap();
+        let handle = event_loop.handle();
+
+        let slot: Rc<RefCell<Option<SourceHandle<'_, PingSource, _>>>> = Rc::default();
+        let slot2 = slot.clone();
+        let (ping, source) = make_ping().unwrap();
+        let dispatcher = Dispatcher::new(source, move |(), &mut (), busy: &mut Option<bool>| {
+            let source = slot2.borrow();
+            let result = source.as_ref().unwrap().with_source(|_| ());
+            *busy = Some(matches!(result, Err(crate::Error::SourceBusy)));
+        });
+        let token = handle.register_dispatcher(dispatcher.clone()).unwrap();
+        *slot.borrow_mut() = Some(handle.source_handle(token, dispatcher).unwrap());
+
+        ping.ping();
+        let mut busy = None;
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut busy)
+            .unwrap();
+        assert_eq!(busy, Some(true));
+
+        // break the reference cycle between the source and its handle
+        slot.borrow_mut().take();
+    }
+
+    #[test]
+    fn source_handle_removed() {
+        let event_loop = EventLoop::<()>::try_new().unwrap();
+        let handle = event_loop.handle();
+
+        let (_ping, source) = make_ping().unwrap();
+        let source = handle
+            .insert_source_with_handle(source, |(), &mut (), _| {})
+            .unwrap();
+        handle.remove(source.token());
+
+        let mut called = false;
+        assert!(matches!(
+            source.with_source(|_| called = true),
+            Err(crate::Error::InvalidToken)
+        ));
+        assert!(!called);
+    }
+
+    #[test]
+    fn post_action_of_secondary_token() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        // A source registering two FDs: the tokens of the second one have a non-zero sub-id.
+        struct TwoPings {
+            first: PingSource,
+            second: PingSource,
+            action: Rc<Cell<PostAction>>,
+        }
+
+        impl crate::EventSource for TwoPings {
+            type Event = &'static str;
+            type Metadata = ();
+            type Ret = ();
+            type Error = PingError;
+
+            fn process_events<F>(
+                &mut self,
+                readiness: Readiness,
+                token: Token,
+                mut callback: F,
+            ) -> Result<PostAction, Self::Error>
+            where
+                F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+            {
+                self.first
+                    .process_events(readiness, token, |(), _| callback("first", &mut ()))?;
+                self.second
+                    .process_events(readiness, token, |(), _| callback("second", &mut ()))?;
+                Ok(self.action.get())
+            }
+
+            fn register(
+                &mut self,
+                poll: &mut Poll,
+                factory: &mut TokenFactory,
+            ) -> crate::Result<()> {
+                self.first.register(poll, factory)?;
+                self.second.register(poll, factory)
+            }
+
+            fn reregister(
+                &mut self,
+                poll: &mut Poll,
+                factory: &mut TokenFactory,
+            ) -> crate::Result<()> {
+                self.first.reregister(poll, factory)?;
+                self.second.reregister(poll, factory)
+            }
+
+            fn unregister(&mut self, poll: &mut Poll) -> crate::Result<()> {
+                self.first.unregister(poll)?;
+                self.second.unregister(poll)
+            }
+        }
+
+        let mut event_loop = EventLoop::<Vec<&'static str>>::try_new().unwrap();
+        let handle = event_loop.handle();
+        let (ping1, first) = make_ping().unwrap();
+        let (ping2, second) = make_ping().unwrap();
+        let action = Rc::new(Cell::new(PostAction::Reregister));
+        let source = TwoPings {
+            first,
+            second,
+            action: action.clone(),
+        };
+        handle
+            .insert_source(source, |name, &mut (), fired| fired.push(name))
+            .unwrap();
+
+        // The source is reregistered with tokens derived from its own ID, not from the token of
+        // the event that triggered it.
+        let mut fired = Vec::new();
+        ping2.ping();
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut fired)
+            .unwrap();
+        assert_eq!(fired, ["second"]);
+
+        action.set(PostAction::Continue);
+        fired.clear();
+        ping1.ping();
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut fired)
+            .unwrap();
+        assert_eq!(fired, ["first"]);
+
+        // Removing the source from the event of its second FD removes the source itself.
+        action.set(PostAction::Remove);
+        fired.clear();
+        ping2.ping();
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut fired)
+            .unwrap();
+        assert_eq!(fired, ["second"]);
+        assert!(handle.sources().is_empty());
+    }
+
+    #[test]
+    fn stale_token_after_generation_wrap() {
+        let event_loop = EventLoop::<()>::try_new().unwrap();
+        let handle = event_loop.handle();
+        let first = handle
+            .insert_source(DummySource, |(), &mut (), _| {})
+            .unwrap();
+        handle.remove(first);
+
+        // Reuse the slot until the generation bits of the poll tokens wrap around.
+        let mut token = first;
+        for i in 0..=GENERATION_MASK {
+            if i > 0 {
+                handle.remove(token);
+            }
+            token = handle
+                .insert_source(DummySource, |(), &mut (), _| {})
+                .unwrap();
+            assert_eq!(token.key, first.key);
+            assert!(matches!(
+                handle.disable(&first),
+                Err(crate::Error::InvalidToken)
+            ));
+        }
+
+        // Both sources share the same poll token ID, but the first token is still stale.
+        assert_eq!(token.id(), first.id());
+        assert_ne!(token, first);
+        handle.remove(first);
+        assert!(handle.inner.contains(&token));
+        handle.disable(&token).unwrap();
+    }
+
     // A dummy EventSource to test insertion and removal of sources
     struct DummySource;
 
@@ -0,0 +1,202 @@
+//! Dispatching metrics of an event loop
+//!
+//! With the `metrics` cargo feature, the event loop measures the time spent in the callbacks of
+//! its sources, and the time spent waiting for events. [`EventLoop::metrics()`] takes a snapshot
+//! of these measurements, which can then be exported to the metrics system of the application.
+//!
+//! All durations are measured with the system monotonic clock, even for event loops using a custom
+//! [`LoopClock`](crate::clock::LoopClock).
+
+use std::time::Duration;
+
+use crate::RegistrationToken;
+
+#[cfg(doc)]
+use crate::EventLoop;
+
+const BUCKETS: usize = 24;
+
+/// A snapshot of the metrics of an event loop
+///
+/// See [`EventLoop::metrics()`].
+#[derive(Clone, Debug)]
+pub struct LoopMetrics {
+    /// The number of dispatching cycles
+    pub dispatches: u64,
+    /// The total time spent waiting for events
+    pub poll_wait: Duration,
+    /// The total time spent dispatching events, outside of waiting for them
+    pub busy: Duration,
+    /// The metrics of each source of the loop
+    pub sources: Vec<SourceMetrics>,
+}
+
+/// A snapshot of the metrics of an event source
+#[derive(Clone, Debug)]
+pub struct SourceMetrics {
+    /// The token of the source
+    pub token: RegistrationToken,
+    /// The name of the source, if it was given one with
+    /// [`LoopHandle::set_name()`](crate::LoopHandle::set_name)
+    pub name: Option<String>,
+    /// The name of the type of the source
+    pub type_name: &'static str,
+    /// The number of times the source was woken up to process its events
+    pub wakeups: u64,
+    /// The number of times the callback of the source was invoked
+    pub invocations: u64,
+    /// The total time spent in the callback of the source
+    pub total_time: Duration,
+    /// The longest time spent in a single invocation of the callback
+    pub max_time: Duration,
+    /// The distribution of the time spent in each invocation of the callback
+    pub histogram: Histogram,
+}
+
+impl SourceMetrics {
+    /// The average time spent in an invocation of the callback
+    ///
+    /// This is `None` if the callback was never invoked.
+    pub fn average_time(&self) -> Option<Duration> {
+        // `Duration` can only be divided by an `u32`
+        let average = self.total_time.as_nanos() / u128::from(self.invocations.max(1));
+        (self.invocations > 0).then(|| Duration::from_nanos(average as u64))
+    }
+
+    /// The average number of callback invocations per wakeup
+    pub fn events_per_wakeup(&self) -> f64 {
+        if self.wakeups == 0 {
+            0.0
+        } else {
+            self.invocations as f64 / self.wakeups as f64
+        }
+    }
+}
+
+/// A histogram of durations
+///
+/// The durations are counted in buckets whose bounds are powers of two microseconds: the first
+/// bucket counts durations under 1µs, the next one durations under 2µs, then 4µs, and so on. The
+/// last bucket counts all the durations that did not fit in the other ones.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+}
+
+impl Histogram {
+    pub(crate) fn record(&mut self, duration: Duration) {
+        let micros = duration.as_micros();
+        let bucket = (u128::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += count;
+        }
+    }
+
+    /// The buckets of the histogram
+    ///
+    /// Each bucket is given as its exclusive upper bound, and the number of durations it counts.
+    /// The upper bound of the last bucket is `None`.
+    pub fn buckets(&self) -> impl Iterator<Item = (Option<Duration>, u64)> + '_ {
+        self.buckets.iter().enumerate().map(|(i, &count)| {
+            let bound = (i < BUCKETS - 1).then(|| Duration::from_micros(1 << i));
+            (bound, count)
+        })
+    }
+
+    /// The total number of durations recorded in the histogram
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+}
+
+// The measurements of a source, from one or several wakeups.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SourceCounters {
+    wakeups: u64,
+    invocations: u64,
+    total_time: Duration,
+    max_time: Duration,
+    histogram: Histogram,
+}
+
+impl SourceCounters {
+    pub(crate) fn record_callback(&mut self, time: Duration) {
+        self.invocations += 1;
+        self.total_time += time;
+        self.max_time = self.max_time.max(time);
+        self.histogram.record(time);
+    }
+
+    // Add the measurements of a single wakeup.
+    pub(crate) fn record_wakeup(&mut self, wakeup: &SourceCounters) {
+        self.wakeups += 1;
+        self.invocations += wakeup.invocations;
+        self.total_time += wakeup.total_time;
+        self.max_time = self.max_time.max(wakeup.max_time);
+        self.histogram.merge(&wakeup.histogram);
+    }
+
+    pub(crate) fn snapshot(
+        &self,
+        token: RegistrationToken,
+        name: Option<String>,
+        type_name: &'static str,
+    ) -> SourceMetrics {
+        SourceMetrics {
+            token,
+            name,
+            type_name,
+            wakeups: self.wakeups,
+            invocations: self.invocations,
+            total_time: self.total_time,
+            max_time: self.max_time,
+            histogram: self.histogram,
+        }
+    }
+}
+
+// The measurements of the loop itself.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LoopCounters {
+    pub(crate) dispatches: u64,
+    pub(crate) poll_wait: Duration,
+    pub(crate) busy: Duration,
+}
+
+impl LoopCounters {
+    // Record a dispatching cycle, given its duration and the poll wait time before it started.
+    pub(crate) fn record_dispatch(&mut self, elapsed: Duration, poll_wait_before: Duration) {
+        let waited = self.poll_wait - poll_wait_before;
+        self.dispatches += 1;
+        self.busy += elapsed.saturating_sub(waited);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn histogram_buckets() {
+        let mut histogram = Histogram::default();
+        histogram.record(Duration::from_nanos(500));
+        histogram.record(Duration::from_micros(1));
+        histogram.record(Duration::from_micros(3));
+        histogram.record(Duration::from_micros(3));
+        histogram.record(Duration::from_secs(3600));
+
+        let buckets = histogram.buckets().collect::<Vec<_>>();
+        assert_eq!(buckets.len(), BUCKETS);
+        assert_eq!(buckets[0], (Some(Duration::from_micros(1)), 1));
+        assert_eq!(buckets[1], (Some(Duration::from_micros(2)), 1));
+        assert_eq!(buckets[2], (Some(Duration::from_micros(4)), 2));
+        assert_eq!(buckets[BUCKETS - 1], (None, 1));
+        assert_eq!(histogram.count(), 5);
+    }
+}
@@ -8,6 +8,7 @@
 //! A synchronous version of the channel is provided by [`sync_channel`], in which
 //! the [`SyncSender`] will block when the channel is full.
 
+use std::num::NonZeroUsize;
 use std::sync::mpsc;
 
 use crate::{EventSource, Poll, PostAction, Readiness, Token, TokenFactory};
@@ -122,6 +123,9 @@ impl<T> SyncSender<T> {
 pub struct Channel<T> {
     receiver: mpsc::Receiver<T>,
     source: PingSource,
+    budget: Option<NonZeroUsize>,
+    // Messages were left in the channel because the budget was exhausted.
+    pending: bool,
 }
 
 // This impl is safe because the Channel is only able to move around threads
@@ -155,14 +159,30 @@ impl<T> Channel<T> {
 pub fn channel<T>() -> (Sender<T>, Channel<T>) {
     let (sender, receiver) = mpsc::channel();
     let (ping, source) = make_ping().expect("Failed to create a Ping.");
-    (Sender { sender, ping }, Channel { receiver, source })
+    (
+        Sender { sender, ping },
+        Channel {
+            receiver,
+            source,
+            budget: None,
+            pending: false,
+        },
+    )
 }
 
 /// Create a new synchronous, bounded channel
 pub fn sync_channel<T>(bound: usize) -> (SyncSender<T>, Channel<T>) {
     let (sender, receiver) = mpsc::sync_channel(bound);
     let (ping, source) = make_ping().expect("Failed to create a Ping.");
-    (SyncSender { sender, ping }, Channel { receiver, source })
+    (
+        SyncSender { sender, ping },
+        Channel {
+            receiver,
+            source,
+            budget: None,
+            pending: false,
+        },
+    )
 }
 
 impl<T> EventSource for Channel<T> {
@@ -180,19 +200,44 @@ impl<T> EventSource for Channel<T> {
     where
         C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
     {
-        let receiver = &self.receiver;
-        self.source
-            .process_events(readiness, token, |(), &mut ()| loop {
-                match receiver.try_recv() {
-                    Ok(val) => callback(Event::Msg(val), &mut ()),
+        // Leftover messages from a previous dispatch are processed even without a new ping.
+        let mut readable = std::mem::take(&mut self.pending);
+        let mut action = if readiness.readable {
+            self.source
+                .process_events(readiness, token, |(), &mut ()| readable = true)
+                .map_err(ChannelError)?
+        } else {
+            PostAction::Continue
+        };
+
+        if readable {
+            let mut count = 0;
+            loop {
+                if self.budget.map_or(false, |budget| count >= budget.get()) {
+                    // Keep the source alive until the remaining messages are processed.
+                    self.pending = true;
+                    if action == PostAction::Remove {
+                        action = PostAction::Continue;
+                    }
+                    break;
+                }
+                match self.receiver.try_recv() {
+                    Ok(val) => {
+                        callback(Event::Msg(val), &mut ());
+                        count += 1;
+                    }
                     Err(mpsc::TryRecvError::Empty) => break,
                     Err(mpsc::TryRecvError::Disconnected) => {
                         callback(Event::Closed, &mut ());
+                        // All senders are gone, the channel won't generate more events.
+                        action = PostAction::Remove;
                         break;
                     }
                 }
-            })
-            .map_err(ChannelError)
+            }
+        }
+
+        Ok(action)
     }
 
     fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> crate::Result<()> {
@@ -210,6 +255,14 @@ impl<T> EventSource for Channel<T> {
     fn unregister(&mut self, poll: &mut Poll) -> crate::Result<()> {
         self.source.unregister(poll)
     }
+
+    fn set_event_budget(&mut self, budget: Option<NonZeroUsize>) {
+        self.budget = budget;
+    }
+
+    fn has_pending_events(&self) -> bool {
+        self.pending
+    }
 }
 
 /// An error arising from processing events for a channel.
@@ -324,4 +377,84 @@ mod tests {
         assert_eq!(received.0, 3);
         assert!(received.1);
     }
+
+    #[test]
+    fn channel_event_budget() {
+        let mut event_loop = crate::EventLoop::try_new().unwrap();
+        event_loop.set_event_budget(NonZeroUsize::new(2));
+
+        let handle = event_loop.handle();
+
+        let (tx, rx) = channel::<u32>();
+
+        let mut received = (Vec::new(), false);
+
+        let _channel_token = handle
+            .insert_source(
+                rx,
+                |evt, &mut (), received: &mut (Vec<u32>, bool)| match evt {
+                    Event::Msg(i) => received.0.push(i),
+                    Event::Closed => received.1 = true,
+                },
+            )
+            .unwrap();
+
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        std::mem::drop(tx);
+
+        // Each dispatch only processes two messages, the leftovers must be processed
+        // without waiting for the timeout
+        let timeout = std::time::Duration::from_secs(10);
+        let start = std::time::Instant::now();
+
+        event_loop.dispatch(timeout, &mut received).unwrap();
+        assert_eq!(received, (vec![0, 1], false));
+
+        event_loop.dispatch(timeout, &mut received).unwrap();
+        assert_eq!(received, (vec![0, 1, 2, 3], false));
+
+        event_loop.dispatch(timeout, &mut received).unwrap();
+        assert_eq!(received, (vec![0, 1, 2, 3, 4], true));
+
+        assert!(start.elapsed() < timeout);
+    }
+
+    #[test]
+    fn channel_smallest_event_budget() {
+        let mut event_loop = crate::EventLoop::try_new().unwrap();
+        // The budget cannot be zero, which would never make progress.
+        event_loop.set_event_budget(NonZeroUsize::new(1));
+        assert_eq!(event_loop.event_budget(), NonZeroUsize::new(1));
+
+        let (tx, rx) = channel::<u32>();
+        let mut received = (Vec::new(), false);
+        let _channel_token = event_loop
+            .handle()
+            .insert_source(
+                rx,
+                |evt, &mut (), received: &mut (Vec<u32>, bool)| match evt {
+                    Event::Msg(i) => received.0.push(i),
+                    Event::Closed => received.1 = true,
+                },
+            )
+            .unwrap();
+
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        std::mem::drop(tx);
+
+        // Every dispatch processes one message
+        let timeout = std::time::Duration::from_secs(10);
+        let start = std::time::Instant::now();
+        for i in 0..3 {
+            event_loop.dispatch(timeout, &mut received).unwrap();
+            assert_eq!(received.0, (0..=i).collect::<Vec<_>>());
+        }
+        event_loop.dispatch(timeout, &mut received).unwrap();
+        assert!(received.1);
+        assert!(start.elapsed() < timeout);
+    }
 }
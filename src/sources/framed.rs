@@ -0,0 +1,807 @@
+//! Event source for exchanging messages over a stream
+//!
+//! The [`Framed`] event source owns a stream, such as a [`TcpStream`](std::net::TcpStream) or a
+//! [`UnixStream`](std::os::unix::net::UnixStream), and splits what it reads into messages
+//! using a [`Decoder`]. Each message is an event of the source. Its callback is given a
+//! [`WriteQueue`], in which replies are turned into bytes by an [`Encoder`] and written to the
+//! stream as soon as it can take them.
+//!
+//! Two codecs are provided: [`LinesCodec`], for newline-separated text, and
+//! [`LengthDelimitedCodec`], for binary messages prefixed by their length.
+//!
+//! ```no_run
+//! use calloop::{framed::{Event, Framed, LinesCodec}, EventLoop};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut event_loop = EventLoop::<()>::try_new()?;
+//!
+//! let stream = std::net::TcpStream::connect("127.0.0.1:8080")?;
+//! let framed = Framed::new(stream, LinesCodec::new())?;
+//! event_loop
+//!     .handle()
+//!     .insert_source(framed, |event, queue, _| match event {
+//!         // Answer each line by echoing it.
+//!         Event::Message(line) => queue.send(line).unwrap(),
+//!         Event::Closed(error) => println!("connection closed: {:?}", error),
+//!     })?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io::{self, Read, Write},
+    num::NonZeroUsize,
+    os::unix::io::AsRawFd,
+};
+
+use io_lifetimes::AsFd;
+use nix::fcntl::{fcntl, FcntlArg, OFlag};
+
+use super::generic::Generic;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// Splits the bytes read from a stream into messages
+pub trait Decoder {
+    /// The type of the decoded messages
+    type Item;
+    /// The error generated when the stream contains an invalid message
+    type Error: Into<Box<dyn std::error::Error + Sync + Send>>;
+
+    /// Decode a message from the beginning of `buffer`
+    ///
+    /// If `buffer` starts with a whole message, it should be removed from the buffer and
+    /// returned. Otherwise, `Ok(None)` asks for more bytes.
+    fn decode(&mut self, buffer: &mut Vec<u8>) -> Result<Option<Self::Item>, Self::Error>;
+
+    /// Decode a message from the beginning of `buffer`, once the end of the stream was reached
+    ///
+    /// This is called until it returns `Ok(None)`. If bytes are then left in the buffer, the
+    /// stream was closed in the middle of a message.
+    ///
+    /// The default implementation calls [`decode()`](Self::decode).
+    fn decode_eof(&mut self, buffer: &mut Vec<u8>) -> Result<Option<Self::Item>, Self::Error> {
+        self.decode(buffer)
+    }
+}
+
+/// Turns messages into bytes to write to a stream
+pub trait Encoder<Item> {
+    /// The error generated when a message cannot be encoded
+    type Error;
+
+    /// Append the encoded `item` to `buffer`
+    fn encode(&mut self, item: Item, buffer: &mut Vec<u8>) -> Result<(), Self::Error>;
+}
+
+/// An event generated by a [`Framed`] source
+#[derive(Debug)]
+pub enum Event<T> {
+    /// A message was received
+    Message(T),
+    /// The stream was closed
+    ///
+    /// This contains the error that closed it, if it was not closed by the peer. This is the last
+    /// event of the source, which is then removed from the event loop.
+    Closed(Option<FramedError>),
+}
+
+/// The queue of data waiting to be written to the stream of a [`Framed`] source
+///
+/// This is the metadata of the [`Framed`] source, given to its callback.
+#[derive(Debug)]
+pub struct WriteQueue<C> {
+    codec: C,
+    buffer: Vec<u8>,
+}
+
+impl<C> WriteQueue<C> {
+    /// Queue a message to be written to the stream
+    ///
+    /// The message is written as soon as the stream can take it.
+    pub fn send<T>(&mut self, item: T) -> Result<(), C::Error>
+    where
+        C: Encoder<T>,
+    {
+        self.codec.encode(item, &mut self.buffer)
+    }
+
+    /// Queue raw bytes to be written to the stream
+    pub fn send_raw(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// The number of bytes waiting to be written
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Whether all the queued data has been written
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Access the codec
+    pub fn codec(&mut self) -> &mut C {
+        &mut self.codec
+    }
+
+    /// Write as much of the queue as the stream takes.
+    fn flush(&mut self, stream: &mut impl Write) -> io::Result<()> {
+        let mut written = 0;
+        let result = loop {
+            if written == self.buffer.len() {
+                break Ok(());
+            }
+            match stream.write(&self.buffer[written..]) {
+                Ok(0) => break Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) => break Err(e),
+            }
+        };
+        self.buffer.drain(..written);
+        result
+    }
+}
+
+/// An event source exchanging messages over a stream
+///
+/// The source waits for the stream to be readable, and also for it to be writable while data is
+/// waiting in its [`WriteQueue`]. Messages queued from outside of its callback, through
+/// [`queue()`](Self::queue), are only written once the source is notified: use
+/// [`LoopHandle::update()`](crate::LoopHandle::update) afterwards.
+///
+/// The source does not read more than [`set_max_buffer_size()`](Self::set_max_buffer_size)
+/// bytes ahead of its decoder. With an [event budget](crate::EventLoop::set_event_budget), it also
+/// reads at most 4 KiB per event of the budget each time it is woken up. The rest of the data is
+/// read during the next dispatching cycles.
+///
+/// During a [graceful shutdown](crate::LoopSignal::shutdown) of the loop, the source reports
+/// itself done once its write queue is empty.
+#[derive(Debug)]
+pub struct Framed<S: AsFd, C> {
+    fd: Generic<S>,
+    read_buffer: Vec<u8>,
+    max_buffer_size: usize,
+    queue: WriteQueue<C>,
+    budget: Option<NonZeroUsize>,
+    /// Whether messages were left in the read buffer because of the budget.
+    pending: bool,
+    /// Whether data was left unread in the stream because of the read limits.
+    unread: bool,
+}
+
+impl<S, C> Framed<S, C>
+where
+    S: AsFd + Read + Write,
+    C: Decoder,
+{
+    /// Create a source exchanging messages over `stream`, using `codec` to decode and encode them
+    ///
+    /// The stream is moved into non-blocking mode.
+    pub fn new(stream: S, codec: C) -> io::Result<Framed<S, C>> {
+        let fd = stream.as_fd().as_raw_fd();
+        let flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
+        fcntl(fd, FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK))?;
+
+        Ok(Framed {
+            fd: Generic::new(stream, Interest::READ, Mode::Level),
+            read_buffer: Vec::new(),
+            max_buffer_size: DEFAULT_MAX_BUFFER_SIZE,
+            queue: WriteQueue {
+                codec,
+                buffer: Vec::new(),
+            },
+            budget: None,
+            pending: false,
+            unread: false,
+        })
+    }
+
+    /// Set the maximum number of bytes read from the stream but not decoded yet
+    ///
+    /// Once this many bytes are buffered without the decoder finding a whole message in them,
+    /// the source is closed with an error. It defaults to 16 MiB.
+    pub fn set_max_buffer_size(&mut self, max_buffer_size: usize) {
+        self.max_buffer_size = max_buffer_size;
+    }
+
+    /// Access the stream
+    pub fn get_ref(&self) -> &S {
+        &self.fd.file
+    }
+
+    /// Access the queue of data to write to the stream
+    pub fn queue(&mut self) -> &mut WriteQueue<C> {
+        &mut self.queue
+    }
+
+    /// Retrieve the stream and the codec
+    ///
+    /// The data that was read but not decoded yet, and the data that was queued but not written
+    /// yet, are lost.
+    pub fn into_inner(self) -> (S, C) {
+        (self.fd.unwrap(), self.queue.codec)
+    }
+
+    fn wanted_interest(&self) -> Interest {
+        if self.queue.is_empty() {
+            Interest::READ
+        } else {
+            Interest::BOTH
+        }
+    }
+}
+
+impl<S, C> EventSource for Framed<S, C>
+where
+    S: AsFd + Read + Write,
+    C: Decoder,
+{
+    type Event = Event<C::Item>;
+    type Metadata = WriteQueue<C>;
+    type Ret = ();
+    type Error = FramedError;
+
+    fn process_events<F>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: F,
+    ) -> Result<PostAction, Self::Error>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let Framed {
+            ref mut fd,
+            ref mut read_buffer,
+            max_buffer_size,
+            ref mut queue,
+            budget,
+            ref mut pending,
+            ref mut unread,
+        } = *self;
+
+        let read_limit =
+            budget.map_or(usize::MAX, |budget| budget.get().saturating_mul(CHUNK_SIZE));
+        let mut eof = false;
+        let mut error: Option<FramedError> = None;
+        fd.process_events(readiness, token, |readiness, stream| {
+            if readiness.writable {
+                if let Err(e) = queue.flush(stream) {
+                    error = Some(FramedError(e.into()));
+                }
+            }
+            // Reading also reports the errors and the end of the stream.
+            if readiness.readable || readiness.error || readiness.hangup || *unread {
+                let limit = max_buffer_size.min(read_buffer.len().saturating_add(read_limit));
+                *unread = false;
+                match read_available(stream, read_buffer, limit) {
+                    Ok(ReadStatus::Drained) => {}
+                    Ok(ReadStatus::Closed) => eof = true,
+                    Ok(ReadStatus::LimitReached) => *unread = true,
+                    Err(e) => error = Some(FramedError(e.into())),
+                }
+            }
+            Ok(PostAction::Continue)
+        })
+        .map_err(|e| FramedError(e.into()))?;
+
+        let mut count = 0;
+        *pending = false;
+        while error.is_none() {
+            if budget.map_or(false, |budget| count >= budget.get()) {
+                *pending = true;
+                break;
+            }
+            let decoded = if eof {
+                queue.codec.decode_eof(read_buffer)
+            } else {
+                queue.codec.decode(read_buffer)
+            };
+            match decoded {
+                Ok(Some(item)) => {
+                    count += 1;
+                    callback(Event::Message(item), queue);
+                }
+                Ok(None) => {
+                    if read_buffer.len() >= max_buffer_size {
+                        error = Some(FramedError(
+                            io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                "message larger than the maximum buffer size",
+                            )
+                            .into(),
+                        ));
+                    }
+                    break;
+                }
+                Err(e) => error = Some(FramedError(e.into())),
+            }
+        }
+        if eof && !*pending && error.is_none() && !read_buffer.is_empty() {
+            error = Some(FramedError(
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "the stream was closed in the middle of a message",
+                )
+                .into(),
+            ));
+        }
+
+        if error.is_none() && !queue.is_empty() {
+            // Don't wait for the stream to be writable if it already is.
+            if let Err(e) = queue.flush(&mut fd.file) {
+                error = Some(FramedError(e.into()));
+            }
+        }
+
+        if error.is_some() || (eof && !*pending) {
+            callback(Event::Closed(error), queue);
+            return Ok(PostAction::Remove);
+        }
+
+        let interest = self.wanted_interest();
+        if self.fd.interest.writable != interest.writable {
+            self.fd.interest = interest;
+            return Ok(PostAction::Reregister);
+        }
+        Ok(PostAction::Continue)
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> crate::Result<()> {
+        self.fd.interest = self.wanted_interest();
+        self.fd.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> crate::Result<()> {
+        self.fd.interest = self.wanted_interest();
+        self.fd.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> crate::Result<()> {
+        self.fd.unregister(poll)
+    }
+
+    fn set_event_budget(&mut self, budget: Option<NonZeroUsize>) {
+        self.budget = budget;
+    }
+
+    fn has_pending_events(&self) -> bool {
+        self.pending || self.unread
+    }
+
+    fn on_shutdown(&mut self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+/// The default maximum size of the read buffer of a [`Framed`] source, 16 MiB
+const DEFAULT_MAX_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+/// The number of bytes read from the stream at once
+const CHUNK_SIZE: usize = 4096;
+
+/// Why [`read_available()`] stopped reading.
+#[derive(Debug, PartialEq, Eq)]
+enum ReadStatus {
+    /// Everything available was read.
+    Drained,
+    /// The end of the stream was reached.
+    Closed,
+    /// The buffer reached the limit, more data may be available.
+    LimitReached,
+}
+
+/// Read what is available from `stream`, until `buffer` holds `limit` bytes.
+fn read_available(
+    stream: &mut impl Read,
+    buffer: &mut Vec<u8>,
+    limit: usize,
+) -> io::Result<ReadStatus> {
+    let mut chunk = [0u8; CHUNK_SIZE];
+    loop {
+        let room = limit.saturating_sub(buffer.len()).min(CHUNK_SIZE);
+        if room == 0 {
+            return Ok(ReadStatus::LimitReached);
+        }
+        match stream.read(&mut chunk[..room]) {
+            Ok(0) => return Ok(ReadStatus::Closed),
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(ReadStatus::Drained),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// An error arising from processing events for a framed stream.
+#[derive(thiserror::Error, Debug)]
+#[error(transparent)]
+pub struct FramedError(Box<dyn std::error::Error + Sync + Send>);
+
+/// A codec for newline-separated UTF-8 text
+///
+/// Decoded lines do not include their trailing `\n`, nor a `\r` before it. Encoded lines are
+/// terminated by a `\n`.
+#[derive(Clone, Debug, Default)]
+pub struct LinesCodec {
+    max_length: Option<usize>,
+    /// How far the buffer has been searched for a newline, so that a long line received in many
+    /// chunks is not searched from its start for each of them.
+    next_index: usize,
+}
+
+impl LinesCodec {
+    /// Create a codec accepting lines of any length
+    pub fn new() -> LinesCodec {
+        LinesCodec {
+            max_length: None,
+            next_index: 0,
+        }
+    }
+
+    /// Create a codec rejecting lines longer than `max_length` bytes
+    ///
+    /// Without a maximum, a peer that never sends a newline makes the source buffer everything
+    /// it receives.
+    pub fn with_max_length(max_length: usize) -> LinesCodec {
+        LinesCodec {
+            max_length: Some(max_length),
+            next_index: 0,
+        }
+    }
+}
+
+impl Decoder for LinesCodec {
+    type Item = String;
+    type Error = io::Error;
+
+    fn decode(&mut self, buffer: &mut Vec<u8>) -> io::Result<Option<String>> {
+        let start = self.next_index.min(buffer.len());
+        let end = match buffer[start..].iter().position(|&byte| byte == b'\n') {
+            Some(offset) => start + offset,
+            None => {
+                self.next_index = buffer.len();
+                if self.max_length.map_or(false, |max| buffer.len() > max) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "line longer than the maximum length",
+                    ));
+                }
+                return Ok(None);
+            }
+        };
+        self.next_index = 0;
+        let mut line: Vec<u8> = buffer.drain(..=end).collect();
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if self.max_length.map_or(false, |max| line.len() > max) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "line longer than the maximum length",
+            ));
+        }
+        String::from_utf8(line)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    fn decode_eof(&mut self, buffer: &mut Vec<u8>) -> io::Result<Option<String>> {
+        match self.decode(buffer)? {
+            Some(line) => Ok(Some(line)),
+            // The last line has no trailing newline.
+            None if !buffer.is_empty() => {
+                buffer.push(b'\n');
+                self.decode(buffer)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl<'a> Encoder<&'a str> for LinesCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, line: &'a str, buffer: &mut Vec<u8>) -> io::Result<()> {
+        buffer.extend_from_slice(line.as_bytes());
+        buffer.push(b'\n');
+        Ok(())
+    }
+}
+
+impl Encoder<String> for LinesCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, line: String, buffer: &mut Vec<u8>) -> io::Result<()> {
+        self.encode(line.as_str(), buffer)
+    }
+}
+
+/// The default maximum length of the frames of a [`LengthDelimitedCodec`], 8 MiB
+const DEFAULT_MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+/// A codec for binary frames prefixed by their length
+///
+/// The length is a big-endian `u32`, and does not include the length prefix itself.
+#[derive(Clone, Debug)]
+pub struct LengthDelimitedCodec {
+    max_frame_length: usize,
+}
+
+impl LengthDelimitedCodec {
+    /// Create a codec accepting frames of up to 8 MiB
+    pub fn new() -> LengthDelimitedCodec {
+        LengthDelimitedCodec {
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+        }
+    }
+
+    /// Create a codec accepting frames of up to `max_frame_length` bytes
+    pub fn with_max_frame_length(max_frame_length: usize) -> LengthDelimitedCodec {
+        LengthDelimitedCodec { max_frame_length }
+    }
+}
+
+impl Default for LengthDelimitedCodec {
+    fn default() -> LengthDelimitedCodec {
+        LengthDelimitedCodec::new()
+    }
+}
+
+impl Decoder for LengthDelimitedCodec {
+    type Item = Vec<u8>;
+    type Error = io::Error;
+
+    fn decode(&mut self, buffer: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        if buffer.len() < 4 {
+            return Ok(None);
+        }
+        let length = u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) as usize;
+        if length > self.max_frame_length {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame longer than the maximum length",
+            ));
+        }
+        if buffer.len() < 4 + length {
+            return Ok(None);
+        }
+        let frame = buffer[4..4 + length].to_vec();
+        buffer.drain(..4 + length);
+        Ok(Some(frame))
+    }
+}
+
+impl<'a> Encoder<&'a [u8]> for LengthDelimitedCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: &'a [u8], buffer: &mut Vec<u8>) -> io::Result<()> {
+        if frame.len() > self.max_frame_length || frame.len() > u32::MAX as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame longer than the maximum length",
+            ));
+        }
+        buffer.extend_from_slice(&(frame.len() as u32).to_be_bytes());
+        buffer.extend_from_slice(frame);
+        Ok(())
+    }
+}
+
+impl Encoder<Vec<u8>> for LengthDelimitedCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Vec<u8>, buffer: &mut Vec<u8>) -> io::Result<()> {
+        self.encode(frame.as_slice(), buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::net::UnixStream, time::Duration};
+
+    use super::*;
+    use crate::EventLoop;
+
+    #[test]
+    fn lines_codec() {
+        let mut codec = LinesCodec::with_max_length(8);
+        let mut buffer = b"one\r\ntwo\nthr".to_vec();
+        assert_eq!(codec.decode(&mut buffer).unwrap().as_deref(), Some("one"));
+        assert_eq!(codec.decode(&mut buffer).unwrap().as_deref(), Some("two"));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+        assert_eq!(
+            codec.decode_eof(&mut buffer).unwrap().as_deref(),
+            Some("thr")
+        );
+        assert!(buffer.is_empty());
+
+        buffer.extend_from_slice(b"much too long");
+        assert!(codec.decode(&mut buffer).is_err());
+
+        // A line received in chunks is only searched once.
+        let mut codec = LinesCodec::new();
+        let mut buffer = Vec::new();
+        for chunk in [&b"fi"[..], b"rst", b" line"] {
+            buffer.extend_from_slice(chunk);
+            assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+            assert_eq!(codec.next_index, buffer.len());
+        }
+        buffer.extend_from_slice(b"\nsecond\n");
+        assert_eq!(
+            codec.decode(&mut buffer).unwrap().as_deref(),
+            Some("first line")
+        );
+        assert_eq!(codec.next_index, 0);
+        assert_eq!(
+            codec.decode(&mut buffer).unwrap().as_deref(),
+            Some("second")
+        );
+
+        let mut buffer = Vec::new();
+        codec.encode("four", &mut buffer).unwrap();
+        codec.encode(String::from("five"), &mut buffer).unwrap();
+        assert_eq!(buffer, b"four\nfive\n");
+    }
+
+    #[test]
+    fn length_delimited_codec() {
+        let mut codec = LengthDelimitedCodec::with_max_frame_length(16);
+        let mut buffer = Vec::new();
+        codec.encode(&b"hello"[..], &mut buffer).unwrap();
+        codec.encode(Vec::new(), &mut buffer).unwrap();
+        assert_eq!(&buffer[..4], &[0, 0, 0, 5]);
+        assert!(codec.encode(vec![0; 17], &mut buffer).is_err());
+
+        let mut partial = buffer[..6].to_vec();
+        assert_eq!(codec.decode(&mut partial).unwrap(), None);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(b"hello".to_vec()));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(Vec::new()));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+
+        let mut buffer = vec![0, 0, 0, 17];
+        assert!(codec.decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn echo_lines() {
+        let mut event_loop = EventLoop::<Vec<String>>::try_new().unwrap();
+        let (local, mut remote) = UnixStream::pair().unwrap();
+        let framed = Framed::new(local, LinesCodec::new()).unwrap();
+        event_loop
+            .handle()
+            .insert_source(framed, |event, queue, lines| match event {
+                Event::Message(line) => {
+                    queue.send(line.to_uppercase()).unwrap();
+                    lines.push(line);
+                }
+                Event::Closed(error) => {
+                    assert!(error.is_none());
+                    lines.push("closed".into());
+                }
+            })
+            .unwrap();
+
+        remote.write_all(b"hello\nwor").unwrap();
+        let mut lines = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut lines)
+            .unwrap();
+        assert_eq!(lines, ["hello"]);
+
+        remote.write_all(b"ld\n").unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut lines)
+            .unwrap();
+        assert_eq!(lines, ["hello", "world"]);
+
+        let mut answer = [0u8; 12];
+        remote.read_exact(&mut answer).unwrap();
+        assert_eq!(&answer, b"HELLO\nWORLD\n");
+
+        drop(remote);
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut lines)
+            .unwrap();
+        assert_eq!(lines, ["hello", "world", "closed"]);
+    }
+
+    #[test]
+    fn bounded_reads() {
+        let mut event_loop = EventLoop::<Vec<String>>::try_new().unwrap();
+        let (local, mut remote) = UnixStream::pair().unwrap();
+        let mut framed = Framed::new(local, LinesCodec::new()).unwrap();
+        framed.set_max_buffer_size(8);
+        event_loop
+            .handle()
+            .insert_source(framed, |event, _, lines| match event {
+                Event::Message(line) => lines.push(line),
+                Event::Closed(error) => lines.push(format!("closed: {}", error.unwrap())),
+            })
+            .unwrap();
+
+        // Only a full buffer is read at once.
+        remote.write_all(b"one\ntwo\nsix\nten\n").unwrap();
+        let mut lines = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut lines)
+            .unwrap();
+        assert_eq!(lines, ["one", "two"]);
+        event_loop.dispatch(Duration::ZERO, &mut lines).unwrap();
+        assert_eq!(lines, ["one", "two", "six", "ten"]);
+
+        // A message that does not fit in the buffer closes the source.
+        remote.write_all(b"much too long\n").unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut lines)
+            .unwrap();
+        assert_eq!(
+            lines,
+            [
+                "one",
+                "two",
+                "six",
+                "ten",
+                "closed: message larger than the maximum buffer size"
+            ]
+        );
+    }
+
+    #[test]
+    fn large_frames() {
+        let mut event_loop = EventLoop::<Option<usize>>::try_new().unwrap();
+        let (local, mut remote) = UnixStream::pair().unwrap();
+        let mut framed = Framed::new(local, LengthDelimitedCodec::new()).unwrap();
+        // More than the socket buffer can hold, so that the source has to wait for it to be
+        // writable again.
+        let frame = vec![42u8; 4 * 1024 * 1024];
+        framed.queue().send(frame.as_slice()).unwrap();
+        event_loop
+            .handle()
+            .insert_source(framed, |event, _, received| match event {
+                Event::Message(frame) => *received = Some(frame.len()),
+                Event::Closed(error) => panic!("unexpected close: {:?}", error),
+            })
+            .unwrap();
+
+        remote.set_nonblocking(true).unwrap();
+        let mut codec = LengthDelimitedCodec::new();
+        let mut buffer = Vec::new();
+        let mut decoded = None;
+        let mut received = None;
+        for _ in 0..1000 {
+            event_loop
+                .dispatch(Duration::from_millis(10), &mut received)
+                .unwrap();
+            read_available(&mut remote, &mut buffer, usize::MAX).unwrap();
+            if let Some(frame) = codec.decode(&mut buffer).unwrap() {
+                decoded = Some(frame);
+                break;
+            }
+        }
+        assert_eq!(decoded, Some(frame));
+
+        // Frames split across several reads are reassembled.
+        remote.set_nonblocking(false).unwrap();
+        let mut data = Vec::new();
+        codec.encode(vec![1u8; 100], &mut data).unwrap();
+        remote.write_all(&data[..50]).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(10), &mut received)
+            .unwrap();
+        assert_eq!(received, None);
+        remote.write_all(&data[50..]).unwrap();
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut received)
+            .unwrap();
+        assert_eq!(received, Some(100));
+    }
+}
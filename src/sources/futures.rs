@@ -14,21 +14,30 @@
 //! by choosing `T = ()` and letting futures handle the forwarding of their return values
 //! (if any) by their own means.
 //!
+//! Futures can also be sent with [`Scheduler::spawn()`], which returns a [`JoinHandle`] to their
+//! return value instead of yielding it into your callback. This handle is itself a future, and
+//! can be used to abort the spawned future.
+//!
+//! The scheduler is bound to the thread of the event loop. To send futures from other threads,
+//! use the [`SendScheduler`] given by [`Scheduler::send_scheduler()`].
+//!
 //! **Note:** The futures must have their own means of being woken up, as this executor is,
 //! by itself, not I/O aware. See [`LoopHandle::adapt_io`](crate::LoopHandle#method.adapt_io)
 //! for that, or you can use some other mechanism if you prefer.
 
-use async_task::{Builder, Runnable};
+use async_task::{Builder, FallibleTask, Runnable, Task};
 use slab::Slab;
 use std::{
     cell::RefCell,
     future::Future,
+    num::NonZeroUsize,
+    pin::Pin,
     rc::Rc,
     sync::{
         atomic::{AtomicBool, Ordering},
         mpsc, Arc, Mutex,
     },
-    task::Waker,
+    task::{Context, Poll as TaskPoll, Waker},
 };
 
 use crate::{
@@ -40,6 +49,9 @@ use crate::{
     Poll, PostAction, Readiness, Token, TokenFactory,
 };
 
+/// The number of runnables processed per dispatch when the event loop sets no budget.
+const DEFAULT_BUDGET: usize = 1024;
+
 /// A future executor as an event source
 #[derive(Debug)]
 pub struct Executor<T> {
@@ -48,6 +60,12 @@ pub struct Executor<T> {
 
     /// Notifies us when the executor is woken up.
     ping: PingSource,
+
+    /// The incoming queue of futures sent from other threads.
+    remote: mpsc::Receiver<RemoteJob<T>>,
+
+    /// The maximum number of runnables to run per dispatch, if set by the event loop.
+    budget: Option<NonZeroUsize>,
 }
 
 /// A scheduler to send futures to an executor
@@ -66,6 +84,9 @@ struct State<T> {
     /// The sender corresponding to `incoming`.
     sender: Arc<Sender>,
 
+    /// The sender corresponding to the `remote` queue of the executor.
+    remote: mpsc::Sender<RemoteJob<T>>,
+
     /// The list of currently active tasks.
     ///
     /// This is set to `None` when the executor is destroyed.
@@ -89,6 +110,11 @@ struct Sender {
     notified: AtomicBool,
 }
 
+/// A future sent to the executor from another thread.
+///
+/// Building the future is deferred to the thread of the executor, so it does not need to be `Send`.
+type RemoteJob<T> = Box<dyn FnOnce(&Scheduler<T>) + Send>;
+
 /// An active future or its result.
 #[derive(Debug)]
 enum Active<T> {
@@ -110,34 +136,77 @@ impl<T> Active<T> {
 impl<T> Scheduler<T> {
     /// Sends the given future to the executor associated to this scheduler
     ///
+    /// The value the future evaluates to is given to the callback of the executor.
+    ///
     /// Returns an error if the the executor not longer exists.
     pub fn schedule<Fut: 'static>(&self, future: Fut) -> Result<(), ExecutorDestroyed>
     where
         Fut: Future<Output = T>,
         T: 'static,
     {
-        /// Store this future's result in the executor.
-        struct StoreOnDrop<'a, T> {
-            index: usize,
-            value: Option<T>,
-            state: &'a State<T>,
-        }
+        let state = self.state.clone();
+        let task = self.spawn_task(move |index| async move {
+            let mut guard = StoreOnDrop {
+                index,
+                value: None,
+                state: &state,
+            };
+
+            // Get the value of the future.
+            let value = future.await;
+
+            // Store it in the executor.
+            guard.value = Some(value);
+        })?;
+
+        // Detach the task so it isn't cancellable.
+        task.detach();
 
-        impl<T> Drop for StoreOnDrop<'_, T> {
-            fn drop(&mut self) {
-                let mut active_tasks = self.state.active_tasks.borrow_mut();
-                if let Some(active_tasks) = active_tasks.as_mut() {
-                    if let Some(value) = self.value.take() {
-                        active_tasks[self.index] = Active::Finished(value);
-                    } else {
-                        // The future was dropped before it finished.
-                        // Remove it from the active list.
-                        active_tasks.remove(self.index);
-                    }
-                }
-            }
-        }
+        Ok(())
+    }
+
+    /// Sends the given future to the executor associated to this scheduler, and returns a handle
+    /// to its result
+    ///
+    /// Unlike with [`schedule()`](Self::schedule), the value the future evaluates to is not given
+    /// to the callback of the executor, but is the output of the returned [`JoinHandle`]. The
+    /// future can evaluate to any type.
+    ///
+    /// Returns an error if the the executor not longer exists.
+    pub fn spawn<Fut, R>(&self, future: Fut) -> Result<JoinHandle<R>, ExecutorDestroyed>
+    where
+        Fut: Future<Output = R> + 'static,
+        R: 'static,
+        T: 'static,
+    {
+        let state = self.state.clone();
+        let task = self.spawn_task(move |index| async move {
+            // Nothing is stored in the executor, this only removes the task from it.
+            let _guard = StoreOnDrop {
+                index,
+                value: None,
+                state: &state,
+            };
+
+            future.await
+        })?;
+
+        Ok(JoinHandle {
+            task: RefCell::new(Some(task.fallible())),
+            cancel_on_drop: false,
+        })
+    }
 
+    /// Spawns the future built by `make_future` from the index of its task in the executor.
+    fn spawn_task<F, Fut>(
+        &self,
+        make_future: F,
+    ) -> Result<Task<Fut::Output, usize>, ExecutorDestroyed>
+    where
+        F: FnOnce(usize) -> Fut,
+        Fut: Future + 'static,
+        Fut::Output: 'static,
+    {
         fn assert_send_and_sync<T: Send + Sync>(_: &T) {}
 
         let mut active_guard = self.state.active_tasks.borrow_mut();
@@ -145,22 +214,7 @@ impl<T> Scheduler<T> {
 
         // Wrap the future in another future that polls it and stores the result.
         let index = active_tasks.vacant_key();
-        let future = {
-            let state = self.state.clone();
-            async move {
-                let mut guard = StoreOnDrop {
-                    index,
-                    value: None,
-                    state: &state,
-                };
-
-                // Get the value of the future.
-                let value = future.await;
-
-                // Store it in the executor.
-                guard.value = Some(value);
-            }
-        };
+        let future = make_future(index);
 
         // A schedule function that inserts the runnable into the incoming queue.
         let schedule = {
@@ -179,14 +233,208 @@ impl<T> Scheduler<T> {
         active_tasks.insert(Active::Future(runnable.waker()));
         drop(active_guard);
 
-        // Schedule the runnable and detach the task so it isn't cancellable.
+        // Schedule the runnable.
         runnable.schedule();
-        task.detach();
+
+        Ok(task)
+    }
+}
+
+/// A thread-safe scheduler to send futures to an executor
+///
+/// Unlike a [`Scheduler`], this scheduler is `Send` and `Sync`, so it can be used from other
+/// threads than the one of the event loop, for example from a thread pool. It is obtained with
+/// [`Scheduler::send_scheduler()`].
+///
+/// The futures it sends are inserted into the executor the next time it is dispatched, and their
+/// values are given to the callback of the executor.
+pub struct SendScheduler<T> {
+    /// The sender corresponding to the `remote` queue of the executor.
+    ///
+    /// `mpsc::Sender` is `!Sync`, wrapping it in a `Mutex` makes it `Sync`.
+    remote: Arc<Mutex<mpsc::Sender<RemoteJob<T>>>>,
+
+    /// Used to wake up the executor.
+    sender: Arc<Sender>,
+}
+
+impl<T> Scheduler<T> {
+    /// Create a thread-safe scheduler sending futures to the same executor as this one
+    pub fn send_scheduler(&self) -> SendScheduler<T> {
+        SendScheduler {
+            remote: Arc::new(Mutex::new(self.state.remote.clone())),
+            sender: self.state.sender.clone(),
+        }
+    }
+}
+
+impl<T> SendScheduler<T> {
+    /// Sends the given future to the executor associated to this scheduler
+    ///
+    /// Returns an error if the the executor not longer exists. Futures sent right before the
+    /// executor is destroyed are dropped without being polled.
+    pub fn schedule<Fut>(&self, future: Fut) -> Result<(), ExecutorDestroyed>
+    where
+        Fut: Future<Output = T> + Send + 'static,
+        T: 'static,
+    {
+        self.schedule_with(move || future)
+    }
+
+    /// Sends a future built by the given closure to the executor associated to this scheduler
+    ///
+    /// The closure is called on the thread of the executor, so the future it returns does not need
+    /// to be `Send`.
+    ///
+    /// Returns an error if the the executor not longer exists. Futures sent right before the
+    /// executor is destroyed are dropped without being built.
+    pub fn schedule_with<F, Fut>(&self, make_future: F) -> Result<(), ExecutorDestroyed>
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = T> + 'static,
+        T: 'static,
+    {
+        let job: RemoteJob<T> = Box::new(move |scheduler| {
+            // The executor is alive while it builds the futures.
+            scheduler.schedule(make_future()).ok();
+        });
+
+        // All we do with the lock is call `send`, so it's safe to ignore the mutex poison.
+        self.remote
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .send(job)
+            .map_err(|_| ExecutorDestroyed)?;
+
+        self.sender.wake();
 
         Ok(())
     }
 }
 
+impl<T> Clone for SendScheduler<T> {
+    fn clone(&self) -> Self {
+        SendScheduler {
+            remote: self.remote.clone(),
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for SendScheduler<T> {
+    #[cfg_attr(feature = "nightly_coverage", no_coverage)]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SendScheduler { ... }")
+    }
+}
+
+/// Store the result of a future in the executor.
+struct StoreOnDrop<'a, T> {
+    index: usize,
+    value: Option<T>,
+    state: &'a State<T>,
+}
+
+impl<T> Drop for StoreOnDrop<'_, T> {
+    fn drop(&mut self) {
+        let mut active_tasks = self.state.active_tasks.borrow_mut();
+        if let Some(active_tasks) = active_tasks.as_mut() {
+            if let Some(value) = self.value.take() {
+                active_tasks[self.index] = Active::Finished(value);
+            } else {
+                // The future was dropped before it finished, or its result does not go to the
+                // executor. Remove it from the active list.
+                active_tasks.remove(self.index);
+            }
+        }
+    }
+}
+
+/// A handle to a future spawned with [`Scheduler::spawn()`]
+///
+/// This handle is itself a future, evaluating to the value of the spawned future once it
+/// finishes. It can also be used to abort the spawned future.
+///
+/// By default, dropping the handle detaches the spawned future, which keeps running in the
+/// executor. See [`set_cancel_on_drop()`](Self::set_cancel_on_drop) to change this.
+pub struct JoinHandle<R> {
+    /// The spawned task, `None` once it has been aborted.
+    task: RefCell<Option<FallibleTask<R, usize>>>,
+
+    /// Whether to cancel the task when the handle is dropped.
+    cancel_on_drop: bool,
+}
+
+impl<R> JoinHandle<R> {
+    /// Abort the spawned future
+    ///
+    /// The future is dropped without being polled again, the next time the executor is
+    /// dispatched. If it had not finished yet, the handle then evaluates to
+    /// [`JoinError::Aborted`].
+    pub fn abort(&self) {
+        self.task.borrow_mut().take();
+    }
+
+    /// Whether the spawned future has finished, or has been aborted
+    pub fn is_finished(&self) -> bool {
+        self.task
+            .borrow()
+            .as_ref()
+            .map_or(true, |task| task.is_finished())
+    }
+
+    /// Set whether dropping this handle aborts the spawned future
+    ///
+    /// If `false`, the default, the spawned future is detached and keeps running in the executor
+    /// when this handle is dropped.
+    pub fn set_cancel_on_drop(&mut self, cancel: bool) {
+        self.cancel_on_drop = cancel;
+    }
+
+    /// Drop this handle, letting the spawned future run to completion in the executor
+    ///
+    /// This is what dropping the handle does unless
+    /// [`set_cancel_on_drop()`](Self::set_cancel_on_drop) was used.
+    pub fn detach(mut self) {
+        if let Some(task) = self.task.get_mut().take() {
+            task.detach();
+        }
+    }
+}
+
+impl<R> Future for JoinHandle<R> {
+    type Output = Result<R, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> TaskPoll<Self::Output> {
+        match self.get_mut().task.get_mut() {
+            Some(task) => Pin::new(task)
+                .poll(cx)
+                .map(|value| value.ok_or(JoinError::ExecutorDestroyed)),
+            None => TaskPoll::Ready(Err(JoinError::Aborted)),
+        }
+    }
+}
+
+impl<R> Drop for JoinHandle<R> {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.get_mut().take() {
+            if !self.cancel_on_drop {
+                task.detach();
+            }
+        }
+    }
+}
+
+impl<R> std::fmt::Debug for JoinHandle<R> {
+    #[cfg_attr(feature = "nightly_coverage", no_coverage)]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("finished", &self.is_finished())
+            .field("cancel_on_drop", &self.cancel_on_drop)
+            .finish_non_exhaustive()
+    }
+}
+
 impl Sender {
     /// Send a runnable to the executor.
     fn send(&self, runnable: Runnable<usize>) {
@@ -211,6 +459,11 @@ impl Sender {
             unreachable!("Attempted to send runnable to a stopped executor");
         }
 
+        self.wake();
+    }
+
+    /// Wake up the executor, if it is not already.
+    fn wake(&self) {
         // If the executor is already awake, don't bother waking it up again.
         if self.notified.swap(true, Ordering::SeqCst) {
             return;
@@ -252,12 +505,25 @@ impl<T> Drop for Executor<T> {
 #[error("the executor was destroyed")]
 pub struct ExecutorDestroyed;
 
+/// Error generated by a [`JoinHandle`] whose future did not finish
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JoinError {
+    /// The future was aborted through its [`JoinHandle`]
+    #[error("the task was aborted")]
+    Aborted,
+
+    /// The executor was destroyed before the future finished
+    #[error("the executor was destroyed")]
+    ExecutorDestroyed,
+}
+
 /// Create a new executor, and its associated scheduler
 ///
 /// May fail due to OS errors preventing calloop to setup its internal pipes (if your
 /// process has reatched its file descriptor limit for example).
 pub fn executor<T>() -> crate::Result<(Executor<T>, Scheduler<T>)> {
     let (sender, incoming) = mpsc::channel();
+    let (remote_sender, remote) = mpsc::channel();
     let (wake_up, ping) = make_ping()?;
 
     let state = Rc::new(State {
@@ -268,12 +534,15 @@ pub fn executor<T>() -> crate::Result<(Executor<T>, Scheduler<T>)> {
             wake_up,
             notified: AtomicBool::new(false),
         }),
+        remote: remote_sender,
     });
 
     Ok((
         Executor {
             state: state.clone(),
             ping,
+            remote,
+            budget: None,
         },
         Scheduler { state },
     ))
@@ -302,8 +571,24 @@ impl<T> EventSource for Executor<T> {
         let clear_readiness = {
             let mut clear_readiness = false;
 
+            // Build the futures sent from other threads, which schedules their runnables.
+            let scheduler = Scheduler {
+                state: state.clone(),
+            };
+            let mut remote_drained = false;
+            for _ in 0..self.budget.map_or(DEFAULT_BUDGET, NonZeroUsize::get) {
+                match self.remote.try_recv() {
+                    Ok(job) => job(&scheduler),
+                    Err(_) => {
+                        remote_drained = true;
+                        break;
+                    }
+                }
+            }
+
             // Process runnables, but not too many at a time; better to move onto the next event quickly!
-            for _ in 0..1024 {
+            // If we stop early, the ping is not drained and the event loop will wake us up again.
+            for _ in 0..self.budget.map_or(DEFAULT_BUDGET, NonZeroUsize::get) {
                 let runnable = match state.incoming.try_recv() {
                     Ok(runnable) => runnable,
                     Err(_) => {
@@ -334,7 +619,7 @@ impl<T> EventSource for Executor<T> {
                 }
             }
 
-            clear_readiness
+            clear_readiness && remote_drained
         };
 
         // Clear the readiness of the ping source if there are no more runnables.
@@ -365,6 +650,10 @@ impl<T> EventSource for Executor<T> {
         self.ping.unregister(poll)?;
         Ok(())
     }
+
+    fn set_event_budget(&mut self, budget: Option<NonZeroUsize>) {
+        self.budget = budget;
+    }
 }
 
 /// An error arising from processing events in an async executor event source.
@@ -381,6 +670,10 @@ pub enum ExecutorError {
 
 #[cfg(test)]
 mod tests {
+    use std::cell::Cell;
+
+    use futures::FutureExt;
+
     use super::*;
 
     #[test]
@@ -417,4 +710,205 @@ mod tests {
         // the future has run
         assert_eq!(got, 42);
     }
+
+    #[test]
+    fn spawn() {
+        let mut event_loop = crate::EventLoop::<u32>::try_new().unwrap();
+
+        let handle = event_loop.handle();
+
+        let (exec, sched) = executor::<u32>().unwrap();
+
+        handle
+            .insert_source(exec, move |ret, &mut (), got| {
+                *got = ret;
+            })
+            .unwrap();
+
+        let mut got = 0;
+
+        let join = sched.spawn(async { "hello" }).unwrap();
+        assert!(!join.is_finished());
+
+        event_loop
+            .dispatch(Some(::std::time::Duration::ZERO), &mut got)
+            .unwrap();
+
+        // the result goes to the handle and not the executor callback
+        assert!(join.is_finished());
+        assert_eq!(join.now_or_never(), Some(Ok("hello")));
+        assert_eq!(got, 0);
+
+        // the handle can be awaited from another future of the executor
+        let join = sched.spawn(async { 21 }).unwrap();
+        sched
+            .schedule(async move { join.await.unwrap() * 2 })
+            .unwrap();
+
+        event_loop
+            .dispatch(Some(::std::time::Duration::ZERO), &mut got)
+            .unwrap();
+
+        assert_eq!(got, 42);
+    }
+
+    #[test]
+    fn spawn_abort() {
+        let mut event_loop = crate::EventLoop::<()>::try_new().unwrap();
+
+        let handle = event_loop.handle();
+
+        let (exec, sched) = executor::<()>().unwrap();
+
+        handle
+            .insert_source(exec, move |(), &mut (), _| {})
+            .unwrap();
+
+        let dropped = Rc::new(Cell::new(false));
+        let join = sched
+            .spawn({
+                let guard = SetOnDrop(dropped.clone());
+                async move {
+                    let _guard = guard;
+                    futures::future::pending::<()>().await
+                }
+            })
+            .unwrap();
+
+        event_loop
+            .dispatch(Some(::std::time::Duration::ZERO), &mut ())
+            .unwrap();
+        assert!(!join.is_finished());
+
+        join.abort();
+        assert!(join.is_finished());
+
+        // the future is dropped by the executor
+        event_loop
+            .dispatch(Some(::std::time::Duration::ZERO), &mut ())
+            .unwrap();
+        assert!(dropped.get());
+
+        assert_eq!(join.now_or_never(), Some(Err(JoinError::Aborted)));
+    }
+
+    #[test]
+    fn spawn_drop_handle() {
+        let mut event_loop = crate::EventLoop::<()>::try_new().unwrap();
+
+        let handle = event_loop.handle();
+
+        let (exec, sched) = executor::<()>().unwrap();
+
+        handle
+            .insert_source(exec, move |(), &mut (), _| {})
+            .unwrap();
+
+        let finished = Rc::new(Cell::new(0));
+        let (tx1, rx1) = futures::channel::oneshot::channel::<()>();
+        let (tx2, rx2) = futures::channel::oneshot::channel::<()>();
+
+        // dropping the handle detaches the future by default
+        drop(
+            sched
+                .spawn({
+                    let finished = finished.clone();
+                    async move {
+                        rx1.await.unwrap();
+                        finished.set(finished.get() + 1);
+                    }
+                })
+                .unwrap(),
+        );
+
+        // unless configured otherwise
+        let mut join = sched
+            .spawn({
+                let finished = finished.clone();
+                async move {
+                    let _ = rx2.await;
+                    finished.set(finished.get() + 1);
+                }
+            })
+            .unwrap();
+        join.set_cancel_on_drop(true);
+        drop(join);
+
+        event_loop
+            .dispatch(Some(::std::time::Duration::ZERO), &mut ())
+            .unwrap();
+
+        tx1.send(()).unwrap();
+        assert!(tx2.send(()).is_err());
+
+        event_loop
+            .dispatch(Some(::std::time::Duration::ZERO), &mut ())
+            .unwrap();
+
+        assert_eq!(finished.get(), 1);
+    }
+
+    #[test]
+    fn spawn_executor_destroyed() {
+        let (exec, sched) = executor::<()>().unwrap();
+
+        let join = sched.spawn(futures::future::pending::<()>()).unwrap();
+        drop(exec);
+
+        assert!(join.is_finished());
+        assert_eq!(join.now_or_never(), Some(Err(JoinError::ExecutorDestroyed)));
+        assert!(sched.spawn(async {}).is_err());
+    }
+
+    #[test]
+    fn send_scheduler() {
+        fn assert_send_and_sync<T: Send + Sync>(_: &T) {}
+
+        let mut event_loop = crate::EventLoop::<Vec<u32>>::try_new().unwrap();
+
+        let handle = event_loop.handle();
+
+        let (exec, sched) = executor::<u32>().unwrap();
+
+        handle
+            .insert_source(exec, move |ret, &mut (), got| {
+                got.push(ret);
+            })
+            .unwrap();
+
+        let send_sched = sched.send_scheduler();
+        assert_send_and_sync(&send_sched);
+
+        let thread = std::thread::spawn(move || {
+            send_sched.schedule(async { 1 }).unwrap();
+            // the future built on the loop thread does not need to be Send
+            send_sched
+                .schedule_with(|| {
+                    let value = Rc::new(2);
+                    async move { *value }
+                })
+                .unwrap();
+        });
+        thread.join().unwrap();
+
+        let mut got = Vec::new();
+        event_loop
+            .dispatch(Some(::std::time::Duration::from_secs(1)), &mut got)
+            .unwrap();
+
+        assert_eq!(got, [1, 2]);
+
+        let send_sched = sched.send_scheduler();
+        drop(handle);
+        drop(event_loop);
+        assert!(send_sched.schedule(async { 3 }).is_err());
+    }
+
+    struct SetOnDrop(Rc<Cell<bool>>);
+
+    impl Drop for SetOnDrop {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
 }
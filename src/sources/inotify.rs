@@ -0,0 +1,770 @@
+//! Event source for watching the filesystem
+//!
+//! Only available on Linux and Android.
+//!
+//! The [`FileWatcher`] reports changes to watched files and directories, using `inotify` under the
+//! hood. Directories can be watched recursively, in which case the watcher follows the
+//! subdirectories that are created in, or moved into, the watched tree.
+//!
+//! ```no_run
+//! use calloop::{inotify::FileWatcher, EventLoop};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut event_loop = EventLoop::<()>::try_new()?;
+//!
+//! let mut watcher = FileWatcher::new()?;
+//! watcher.add_recursive_watch("/etc/my-service")?;
+//!
+//! event_loop.handle().insert_source(watcher, |event, _, _| {
+//!     println!("{:?}", event);
+//! })?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Like `inotify` itself, the watcher reports the changes it sees, but does not scan directories
+//! to find out about the changes it missed: for example, files created in a new subdirectory
+//! before the watcher had the time to watch it are not reported, and after an
+//! [`Event::Overflow`], some events have been lost.
+
+use std::{
+    cell::Cell,
+    collections::{HashMap, VecDeque},
+    io,
+    num::NonZeroUsize,
+    os::unix::io::{AsRawFd, FromRawFd},
+    path::{Path, PathBuf},
+};
+
+use io_lifetimes::OwnedFd;
+use nix::{
+    errno::Errno,
+    sys::inotify::{AddWatchFlags, InitFlags, Inotify, InotifyEvent, WatchDescriptor},
+};
+
+use super::generic::Generic;
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// The changes reported for the watched files and directories.
+const WATCH_FLAGS: AddWatchFlags = AddWatchFlags::from_bits_truncate(
+    AddWatchFlags::IN_CREATE.bits()
+        | AddWatchFlags::IN_MODIFY.bits()
+        | AddWatchFlags::IN_DELETE.bits()
+        | AddWatchFlags::IN_MOVED_FROM.bits()
+        | AddWatchFlags::IN_MOVED_TO.bits(),
+);
+
+/// Identifies a watch of a [`FileWatcher`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WatchId(u64);
+
+/// An event generated by the [`FileWatcher`]
+///
+/// Paths are the path of the watch they come from, joined with the name of the file or
+/// directory the event is about, if it is in a watched directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// A file or directory was created
+    Create {
+        /// The watch of the parent directory
+        watch: WatchId,
+        /// The path of the new file or directory
+        path: PathBuf,
+    },
+    /// A file was modified
+    Modify {
+        /// The watch of the file, or of its parent directory
+        watch: WatchId,
+        /// The path of the file
+        path: PathBuf,
+    },
+    /// A file or directory was deleted
+    Delete {
+        /// The watch of the parent directory
+        watch: WatchId,
+        /// The path of the deleted file or directory
+        path: PathBuf,
+    },
+    /// A file or directory was moved from a watched directory to another one
+    ///
+    /// The two halves of the move are paired using the cookie `inotify` provides.
+    Move {
+        /// The watch of the destination directory
+        watch: WatchId,
+        /// The previous path of the file or directory
+        from: PathBuf,
+        /// The new path of the file or directory
+        to: PathBuf,
+    },
+    /// A file or directory was moved out of the watched directories
+    MoveOut {
+        /// The watch of the directory it was moved out of
+        watch: WatchId,
+        /// The previous path of the file or directory
+        path: PathBuf,
+    },
+    /// A file or directory was moved into a watched directory from an unwatched one
+    MoveIn {
+        /// The watch of the directory it was moved into
+        watch: WatchId,
+        /// The new path of the file or directory
+        path: PathBuf,
+    },
+    /// The watched file or directory was deleted, or its filesystem was unmounted
+    ///
+    /// The watch does not exist anymore.
+    WatchRemoved {
+        /// The removed watch
+        watch: WatchId,
+    },
+    /// The queue of events overflowed, and some events were lost
+    Overflow,
+}
+
+/// An event source watching files and directories
+#[derive(Debug)]
+pub struct FileWatcher {
+    fd: Generic<OwnedFd>,
+    /// The watched files and directories, by their watch descriptor.
+    descriptors: HashMap<WatchDescriptor, Descriptor>,
+    /// The watches added by the user.
+    watches: HashMap<WatchId, Watch>,
+    next_id: u64,
+    /// The first half of a move, waiting for its second half.
+    pending_move: Option<PendingMove>,
+    /// The events read from inotify but not handled yet, because the budget was exhausted.
+    queued: VecDeque<InotifyEvent>,
+    budget: Option<NonZeroUsize>,
+    // Events were left in the queue or in inotify because the budget was exhausted.
+    pending: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Descriptor {
+    watch: WatchId,
+    path: PathBuf,
+}
+
+#[derive(Debug)]
+struct Watch {
+    root: WatchDescriptor,
+    recursive: bool,
+}
+
+#[derive(Debug)]
+struct PendingMove {
+    cookie: u32,
+    watch: WatchId,
+    path: PathBuf,
+    is_dir: bool,
+}
+
+impl FileWatcher {
+    /// Create a new file watcher, with no watches
+    pub fn new() -> crate::Result<FileWatcher> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        let fd = unsafe { OwnedFd::from_raw_fd(inotify.as_raw_fd()) };
+
+        Ok(FileWatcher {
+            fd: Generic::new(fd, Interest::READ, Mode::Level),
+            descriptors: HashMap::new(),
+            watches: HashMap::new(),
+            next_id: 0,
+            pending_move: None,
+            queued: VecDeque::new(),
+            budget: None,
+            pending: false,
+        })
+    }
+
+    /// Watch a file, or the content of a directory
+    ///
+    /// Fails if the file or directory is already watched by this watcher.
+    pub fn add_watch(&mut self, path: impl AsRef<Path>) -> crate::Result<WatchId> {
+        self.add_watch_inner(path.as_ref(), false)
+    }
+
+    /// Watch the content of a directory and of its subdirectories
+    ///
+    /// Subdirectories already watched by this watcher are left to their current watch.
+    ///
+    /// Fails if the directory is already watched by this watcher.
+    pub fn add_recursive_watch(&mut self, path: impl AsRef<Path>) -> crate::Result<WatchId> {
+        self.add_watch_inner(path.as_ref(), true)
+    }
+
+    fn add_watch_inner(&mut self, path: &Path, recursive: bool) -> crate::Result<WatchId> {
+        let mut flags = WATCH_FLAGS;
+        if recursive {
+            flags |= AddWatchFlags::IN_ONLYDIR;
+        }
+        let wd = self.inotify().add_watch(path, flags)?;
+        if self.descriptors.contains_key(&wd) {
+            return Err(
+                io::Error::new(io::ErrorKind::AlreadyExists, "path is already watched").into(),
+            );
+        }
+
+        let watch = WatchId(self.next_id);
+        self.next_id += 1;
+        self.descriptors.insert(
+            wd,
+            Descriptor {
+                watch,
+                path: path.to_owned(),
+            },
+        );
+        self.watches.insert(
+            watch,
+            Watch {
+                root: wd,
+                recursive,
+            },
+        );
+
+        if recursive {
+            if let Err(e) = self.watch_subdirectories(watch, path) {
+                let _ = self.remove_watch(watch);
+                return Err(e.into());
+            }
+        }
+
+        Ok(watch)
+    }
+
+    /// Stop watching a file or directory
+    ///
+    /// No event is generated for this watch afterwards.
+    pub fn remove_watch(&mut self, watch: WatchId) -> crate::Result<()> {
+        if self.watches.remove(&watch).is_none() {
+            return Ok(());
+        }
+
+        let inotify = self.inotify();
+        let mut result = Ok(());
+        self.descriptors.retain(|wd, descriptor| {
+            if descriptor.watch != watch {
+                return true;
+            }
+            match inotify.rm_watch(*wd) {
+                // The watch was already removed by the system.
+                Ok(()) | Err(Errno::EINVAL) => {}
+                Err(e) => result = Err(e.into()),
+            }
+            false
+        });
+        if matches!(&self.pending_move, Some(pending) if pending.watch == watch) {
+            self.pending_move = None;
+        }
+
+        result
+    }
+
+    /// Get the path a watch was created with
+    pub fn watch_path(&self, watch: WatchId) -> Option<&Path> {
+        let root = self.watches.get(&watch)?.root;
+        Some(&self.descriptors[&root].path)
+    }
+
+    fn inotify(&self) -> Inotify {
+        unsafe { Inotify::from_raw_fd(self.fd.file.as_raw_fd()) }
+    }
+
+    fn is_recursive(&self, watch: WatchId) -> bool {
+        self.watches
+            .get(&watch)
+            .map_or(false, |watch| watch.recursive)
+    }
+
+    /// Watch the subdirectories of a directory of a recursive watch.
+    fn watch_subdirectories(&mut self, watch: WatchId, dir: &Path) -> io::Result<()> {
+        let inotify = self.inotify();
+        let mut stack = vec![dir.to_owned()];
+        while let Some(dir) = stack.pop() {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                // The directory was removed in the meantime.
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for entry in entries {
+                let entry = entry?;
+                if !entry.file_type()?.is_dir() {
+                    continue;
+                }
+                let path = entry.path();
+                let wd = match inotify.add_watch(&path, WATCH_FLAGS | AddWatchFlags::IN_ONLYDIR) {
+                    Ok(wd) => wd,
+                    Err(Errno::ENOENT) | Err(Errno::ENOTDIR) => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                if self.descriptors.contains_key(&wd) {
+                    // Already watched, possibly by another watch.
+                    continue;
+                }
+                self.descriptors.insert(
+                    wd,
+                    Descriptor {
+                        watch,
+                        path: path.clone(),
+                    },
+                );
+                stack.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// Follow a directory that appeared in a recursive watch.
+    fn watch_new_directory(&mut self, watch: WatchId, path: &Path) {
+        let wd = match self
+            .inotify()
+            .add_watch(path, WATCH_FLAGS | AddWatchFlags::IN_ONLYDIR)
+        {
+            Ok(wd) => wd,
+            Err(Errno::ENOENT) | Err(Errno::ENOTDIR) => return,
+            Err(e) => {
+                log::warn!("[calloop] Failed to watch directory {:?}: {}", path, e);
+                return;
+            }
+        };
+        if self.descriptors.contains_key(&wd) {
+            return;
+        }
+        self.descriptors.insert(
+            wd,
+            Descriptor {
+                watch,
+                path: path.to_owned(),
+            },
+        );
+        if let Err(e) = self.watch_subdirectories(watch, path) {
+            log::warn!("[calloop] Failed to watch directory {:?}: {}", path, e);
+        }
+    }
+
+    /// Stop following a directory that left a recursive watch, and its subdirectories.
+    fn forget_directory(&mut self, watch: WatchId, path: &Path) {
+        let root = match self.watches.get(&watch) {
+            Some(watch) => watch.root,
+            None => return,
+        };
+        let inotify = self.inotify();
+        self.descriptors.retain(|wd, descriptor| {
+            if descriptor.watch != watch || *wd == root || !descriptor.path.starts_with(path) {
+                return true;
+            }
+            let _ = inotify.rm_watch(*wd);
+            false
+        });
+    }
+
+    /// Follow a directory moved within the watched directories.
+    fn move_directory(&mut self, from_watch: WatchId, from: &Path, to_watch: WatchId, to: &Path) {
+        match (self.is_recursive(from_watch), self.is_recursive(to_watch)) {
+            (true, true) => {
+                // Keep the existing watch descriptors, which may have events queued already.
+                let root = self.watches[&from_watch].root;
+                for (wd, descriptor) in self.descriptors.iter_mut() {
+                    if descriptor.watch != from_watch || *wd == root {
+                        continue;
+                    }
+                    if let Ok(rest) = descriptor.path.strip_prefix(from) {
+                        descriptor.path = to.join(rest);
+                        descriptor.watch = to_watch;
+                    }
+                }
+            }
+            (true, false) => self.forget_directory(from_watch, from),
+            (false, true) => self.watch_new_directory(to_watch, to),
+            (false, false) => {}
+        }
+    }
+
+    fn flush_pending_move<F>(&mut self, callback: &mut F)
+    where
+        F: FnMut(Event, &mut ()),
+    {
+        if let Some(pending) = self.pending_move.take() {
+            if pending.is_dir && self.is_recursive(pending.watch) {
+                self.forget_directory(pending.watch, &pending.path);
+            }
+            callback(
+                Event::MoveOut {
+                    watch: pending.watch,
+                    path: pending.path,
+                },
+                &mut (),
+            );
+        }
+    }
+
+    fn handle_event<F>(&mut self, event: InotifyEvent, callback: &mut F)
+    where
+        F: FnMut(Event, &mut ()),
+    {
+        if event.mask.contains(AddWatchFlags::IN_Q_OVERFLOW) {
+            self.flush_pending_move(callback);
+            callback(Event::Overflow, &mut ());
+            return;
+        }
+
+        let descriptor = match self.descriptors.get(&event.wd) {
+            Some(descriptor) => descriptor.clone(),
+            // This watch was removed.
+            None => return,
+        };
+        let watch = descriptor.watch;
+        let is_dir = event.mask.contains(AddWatchFlags::IN_ISDIR);
+        let path = match event.name {
+            Some(name) => descriptor.path.join(name),
+            None => descriptor.path,
+        };
+
+        if event.mask.contains(AddWatchFlags::IN_MOVED_TO) {
+            if let Some(pending) = self.pending_move.take() {
+                if pending.cookie == event.cookie {
+                    if pending.is_dir {
+                        self.move_directory(pending.watch, &pending.path, watch, &path);
+                    }
+                    callback(
+                        Event::Move {
+                            watch,
+                            from: pending.path,
+                            to: path,
+                        },
+                        &mut (),
+                    );
+                    return;
+                }
+                self.pending_move = Some(pending);
+            }
+        }
+        self.flush_pending_move(callback);
+
+        if event.mask.contains(AddWatchFlags::IN_IGNORED) {
+            self.descriptors.remove(&event.wd);
+            if self.watches.get(&watch).map(|watch| watch.root) == Some(event.wd) {
+                self.watches.remove(&watch);
+                callback(Event::WatchRemoved { watch }, &mut ());
+            }
+        } else if event.mask.contains(AddWatchFlags::IN_CREATE) {
+            if is_dir && self.is_recursive(watch) {
+                self.watch_new_directory(watch, &path);
+            }
+            callback(Event::Create { watch, path }, &mut ());
+        } else if event.mask.contains(AddWatchFlags::IN_MODIFY) {
+            callback(Event::Modify { watch, path }, &mut ());
+        } else if event.mask.contains(AddWatchFlags::IN_DELETE) {
+            callback(Event::Delete { watch, path }, &mut ());
+        } else if event.mask.contains(AddWatchFlags::IN_MOVED_FROM) {
+            self.pending_move = Some(PendingMove {
+                cookie: event.cookie,
+                watch,
+                path,
+                is_dir,
+            });
+        } else if event.mask.contains(AddWatchFlags::IN_MOVED_TO) {
+            if is_dir && self.is_recursive(watch) {
+                self.watch_new_directory(watch, &path);
+            }
+            callback(Event::MoveIn { watch, path }, &mut ());
+        }
+    }
+}
+
+impl EventSource for FileWatcher {
+    type Event = Event;
+    type Metadata = ();
+    type Ret = ();
+    type Error = FileWatcherError;
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> Result<PostAction, Self::Error>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        // Leftover events from a previous dispatch are processed even without new ones.
+        let mut readable = std::mem::take(&mut self.pending);
+        self.fd
+            .process_events(readiness, token, |_, _| {
+                readable = true;
+                Ok(PostAction::Continue)
+            })
+            .map_err(|e| FileWatcherError(e.into()))?;
+        if !readable {
+            return Ok(PostAction::Continue);
+        }
+
+        let inotify = self.inotify();
+        let budget = self.budget;
+        let generated = Cell::new(0);
+        let mut callback = |event, metadata: &mut ()| {
+            generated.set(generated.get() + 1);
+            callback(event, metadata)
+        };
+        loop {
+            if budget.map_or(false, |budget| generated.get() >= budget.get()) {
+                self.pending = true;
+                return Ok(PostAction::Continue);
+            }
+            let event = match self.queued.pop_front() {
+                Some(event) => event,
+                None => match inotify.read_events() {
+                    Ok(events) => {
+                        self.queued.extend(events);
+                        continue;
+                    }
+                    Err(Errno::EAGAIN) => break,
+                    Err(e) => {
+                        log::warn!("[calloop] Error reading from inotify: {}", e);
+                        return Err(FileWatcherError(e.into()));
+                    }
+                },
+            };
+            self.handle_event(event, &mut callback);
+        }
+        // The second half of a move is queued right after the first one: if it is not there,
+        // the file was moved out of the watched directories.
+        self.flush_pending_move(&mut callback);
+
+        Ok(PostAction::Continue)
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> crate::Result<()> {
+        self.fd.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> crate::Result<()> {
+        self.fd.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> crate::Result<()> {
+        self.fd.unregister(poll)
+    }
+
+    fn set_event_budget(&mut self, budget: Option<NonZeroUsize>) {
+        self.budget = budget;
+    }
+
+    fn has_pending_events(&self) -> bool {
+        self.pending
+    }
+}
+
+/// An error arising from processing events for a file watcher.
+#[derive(thiserror::Error, Debug)]
+#[error(transparent)]
+pub struct FileWatcherError(Box<dyn std::error::Error + Sync + Send>);
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, time::Duration};
+
+    use super::*;
+    use crate::EventLoop;
+
+    /// A fresh directory for a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("calloop-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn dispatch(event_loop: &mut EventLoop<Vec<Event>>) -> Vec<Event> {
+        let mut events = Vec::new();
+        event_loop.dispatch(Duration::ZERO, &mut events).unwrap();
+        events
+    }
+
+    #[test]
+    fn watch_directory() {
+        let dir = test_dir("watch-directory");
+        let outside = test_dir("watch-directory-outside");
+
+        let mut event_loop = EventLoop::<Vec<Event>>::try_new().unwrap();
+        let mut watcher = FileWatcher::new().unwrap();
+        let watch = watcher.add_watch(&dir).unwrap();
+        assert!(watcher.add_watch(&dir).is_err());
+        assert_eq!(watcher.watch_path(watch), Some(dir.as_path()));
+        event_loop
+            .handle()
+            .insert_source(watcher, |event, _, events| events.push(event))
+            .unwrap();
+
+        assert_eq!(dispatch(&mut event_loop), []);
+
+        let file = dir.join("file");
+        fs::write(&file, b"hello").unwrap();
+        let renamed = dir.join("renamed");
+        fs::rename(&file, &renamed).unwrap();
+        assert_eq!(
+            dispatch(&mut event_loop),
+            [
+                Event::Create {
+                    watch,
+                    path: file.clone()
+                },
+                Event::Modify {
+                    watch,
+                    path: file.clone()
+                },
+                Event::Move {
+                    watch,
+                    from: file.clone(),
+                    to: renamed.clone()
+                },
+            ]
+        );
+
+        fs::rename(&renamed, outside.join("file")).unwrap();
+        fs::rename(outside.join("file"), &file).unwrap();
+        fs::remove_file(&file).unwrap();
+        assert_eq!(
+            dispatch(&mut event_loop),
+            [
+                Event::MoveOut {
+                    watch,
+                    path: renamed
+                },
+                Event::MoveIn {
+                    watch,
+                    path: file.clone()
+                },
+                Event::Delete { watch, path: file },
+            ]
+        );
+
+        fs::remove_dir(&dir).unwrap();
+        assert_eq!(dispatch(&mut event_loop), [Event::WatchRemoved { watch }]);
+
+        fs::remove_dir_all(&outside).unwrap();
+    }
+
+    #[test]
+    fn recursive_watch() {
+        let dir = test_dir("recursive-watch");
+        fs::create_dir(dir.join("existing")).unwrap();
+
+        let mut event_loop = EventLoop::<Vec<Event>>::try_new().unwrap();
+        let mut watcher = FileWatcher::new().unwrap();
+        let watch = watcher.add_recursive_watch(&dir).unwrap();
+        event_loop
+            .handle()
+            .insert_source(watcher, |event, _, events| events.push(event))
+            .unwrap();
+
+        // Existing and new subdirectories are watched.
+        fs::create_dir(dir.join("new")).unwrap();
+        assert_eq!(
+            dispatch(&mut event_loop),
+            [Event::Create {
+                watch,
+                path: dir.join("new")
+            }]
+        );
+        fs::write(dir.join("existing/file"), b"").unwrap();
+        fs::write(dir.join("new/file"), b"").unwrap();
+        assert_eq!(
+            dispatch(&mut event_loop),
+            [
+                Event::Create {
+                    watch,
+                    path: dir.join("existing/file")
+                },
+                Event::Create {
+                    watch,
+                    path: dir.join("new/file")
+                },
+            ]
+        );
+
+        // Moved subdirectories are followed.
+        fs::rename(dir.join("new"), dir.join("existing/moved")).unwrap();
+        fs::remove_file(dir.join("existing/moved/file")).unwrap();
+        assert_eq!(
+            dispatch(&mut event_loop),
+            [
+                Event::Move {
+                    watch,
+                    from: dir.join("new"),
+                    to: dir.join("existing/moved")
+                },
+                Event::Delete {
+                    watch,
+                    path: dir.join("existing/moved/file")
+                },
+            ]
+        );
+
+        // Deleted subdirectories do not remove the watch.
+        fs::remove_dir(dir.join("existing/moved")).unwrap();
+        assert_eq!(
+            dispatch(&mut event_loop),
+            [Event::Delete {
+                watch,
+                path: dir.join("existing/moved")
+            }]
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn event_budget() {
+        let dir = test_dir("event-budget");
+
+        let mut event_loop = EventLoop::<Vec<Event>>::try_new().unwrap();
+        event_loop.set_event_budget(NonZeroUsize::new(2));
+        let mut watcher = FileWatcher::new().unwrap();
+        let watch = watcher.add_watch(&dir).unwrap();
+        event_loop
+            .handle()
+            .insert_source(watcher, |event, _, events| events.push(event))
+            .unwrap();
+
+        for name in ["a", "b", "c"] {
+            fs::File::create(dir.join(name)).unwrap();
+        }
+        fs::rename(dir.join("a"), dir.join("d")).unwrap();
+
+        // Each dispatch only generates two events, the leftovers must be processed without
+        // waiting for the timeout.
+        let timeout = Duration::from_secs(10);
+        let start = std::time::Instant::now();
+        let create = |name| Event::Create {
+            watch,
+            path: dir.join(name),
+        };
+
+        let mut events = Vec::new();
+        event_loop.dispatch(timeout, &mut events).unwrap();
+        assert_eq!(events, [create("a"), create("b")]);
+
+        events.clear();
+        event_loop.dispatch(timeout, &mut events).unwrap();
+        assert_eq!(
+            events,
+            [
+                create("c"),
+                Event::Move {
+                    watch,
+                    from: dir.join("a"),
+                    to: dir.join("d")
+                },
+            ]
+        );
+
+        assert!(start.elapsed() < timeout);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
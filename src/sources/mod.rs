@@ -1,5 +1,6 @@
 use std::{
     cell::{Ref, RefCell, RefMut},
+    num::NonZeroUsize,
     ops::{BitOr, BitOrAssign},
     rc::Rc,
 };
@@ -7,13 +8,22 @@ use std::{
 use crate::{sys::TokenFactory, Poll, Readiness, Token};
 
 pub mod channel;
+pub mod framed;
 #[cfg(feature = "executor")]
 #[cfg_attr(docsrs, doc(cfg(feature = "executor")))]
 pub mod futures;
 pub mod generic;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[cfg_attr(docsrs, doc(cfg(any(target_os = "linux", target_os = "android"))))]
+pub mod inotify;
+pub mod nested;
+pub mod net;
 pub mod ping;
 #[cfg(target_os = "linux")]
 #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+pub mod process;
+#[cfg(target_os = "linux")]
+#[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
 pub mod signals;
 pub mod timer;
 pub mod transient;
@@ -176,6 +186,46 @@ pub trait EventSource {
     {
         Ok(())
     }
+
+    /// Limit the number of events generated by a single call to `process_events`
+    ///
+    /// This is invoked by the event loop when your source is registered, and whenever the
+    /// budget is changed using [`EventLoop::set_event_budget`](crate::EventLoop#method.set_event_budget).
+    /// `None` means there is no limit.
+    ///
+    /// If a single readiness notification can make your source generate an unbounded number
+    /// of events (for example by draining a queue), it should stop once `budget` events
+    /// have been generated and leave the rest for a later dispatching cycle. If your file
+    /// descriptors remain ready in that case, the event loop will wake up again by itself.
+    /// Otherwise, report the leftover work using [`has_pending_events`](Self::has_pending_events).
+    ///
+    /// The default implementation ignores the budget.
+    fn set_event_budget(&mut self, _budget: Option<NonZeroUsize>) {}
+
+    /// Whether events were left unprocessed by the last call to `process_events`
+    ///
+    /// If this returns `true` right after `process_events` was called, the event loop will
+    /// call `process_events` again during its next dispatching cycle, without waiting for new
+    /// events. This call will be made with the same [`Token`] and an empty
+    /// [`Readiness`](crate::Readiness).
+    ///
+    /// The default implementation returns `false`.
+    fn has_pending_events(&self) -> bool {
+        false
+    }
+
+    /// Notification that the event loop is shutting down
+    ///
+    /// This is invoked by [`EventLoop::run`](crate::EventLoop#method.run) once
+    /// [`LoopSignal::shutdown`](crate::LoopSignal#method.shutdown) was called, before each of its
+    /// remaining dispatching cycles. Return `false` if your source still has work to finish, like
+    /// flushing buffered data, and `true` once it is done. The loop stops when all its sources are
+    /// done, or when the shutdown deadline is reached.
+    ///
+    /// The default implementation returns `true`.
+    fn on_shutdown(&mut self) -> bool {
+        true
+    }
 }
 
 /// Blanket implementation for boxed event sources. [`EventSource`] is not an
@@ -227,6 +277,18 @@ impl<T: EventSource> EventSource for Box<T> {
     {
         T::post_run(&mut **self, callback)
     }
+
+    fn set_event_budget(&mut self, budget: Option<NonZeroUsize>) {
+        T::set_event_budget(&mut **self, budget)
+    }
+
+    fn has_pending_events(&self) -> bool {
+        T::has_pending_events(&**self)
+    }
+
+    fn on_shutdown(&mut self) -> bool {
+        T::on_shutdown(&mut **self)
+    }
 }
 
 /// Blanket implementation for exclusive references to event sources.
@@ -279,6 +341,18 @@ impl<T: EventSource> EventSource for &mut T {
     {
         T::post_run(&mut **self, callback)
     }
+
+    fn set_event_budget(&mut self, budget: Option<NonZeroUsize>) {
+        T::set_event_budget(&mut **self, budget)
+    }
+
+    fn has_pending_events(&self) -> bool {
+        T::has_pending_events(&**self)
+    }
+
+    fn on_shutdown(&mut self) -> bool {
+        T::on_shutdown(&mut **self)
+    }
 }
 
 pub(crate) struct DispatcherInner<S, F> {
@@ -296,6 +370,7 @@ where
         readiness: Readiness,
         token: Token,
         data: &mut Data,
+        #[cfg(feature = "metrics")] counters: &mut crate::metrics::SourceCounters,
     ) -> crate::Result<PostAction> {
         let mut disp = self.borrow_mut();
         let DispatcherInner {
@@ -303,7 +378,14 @@ where
             ref mut callback,
         } = *disp;
         source
-            .process_events(readiness, token, |event, meta| callback(event, meta, data))
+            .process_events(readiness, token, |event, meta| {
+                #[cfg(feature = "metrics")]
+                let start = std::time::Instant::now();
+                let ret = callback(event, meta, data);
+                #[cfg(feature = "metrics")]
+                counters.record_callback(start.elapsed());
+                ret
+            })
             .map_err(|e| crate::Error::OtherError(e.into()))
     }
 
@@ -346,6 +428,22 @@ where
         } = *disp;
         source.post_run(|event, meta| callback(event, meta, data))
     }
+
+    fn set_event_budget(&self, budget: Option<NonZeroUsize>) {
+        self.borrow_mut().source.set_event_budget(budget)
+    }
+
+    fn has_pending_events(&self) -> bool {
+        self.borrow().source.has_pending_events()
+    }
+
+    fn on_shutdown(&self) -> bool {
+        self.borrow_mut().source.on_shutdown()
+    }
+
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<S>()
+    }
 }
 
 pub(crate) trait EventDispatcher<Data> {
@@ -354,6 +452,7 @@ pub(crate) trait EventDispatcher<Data> {
         readiness: Readiness,
         token: Token,
         data: &mut Data,
+        #[cfg(feature = "metrics")] counters: &mut crate::metrics::SourceCounters,
     ) -> crate::Result<PostAction>;
 
     fn register(&self, poll: &mut Poll, token_factory: &mut TokenFactory) -> crate::Result<()>;
@@ -365,12 +464,21 @@ pub(crate) trait EventDispatcher<Data> {
     fn pre_run(&self, data: &mut Data) -> crate::Result<()>;
 
     fn post_run(&self, data: &mut Data) -> crate::Result<()>;
+
+    fn set_event_budget(&self, budget: Option<NonZeroUsize>);
+
+    fn has_pending_events(&self) -> bool;
+
+    fn on_shutdown(&self) -> bool;
+
+    fn type_name(&self) -> &'static str;
 }
 
 // An internal trait to erase the `F` type parameter of `DispatcherInner`
 trait ErasedDispatcher<'a, S, Data> {
     fn as_source_ref(&self) -> Ref<S>;
     fn as_source_mut(&self) -> RefMut<S>;
+    fn try_as_source_mut(&self) -> Option<RefMut<S>>;
     fn into_source_inner(self: Rc<Self>) -> S;
     fn into_event_dispatcher(self: Rc<Self>) -> Rc<dyn EventDispatcher<Data> + 'a>;
 }
@@ -388,6 +496,11 @@ where
         RefMut::map(self.borrow_mut(), |inner| &mut inner.source)
     }
 
+    fn try_as_source_mut(&self) -> Option<RefMut<S>> {
+        let inner = self.try_borrow_mut().ok()?;
+        Some(RefMut::map(inner, |inner| &mut inner.source))
+    }
+
     fn into_source_inner(self: Rc<Self>) -> S {
         if let Ok(ref_cell) = Rc::try_unwrap(self) {
             ref_cell.into_inner().source
@@ -465,9 +578,22 @@ where
         self.0.into_source_inner()
     }
 
+    /// Returns a mutable reference to the event source, unless it is already borrowed.
+    pub(crate) fn try_as_source_mut(&self) -> Option<RefMut<S>> {
+        self.0.try_as_source_mut()
+    }
+
     pub(crate) fn clone_as_event_dispatcher(&self) -> Rc<dyn EventDispatcher<Data> + 'a> {
         Rc::clone(&self.0).into_event_dispatcher()
     }
+
+    /// Whether `dispatcher` was obtained from this dispatcher.
+    pub(crate) fn is(&self, dispatcher: &Rc<dyn EventDispatcher<Data> + 'a>) -> bool {
+        std::ptr::eq(
+            Rc::as_ptr(&self.0) as *const (),
+            Rc::as_ptr(dispatcher) as *const (),
+        )
+    }
 }
 
 impl<'a, S, Data> Clone for Dispatcher<'a, S, Data> {
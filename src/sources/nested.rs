@@ -0,0 +1,282 @@
+//! An event source driving another event loop
+//!
+//! The [`NestedLoop`] event source wraps a child [`EventLoop`], and generates an event every time
+//! the child loop needs to be dispatched: when its sources have events ready, when it is woken up
+//! through a [`LoopSignal`](crate::LoopSignal), or when one of its timers expires.
+//!
+//! The child loop is given to your callback, which is responsible for dispatching it with the
+//! data it needs, typically with [`EventLoop::dispatch_pending()`]:
+//!
+//! ```
+//! use calloop::{nested::NestedLoop, EventLoop};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! struct State {
+//!     child_data: u32,
+//! }
+//!
+//! let mut event_loop = EventLoop::<State>::try_new()?;
+//! let child_loop = EventLoop::<u32>::try_new()?;
+//!
+//! event_loop
+//!     .handle()
+//!     .insert_source(NestedLoop::new(child_loop), |(), child_loop, state| {
+//!         child_loop.dispatch_pending(&mut state.child_data)
+//!     })?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The child loop can have a different data type and lifetime than the parent loop.
+
+use std::{cell::RefCell, rc::Rc, time::Instant};
+
+use crate::{
+    clock::LoopClock,
+    sources::timer::{TimeoutId, TimerWheel},
+    EventLoop, EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory,
+};
+
+/// An event source driving a child event loop
+///
+/// See the [module documentation](self) for details.
+///
+/// The file descriptor of the child loop is monitored directly by the parent loop. On the other
+/// hand, the deadline of the next timer of the child loop is only checked when this source is
+/// registered and after every call to your callback: timers inserted into the child loop in
+/// between are only taken into account once it has been dispatched again.
+pub struct NestedLoop<'l, Data> {
+    event_loop: EventLoop<'l, Data>,
+    fd_token: Option<Token>,
+    timer: Option<TimerRegistration>,
+}
+
+struct TimerRegistration {
+    wheel: Rc<RefCell<TimerWheel>>,
+    clock: Rc<dyn LoopClock>,
+    token: Token,
+    /// The timeout currently in the wheel, and its deadline.
+    armed: Option<(TimeoutId, Instant)>,
+}
+
+impl<'l, Data> std::fmt::Debug for NestedLoop<'l, Data> {
+    #[cfg_attr(feature = "nightly_coverage", no_coverage)]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("NestedLoop { ... }")
+    }
+}
+
+impl<'l, Data> NestedLoop<'l, Data> {
+    /// Wrap a child event loop in an event source
+    pub fn new(event_loop: EventLoop<'l, Data>) -> Self {
+        NestedLoop {
+            event_loop,
+            fd_token: None,
+            timer: None,
+        }
+    }
+
+    /// Access the child event loop
+    pub fn event_loop(&self) -> &EventLoop<'l, Data> {
+        &self.event_loop
+    }
+
+    /// Mutably access the child event loop
+    pub fn event_loop_mut(&mut self) -> &mut EventLoop<'l, Data> {
+        &mut self.event_loop
+    }
+
+    /// Unwrap the child event loop
+    pub fn into_inner(self) -> EventLoop<'l, Data> {
+        self.event_loop
+    }
+
+    /// Make sure the parent loop wakes up by the time the child loop needs to be dispatched.
+    fn arm_timer(&mut self) {
+        let timer = match self.timer.as_mut() {
+            Some(timer) => timer,
+            None => return,
+        };
+
+        let deadline = self
+            .event_loop
+            .next_timeout()
+            .and_then(|timeout| timer.clock.now().checked_add(timeout));
+        if timer.armed.map(|(_, deadline)| deadline) == deadline {
+            return;
+        }
+
+        let mut wheel = timer.wheel.borrow_mut();
+        if let Some((timeout, _)) = timer.armed.take() {
+            wheel.cancel(timeout);
+        }
+        if let Some(deadline) = deadline {
+            let timeout = wheel.insert(deadline, timer.token);
+            timer.armed = Some((timeout, deadline));
+        }
+    }
+
+    fn disarm_timer(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            if let Some((timeout, _)) = timer.armed {
+                timer.wheel.borrow_mut().cancel(timeout);
+            }
+        }
+    }
+}
+
+impl<'l, Data> EventSource for NestedLoop<'l, Data> {
+    type Event = ();
+    type Metadata = EventLoop<'l, Data>;
+    type Ret = crate::Result<()>;
+    type Error = crate::Error;
+
+    fn process_events<F>(
+        &mut self,
+        _: Readiness,
+        token: Token,
+        mut callback: F,
+    ) -> Result<PostAction, Self::Error>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if let Some(timer) = self.timer.as_mut() {
+            if timer.token == token {
+                // The timeout has been removed from the wheel when it expired.
+                timer.armed = None;
+            }
+        }
+
+        if Some(token) == self.fd_token
+            || self.timer.as_ref().map(|timer| timer.token) == Some(token)
+        {
+            callback((), &mut self.event_loop)?;
+            self.arm_timer();
+        }
+
+        Ok(PostAction::Continue)
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> crate::Result<()> {
+        let fd_token = token_factory.token();
+        poll.register(&self.event_loop, Interest::READ, Mode::Level, fd_token)?;
+        self.fd_token = Some(fd_token);
+
+        self.timer = Some(TimerRegistration {
+            wheel: poll.timers.clone(),
+            clock: poll.clock.clone(),
+            token: token_factory.token(),
+            armed: None,
+        });
+        self.arm_timer();
+
+        Ok(())
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> crate::Result<()> {
+        let fd_token = token_factory.token();
+        poll.reregister(&self.event_loop, Interest::READ, Mode::Level, fd_token)?;
+        self.fd_token = Some(fd_token);
+
+        self.disarm_timer();
+        self.timer = Some(TimerRegistration {
+            wheel: poll.timers.clone(),
+            clock: poll.clock.clone(),
+            token: token_factory.token(),
+            armed: None,
+        });
+        self.arm_timer();
+
+        Ok(())
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> crate::Result<()> {
+        poll.unregister(&self.event_loop)?;
+        self.fd_token = None;
+        self.disarm_timer();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{ping::make_ping, timer::TimeoutAction, timer::Timer};
+
+    #[test]
+    fn nested_loop() {
+        let mut event_loop = EventLoop::<Vec<&str>>::try_new().unwrap();
+        let child_loop = EventLoop::<Vec<&str>>::try_new().unwrap();
+        let child_handle = child_loop.handle();
+
+        let (ping, ping_source) = make_ping().unwrap();
+        child_handle
+            .insert_source(ping_source, |(), &mut (), events| events.push("ping"))
+            .unwrap();
+        child_handle
+            .insert_source(
+                Timer::from_duration(Duration::from_millis(100)),
+                |_, &mut (), events| {
+                    events.push("timer");
+                    TimeoutAction::Drop
+                },
+            )
+            .unwrap();
+
+        event_loop
+            .handle()
+            .insert_source(NestedLoop::new(child_loop), |(), child_loop, events| {
+                child_loop.dispatch_pending(events)
+            })
+            .unwrap();
+
+        let mut events = Vec::new();
+
+        // Nothing to do yet.
+        event_loop.dispatch(Duration::ZERO, &mut events).unwrap();
+        assert!(events.is_empty());
+
+        // Events of the sources of the child loop wake up the parent loop.
+        ping.ping();
+        event_loop
+            .dispatch(Duration::from_secs(1), &mut events)
+            .unwrap();
+        assert_eq!(events, ["ping"]);
+
+        // And so do its timers.
+        let now = Instant::now();
+        event_loop
+            .dispatch(Duration::from_secs(1), &mut events)
+            .unwrap();
+        assert_eq!(events, ["ping", "timer"]);
+        assert!(now.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn next_timeout() {
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        let handle = event_loop.handle();
+        assert_eq!(event_loop.next_timeout(), None);
+
+        handle
+            .insert_source(Timer::from_duration(Duration::from_secs(60)), |_, _, _| {
+                TimeoutAction::Drop
+            })
+            .unwrap();
+        let timeout = event_loop.next_timeout().unwrap();
+        assert!(timeout > Duration::from_secs(50) && timeout <= Duration::from_secs(60));
+
+        // Idle callbacks need the loop to be dispatched right away.
+        handle.insert_idle(|_| {});
+        assert_eq!(event_loop.next_timeout(), Some(Duration::ZERO));
+        event_loop.dispatch_pending(&mut ()).unwrap();
+        assert!(event_loop.next_timeout().unwrap() > Duration::ZERO);
+    }
+}
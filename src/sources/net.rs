@@ -0,0 +1,490 @@
+//! Event source for accepting connections on a listening socket
+//!
+//! The [`Listener`] event source wraps a [`TcpListener`] or a [`UnixListener`], and generates an
+//! event for each accepted connection, with the address of the peer.
+//!
+//! ```no_run
+//! use calloop::{net::Listener, EventLoop};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut event_loop = EventLoop::<()>::try_new()?;
+//!
+//! let listener = Listener::new(std::net::TcpListener::bind("127.0.0.1:8080")?)?;
+//! event_loop
+//!     .handle()
+//!     .insert_source(listener, |(stream, addr), _, _| {
+//!         println!("new connection from {}", addr);
+//!         // insert the stream in the event loop...
+//!     })?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! When the process runs out of file descriptors, the pending connections cannot be accepted and
+//! the listening socket stays readable. Rather than waking up the event loop again and again, the
+//! listener then pauses accepting connections for a while, see
+//! [`Listener::set_fd_limit_backoff()`].
+
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, TcpStream},
+    num::NonZeroUsize,
+    os::unix::net::{self, UnixListener, UnixStream},
+    time::{Duration, Instant},
+};
+
+use io_lifetimes::AsFd;
+
+use super::{
+    generic::Generic,
+    timer::{TimeoutAction, Timer},
+};
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// The default time a [`Listener`] pauses for after running out of file descriptors
+const DEFAULT_FD_LIMIT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// A listening socket [`Listener`] can accept connections from
+pub trait Accept: AsFd {
+    /// The type of the accepted connections
+    type Stream;
+    /// The type of the address of the peers
+    type Addr;
+
+    /// Accept a new connection
+    ///
+    /// This is called with the socket in non-blocking mode, and should return an error of kind
+    /// [`WouldBlock`](io::ErrorKind::WouldBlock) when there is no pending connection.
+    fn accept(&self) -> io::Result<(Self::Stream, Self::Addr)>;
+
+    /// Move the socket into or out of non-blocking mode
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+
+    /// Move an accepted connection into or out of non-blocking mode
+    fn set_stream_nonblocking(stream: &Self::Stream, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Accept for TcpListener {
+    type Stream = TcpStream;
+    type Addr = SocketAddr;
+
+    fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        TcpListener::accept(self)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        TcpListener::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_stream_nonblocking(stream: &TcpStream, nonblocking: bool) -> io::Result<()> {
+        stream.set_nonblocking(nonblocking)
+    }
+}
+
+impl Accept for UnixListener {
+    type Stream = UnixStream;
+    type Addr = net::SocketAddr;
+
+    fn accept(&self) -> io::Result<(UnixStream, net::SocketAddr)> {
+        UnixListener::accept(self)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        UnixListener::set_nonblocking(self, nonblocking)
+    }
+
+    fn set_stream_nonblocking(stream: &UnixStream, nonblocking: bool) -> io::Result<()> {
+        stream.set_nonblocking(nonblocking)
+    }
+}
+
+/// An event source accepting connections
+///
+/// Each event is an accepted connection, in non-blocking mode so that it can be inserted in the
+/// event loop, with the address of the peer.
+#[derive(Debug)]
+pub struct Listener<L: Accept> {
+    fd: Generic<L>,
+    max_accepts: Option<NonZeroUsize>,
+    budget: Option<NonZeroUsize>,
+    fd_limit_backoff: Duration,
+    /// Set while accepting is paused, until the timer fires.
+    paused: Option<Timer>,
+    /// Whether the timer of `paused` fired, and accepting can resume.
+    resumed: bool,
+}
+
+impl<L: Accept> Listener<L> {
+    /// Create a source accepting connections on a listening socket
+    ///
+    /// The socket is moved into non-blocking mode.
+    pub fn new(listener: L) -> io::Result<Listener<L>> {
+        listener.set_nonblocking(true)?;
+        Ok(Listener {
+            fd: Generic::new(listener, Interest::READ, Mode::Level),
+            max_accepts: None,
+            budget: None,
+            fd_limit_backoff: DEFAULT_FD_LIMIT_BACKOFF,
+            paused: None,
+            resumed: false,
+        })
+    }
+
+    /// Limit the number of connections accepted each time the event loop is woken up
+    ///
+    /// The connections left pending are accepted during the next dispatching cycles. `None`, the
+    /// default, accepts every pending connection at once. The event budget of the loop (see
+    /// [`EventLoop::set_event_budget`](crate::EventLoop::set_event_budget)) also applies.
+    pub fn set_max_accepts(&mut self, max_accepts: Option<NonZeroUsize>) {
+        self.max_accepts = max_accepts;
+    }
+
+    /// Set how long accepting pauses for when the process runs out of file descriptors
+    ///
+    /// When accepting a connection fails because the per-process or system-wide limit on open
+    /// file descriptors was reached (`EMFILE` or `ENFILE`), the listener stops accepting
+    /// connections for this duration, and then tries again. It defaults to 100 milliseconds.
+    pub fn set_fd_limit_backoff(&mut self, backoff: Duration) {
+        self.fd_limit_backoff = backoff;
+    }
+
+    /// Whether accepting is paused because the process ran out of file descriptors
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_some()
+    }
+
+    /// Access the listening socket
+    pub fn get_ref(&self) -> &L {
+        &self.fd.file
+    }
+
+    /// Retrieve the listening socket
+    pub fn into_inner(self) -> L {
+        self.fd.unwrap()
+    }
+
+    fn accept_limit(&self) -> Option<NonZeroUsize> {
+        match (self.max_accepts, self.budget) {
+            (Some(max), Some(budget)) => Some(max.min(budget)),
+            (max, budget) => max.or(budget),
+        }
+    }
+}
+
+impl<L: Accept> EventSource for Listener<L> {
+    type Event = (L::Stream, L::Addr);
+    type Metadata = ();
+    type Ret = ();
+    type Error = ListenerError;
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> Result<PostAction, Self::Error>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if let Some(timer) = &mut self.paused {
+            let resumed = &mut self.resumed;
+            timer
+                .process_events(readiness, token, |_: Instant, &mut ()| {
+                    *resumed = true;
+                    TimeoutAction::Drop
+                })
+                .map_err(|e| ListenerError(e.into()))?;
+            if self.resumed {
+                // Watch the socket again.
+                return Ok(PostAction::Reregister);
+            }
+            return Ok(PostAction::Continue);
+        }
+
+        let limit = self.accept_limit();
+        let fd_limit_backoff = self.fd_limit_backoff;
+        let mut paused = None;
+        let action = self
+            .fd
+            .process_events(readiness, token, |_, listener| {
+                let mut count = 0;
+                while limit.map_or(true, |limit| count < limit.get()) {
+                    match listener.accept() {
+                        Ok((stream, addr)) => {
+                            L::set_stream_nonblocking(&stream, true)?;
+                            count += 1;
+                            callback((stream, addr), &mut ());
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        // The connection was reset before it could be accepted.
+                        Err(e) if e.kind() == io::ErrorKind::ConnectionAborted => {}
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                        Err(e)
+                            if matches!(
+                                e.raw_os_error(),
+                                Some(nix::libc::EMFILE) | Some(nix::libc::ENFILE)
+                            ) =>
+                        {
+                            log::warn!(
+                                "[calloop] Out of file descriptors, pausing accepting connections for {:?}",
+                                fd_limit_backoff
+                            );
+                            paused = Some(Timer::from_duration(fd_limit_backoff));
+                            return Ok(PostAction::Reregister);
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                Ok(PostAction::Continue)
+            })
+            .map_err(|e| ListenerError(e.into()))?;
+
+        self.paused = paused;
+        Ok(action)
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> crate::Result<()> {
+        self.fd.interest = if self.paused.is_some() {
+            Interest::EMPTY
+        } else {
+            Interest::READ
+        };
+        self.fd.register(poll, token_factory)?;
+        if let Some(timer) = &mut self.paused {
+            timer.register(poll, token_factory)?;
+        }
+        Ok(())
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> crate::Result<()> {
+        if self.resumed {
+            if let Some(mut timer) = self.paused.take() {
+                timer.unregister(poll)?;
+            }
+            self.resumed = false;
+        }
+        self.fd.interest = if self.paused.is_some() {
+            Interest::EMPTY
+        } else {
+            Interest::READ
+        };
+        self.fd.reregister(poll, token_factory)?;
+        if let Some(timer) = &mut self.paused {
+            timer.reregister(poll, token_factory)?;
+        }
+        Ok(())
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> crate::Result<()> {
+        self.fd.unregister(poll)?;
+        if let Some(timer) = &mut self.paused {
+            timer.unregister(poll)?;
+        }
+        Ok(())
+    }
+
+    fn set_event_budget(&mut self, budget: Option<NonZeroUsize>) {
+        self.budget = budget;
+    }
+}
+
+/// An error arising from processing events for a listener.
+#[derive(thiserror::Error, Debug)]
+#[error(transparent)]
+pub struct ListenerError(Box<dyn std::error::Error + Sync + Send>);
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        io::{Read, Write},
+        rc::Rc,
+    };
+
+    use super::*;
+    use crate::{clock::ManualClock, EventLoop};
+
+    #[test]
+    fn accept_tcp() {
+        let mut event_loop = EventLoop::<Vec<TcpStream>>::try_new().unwrap();
+        let mut listener = Listener::new(TcpListener::bind("127.0.0.1:0").unwrap()).unwrap();
+        listener.set_max_accepts(NonZeroUsize::new(2));
+        let addr = listener.get_ref().local_addr().unwrap();
+        event_loop
+            .handle()
+            .insert_source(listener, |(stream, peer), _, streams| {
+                assert_eq!(stream.peer_addr().unwrap(), peer);
+                streams.push(stream);
+            })
+            .unwrap();
+
+        let mut clients: Vec<_> = (0..3).map(|_| TcpStream::connect(addr).unwrap()).collect();
+
+        let mut streams = Vec::new();
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut streams)
+            .unwrap();
+        assert_eq!(streams.len(), 2);
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut streams)
+            .unwrap();
+        assert_eq!(streams.len(), 3);
+
+        // The accepted streams are non-blocking.
+        let mut buffer = [0u8; 5];
+        assert_eq!(
+            streams[0].read(&mut buffer).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        clients[0].write_all(b"hello").unwrap();
+        let client_addr = clients[0].local_addr().unwrap();
+        let stream = streams
+            .iter_mut()
+            .find(|stream| stream.peer_addr().unwrap() == client_addr)
+            .unwrap();
+        stream.set_nonblocking(false).unwrap();
+        stream.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"hello");
+    }
+
+    #[test]
+    fn accept_one_at_a_time() {
+        let mut event_loop = EventLoop::<usize>::try_new().unwrap();
+        let mut listener = Listener::new(TcpListener::bind("127.0.0.1:0").unwrap()).unwrap();
+        listener.set_max_accepts(NonZeroUsize::new(1));
+        let addr = listener.get_ref().local_addr().unwrap();
+        event_loop
+            .handle()
+            .insert_source(listener, |_, _, accepted| *accepted += 1)
+            .unwrap();
+
+        let _clients: Vec<_> = (0..2).map(|_| TcpStream::connect(addr).unwrap()).collect();
+
+        let mut accepted = 0;
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut accepted)
+            .unwrap();
+        assert_eq!(accepted, 1);
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut accepted)
+            .unwrap();
+        assert_eq!(accepted, 2);
+
+        // Nothing is left pending, so the next dispatch waits for the whole timeout.
+        let start = std::time::Instant::now();
+        event_loop
+            .dispatch(Duration::from_millis(50), &mut accepted)
+            .unwrap();
+        assert_eq!(accepted, 2);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn accept_unix() {
+        let path = std::env::temp_dir().join(format!("calloop-listener-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut event_loop = EventLoop::<usize>::try_new().unwrap();
+        let listener = Listener::new(UnixListener::bind(&path).unwrap()).unwrap();
+        event_loop
+            .handle()
+            .insert_source(listener, |(_stream, addr), _, accepted| {
+                // The client socket is not bound to a path.
+                assert!(addr.as_pathname().is_none());
+                *accepted += 1;
+            })
+            .unwrap();
+
+        let _client = UnixStream::connect(&path).unwrap();
+        let mut accepted = 0;
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut accepted)
+            .unwrap();
+        assert_eq!(accepted, 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// A listener that runs out of file descriptors on demand.
+    #[derive(Debug)]
+    struct LimitedListener {
+        inner: TcpListener,
+        out_of_fds: Rc<Cell<bool>>,
+    }
+
+    impl AsFd for LimitedListener {
+        fn as_fd(&self) -> io_lifetimes::BorrowedFd<'_> {
+            self.inner.as_fd()
+        }
+    }
+
+    impl Accept for LimitedListener {
+        type Stream = TcpStream;
+        type Addr = SocketAddr;
+
+        fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+            if self.out_of_fds.get() {
+                Err(io::Error::from_raw_os_error(nix::libc::EMFILE))
+            } else {
+                self.inner.accept()
+            }
+        }
+
+        fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+            self.inner.set_nonblocking(nonblocking)
+        }
+
+        fn set_stream_nonblocking(stream: &TcpStream, nonblocking: bool) -> io::Result<()> {
+            stream.set_nonblocking(nonblocking)
+        }
+    }
+
+    #[test]
+    fn fd_limit_backoff() {
+        let clock = ManualClock::new();
+        let mut event_loop = EventLoop::<usize>::with_clock(clock.clone()).unwrap();
+        let out_of_fds = Rc::new(Cell::new(true));
+        let inner = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = inner.local_addr().unwrap();
+        let mut listener = Listener::new(LimitedListener {
+            inner,
+            out_of_fds: out_of_fds.clone(),
+        })
+        .unwrap();
+        listener.set_fd_limit_backoff(Duration::from_secs(1));
+        let dispatcher = crate::Dispatcher::new(listener, |_, _, accepted: &mut usize| {
+            *accepted += 1;
+        });
+        event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+
+        let _client = TcpStream::connect(addr).unwrap();
+        let mut accepted = 0;
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut accepted)
+            .unwrap();
+        assert!(dispatcher.as_source_ref().is_paused());
+
+        // The pending connection does not wake the loop up while paused.
+        out_of_fds.set(false);
+        event_loop
+            .dispatch(Duration::from_millis(10), &mut accepted)
+            .unwrap();
+        assert_eq!(accepted, 0);
+        assert!(dispatcher.as_source_ref().is_paused());
+
+        clock.advance(Duration::from_secs(1));
+        event_loop.dispatch(Duration::ZERO, &mut accepted).unwrap();
+        assert!(!dispatcher.as_source_ref().is_paused());
+        event_loop
+            .dispatch(Duration::from_millis(100), &mut accepted)
+            .unwrap();
+        assert_eq!(accepted, 1);
+    }
+}
@@ -0,0 +1,1193 @@
+//! Event sources for running and waiting on child processes
+//!
+//! Only available on Linux.
+//!
+//! The [`Subprocess`] source, spawned by a [`Command`], runs a child process, streams its
+//! standard output and error to the event loop, and then reports its exit status. Its callback can
+//! write to the standard input of the child or kill it through the [`Control`] it is given.
+//!
+//! ```no_run
+//! use calloop::{process::{Command, Event}, EventLoop};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut event_loop = EventLoop::<()>::try_new()?;
+//!
+//! let subprocess = Command::new("ls").arg("/").lines().spawn()?;
+//! event_loop
+//!     .handle()
+//!     .insert_source(subprocess, |event, _control, _| match event {
+//!         Event::Stdout(line) => println!("{}", String::from_utf8_lossy(&line)),
+//!         Event::Stderr(line) => eprintln!("{}", String::from_utf8_lossy(&line)),
+//!         Event::Exited(status) => println!("ls exited with {}", status),
+//!     })?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The [`ChildExit`] source delivers the [`ExitStatus`] of a child process once it exits. It uses
+//! a `pidfd` under the hood, which does not require masking `SIGCHLD` like the
+//! [`Signals`](crate::signals::Signals) source does.
+//!
+//! ```no_run
+//! use calloop::{process::ChildExit, EventLoop};
+//!
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut event_loop = EventLoop::<()>::try_new()?;
+//!
+//! let child = std::process::Command::new("true").spawn()?;
+//! event_loop
+//!     .handle()
+//!     .insert_source(ChildExit::new(&child)?, |status, _, _| {
+//!         println!("child exited with {}", status);
+//!     })?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`ChildExit`] does not collect the exit status of the child: it stays a zombie until its owner
+//! does so with [`Child::wait`] or [`Child::try_wait`], which return right away once the source
+//! has reported it. This keeps its process ID from being reused in the meantime. A
+//! [`Subprocess`] owns its child, and collects its exit status itself.
+//!
+//! On kernels without `pidfd` support (before Linux 5.3), the source falls back to a `SIGCHLD`
+//! handler shared by all the sources of this module. This handler is installed when
+//! the first such source is created, and calls the handler that was previously installed, if
+//! any. An ignored `SIGCHLD` is no longer ignored once it is installed, as the kernel would
+//! otherwise collect the exit status of the children on its own.
+
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    io::{self, Read, Write},
+    os::{
+        raw::{c_int, c_void},
+        unix::{
+            io::{AsRawFd, FromRawFd, RawFd},
+            process::ExitStatusExt,
+        },
+    },
+    path::Path,
+    process::{Child, ChildStderr, ChildStdin, ChildStdout, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicI32, AtomicUsize, Ordering},
+        Mutex, Once,
+    },
+};
+
+use io_lifetimes::{AsFd, OwnedFd};
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg, OFlag},
+    libc,
+    sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet},
+    unistd::{pipe2, read, Pid},
+};
+
+pub use nix::sys::signal::Signal;
+
+use super::{
+    generic::Generic,
+    ping::{make_ping, Ping, PingSource},
+};
+use crate::{EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory};
+
+/// An event source delivering the exit status of a child process
+///
+/// The source generates a single event, once the child has exited, and then removes itself from
+/// the event loop.
+///
+/// The exit status is left for the owner of the [`Child`] to collect. If it is collected before
+/// the source reports it, the source fails instead.
+#[derive(Debug)]
+pub struct ChildExit {
+    pid: libc::pid_t,
+    notifier: Notifier,
+    exited: bool,
+}
+
+#[derive(Debug)]
+enum Notifier {
+    /// A `pidfd`, which becomes readable when the child exits.
+    PidFd(Generic<OwnedFd>),
+    /// A ping from the shared `SIGCHLD` reaper.
+    Reaper { source: PingSource, id: u64 },
+}
+
+impl ChildExit {
+    /// Create a source waiting for a child process to exit
+    ///
+    /// Fails if the exit status of the child has already been collected, for example by
+    /// [`Child::try_wait`].
+    pub fn new(child: &Child) -> crate::Result<ChildExit> {
+        let pid = child.id() as libc::pid_t;
+        match pidfd_open(pid) {
+            Ok(fd) => Ok(ChildExit {
+                pid,
+                notifier: Notifier::PidFd(Generic::new(fd, Interest::READ, Mode::Level)),
+                exited: false,
+            }),
+            // `pidfd_open` is not supported by this kernel, or forbidden by a seccomp filter
+            // unaware of it.
+            Err(e) if matches!(e.raw_os_error(), Some(libc::ENOSYS) | Some(libc::EPERM)) => {
+                ChildExit::with_reaper(pid)
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Create a source notified by the shared `SIGCHLD` reaper.
+    fn with_reaper(pid: libc::pid_t) -> crate::Result<ChildExit> {
+        let (ping, source) = make_ping()?;
+        // The child may have exited before the reaper started listening: check it on the first
+        // dispatch.
+        ping.ping();
+        let id = reaper::add(ping)?;
+        Ok(ChildExit {
+            pid,
+            notifier: Notifier::Reaper { source, id },
+            exited: false,
+        })
+    }
+
+    /// The process ID of the child
+    pub fn pid(&self) -> u32 {
+        self.pid as u32
+    }
+
+    /// Get the exit status of the child, if it has exited, without collecting it.
+    fn try_wait(&self) -> io::Result<Option<ExitStatus>> {
+        // Through the `pidfd`, the process ID cannot be mistaken for a new process if the child
+        // has been collected behind our back.
+        if let Notifier::PidFd(ref fd) = self.notifier {
+            match waitid(libc::P_PIDFD, fd.file.as_raw_fd() as libc::id_t) {
+                // `waitid` only accepts a `pidfd` since Linux 5.4, one release after
+                // `pidfd_open`.
+                Err(e) if e.raw_os_error() == Some(libc::EINVAL) => {}
+                result => return result,
+            }
+        }
+        waitid(libc::P_PID, self.pid as libc::id_t)
+    }
+}
+
+impl Drop for ChildExit {
+    fn drop(&mut self) {
+        if let Notifier::Reaper { id, .. } = self.notifier {
+            reaper::remove(id);
+        }
+    }
+}
+
+impl EventSource for ChildExit {
+    type Event = ExitStatus;
+    type Metadata = ();
+    type Ret = ();
+    type Error = ChildExitError;
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> Result<PostAction, Self::Error>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        if self.exited {
+            return Ok(PostAction::Remove);
+        }
+
+        let mut notified = false;
+        match self.notifier {
+            Notifier::PidFd(ref mut fd) => {
+                fd.process_events(readiness, token, |_, _| {
+                    notified = true;
+                    Ok(PostAction::Continue)
+                })
+                .map_err(|e| ChildExitError(e.into()))?;
+            }
+            Notifier::Reaper { ref mut source, .. } => {
+                source
+                    .process_events(readiness, token, |(), &mut ()| notified = true)
+                    .map_err(|e| ChildExitError(e.into()))?;
+            }
+        }
+        if !notified {
+            return Ok(PostAction::Continue);
+        }
+
+        match self.try_wait() {
+            Ok(Some(status)) => {
+                self.exited = true;
+                callback(status, &mut ());
+                Ok(PostAction::Remove)
+            }
+            // The reaper wakes every source up on each `SIGCHLD`, this was another child.
+            Ok(None) => Ok(PostAction::Continue),
+            Err(e) => Err(ChildExitError(e.into())),
+        }
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> crate::Result<()> {
+        match self.notifier {
+            Notifier::PidFd(ref mut fd) => fd.register(poll, token_factory),
+            Notifier::Reaper { ref mut source, .. } => source.register(poll, token_factory),
+        }
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> crate::Result<()> {
+        match self.notifier {
+            Notifier::PidFd(ref mut fd) => fd.reregister(poll, token_factory),
+            Notifier::Reaper { ref mut source, .. } => source.reregister(poll, token_factory),
+        }
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> crate::Result<()> {
+        match self.notifier {
+            Notifier::PidFd(ref mut fd) => fd.unregister(poll),
+            Notifier::Reaper { ref mut source, .. } => source.unregister(poll),
+        }
+    }
+}
+
+/// An error arising from processing events for a child process.
+#[derive(thiserror::Error, Debug)]
+#[error(transparent)]
+pub struct ChildExitError(Box<dyn std::error::Error + Sync + Send>);
+
+/// A builder for a [`Subprocess`]
+///
+/// This mirrors [`std::process::Command`], which can also be converted into it. The standard
+/// output and error of the child are always piped to the [`Subprocess`]. Its standard input is
+/// only piped if [`pipe_stdin()`](Command::pipe_stdin) is used, and is `/dev/null` otherwise.
+#[derive(Debug)]
+pub struct Command {
+    inner: std::process::Command,
+    pipe_stdin: bool,
+    lines: bool,
+}
+
+impl Command {
+    /// Create a builder for running `program`
+    pub fn new(program: impl AsRef<OsStr>) -> Command {
+        std::process::Command::new(program).into()
+    }
+
+    /// Add an argument to pass to the program
+    pub fn arg(&mut self, arg: impl AsRef<OsStr>) -> &mut Command {
+        self.inner.arg(arg);
+        self
+    }
+
+    /// Add multiple arguments to pass to the program
+    pub fn args<I, S>(&mut self, args: I) -> &mut Command
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.inner.args(args);
+        self
+    }
+
+    /// Set an environment variable for the program
+    pub fn env(&mut self, key: impl AsRef<OsStr>, value: impl AsRef<OsStr>) -> &mut Command {
+        self.inner.env(key, value);
+        self
+    }
+
+    /// Remove an environment variable for the program
+    pub fn env_remove(&mut self, key: impl AsRef<OsStr>) -> &mut Command {
+        self.inner.env_remove(key);
+        self
+    }
+
+    /// Clear the environment of the program
+    pub fn env_clear(&mut self) -> &mut Command {
+        self.inner.env_clear();
+        self
+    }
+
+    /// Set the working directory of the program
+    pub fn current_dir(&mut self, dir: impl AsRef<Path>) -> &mut Command {
+        self.inner.current_dir(dir);
+        self
+    }
+
+    /// Pipe the standard input of the child, to write to it through [`Control::write_stdin()`]
+    pub fn pipe_stdin(&mut self) -> &mut Command {
+        self.pipe_stdin = true;
+        self
+    }
+
+    /// Split the output of the child into lines
+    ///
+    /// Each [`Event::Stdout`] and [`Event::Stderr`] then contains a single line, without its
+    /// trailing newline. Otherwise, they contain the output of the child as it is read.
+    pub fn lines(&mut self) -> &mut Command {
+        self.lines = true;
+        self
+    }
+
+    /// Spawn the child process
+    pub fn spawn(&mut self) -> crate::Result<Subprocess> {
+        let stdin = if self.pipe_stdin {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        };
+        let mut child = self
+            .inner
+            .stdin(stdin)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let setup = (|| -> crate::Result<_> {
+            let stdin = child.stdin.take().map(non_blocking).transpose()?;
+            let stdout = child.stdout.take().map(non_blocking).transpose()?;
+            let stderr = child.stderr.take().map(non_blocking).transpose()?;
+            let exit = ChildExit::new(&child)?;
+            Ok((stdin, stdout, stderr, exit))
+        })();
+        let (stdin, stdout, stderr, exit) = match setup {
+            Ok(setup) => setup,
+            Err(err) => {
+                // Do not leave the child running, nor as a zombie once it exits.
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(err);
+            }
+        };
+
+        Ok(Subprocess {
+            stdout: stdout.map(OutputPipe::new),
+            stderr: stderr.map(OutputPipe::new),
+            exit: Some(exit),
+            status: None,
+            lines: self.lines,
+            control: Control {
+                pid: child.id(),
+                exited: false,
+                stdin: stdin.map(|stdin| Generic::new(stdin, Interest::EMPTY, Mode::Level)),
+                stdin_buffer: Vec::new(),
+                close_stdin: false,
+            },
+            child,
+        })
+    }
+}
+
+impl From<std::process::Command> for Command {
+    fn from(command: std::process::Command) -> Command {
+        Command {
+            inner: command,
+            pipe_stdin: false,
+            lines: false,
+        }
+    }
+}
+
+/// An event generated by a [`Subprocess`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Event {
+    /// Output of the child on its standard output
+    Stdout(Vec<u8>),
+    /// Output of the child on its standard error
+    Stderr(Vec<u8>),
+    /// The child exited
+    ///
+    /// This is the last event of the subprocess, which is then removed from the event loop.
+    Exited(ExitStatus),
+}
+
+/// An event source running a child process and streaming its output
+///
+/// It is created by a [`Command`]. The [`Event::Exited`] event is only generated once the child
+/// has exited and its standard output and error have been read until their end, so it always
+/// comes after the whole output of the child. If the child left processes of its own holding
+/// them open, it is delayed until they close them too.
+#[derive(Debug)]
+pub struct Subprocess {
+    /// Collected once the exit of the child has been reported.
+    child: Child,
+    stdout: Option<OutputPipe<ChildStdout>>,
+    stderr: Option<OutputPipe<ChildStderr>>,
+    /// Removed once the exit status has been collected.
+    exit: Option<ChildExit>,
+    status: Option<ExitStatus>,
+    lines: bool,
+    control: Control,
+}
+
+impl Subprocess {
+    /// The process ID of the child
+    pub fn pid(&self) -> u32 {
+        self.control.pid
+    }
+
+    /// Access the controls of the child outside of the callback of the source
+    ///
+    /// If you write to the standard input of the child from here while the source is in an
+    /// event loop, use [`LoopHandle::update()`](crate::LoopHandle::update) afterwards so that
+    /// the source waits for it to be writable.
+    pub fn control(&mut self) -> &mut Control {
+        &mut self.control
+    }
+
+    fn needs_reregister(&self) -> bool {
+        self.stdout.as_ref().map_or(false, |pipe| pipe.closed)
+            || self.stderr.as_ref().map_or(false, |pipe| pipe.closed)
+            || (self.status.is_some() && self.exit.is_some())
+            || self.control.needs_reregister()
+    }
+
+    /// Unregister and close the parts of the source that are done.
+    fn close_finished(&mut self, poll: &mut Poll) -> crate::Result<()> {
+        close_pipe(&mut self.stdout, poll)?;
+        close_pipe(&mut self.stderr, poll)?;
+        if self.status.is_some() {
+            if let Some(mut exit) = self.exit.take() {
+                exit.unregister(poll)?;
+            }
+        }
+        if self.control.stdin.is_some() && self.control.stdin_finished() {
+            if let Some(mut stdin) = self.control.stdin.take() {
+                stdin.unregister(poll)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl EventSource for Subprocess {
+    type Event = Event;
+    type Metadata = Control;
+    type Ret = ();
+    type Error = SubprocessError;
+
+    fn process_events<C>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: C,
+    ) -> Result<PostAction, Self::Error>
+    where
+        C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let Subprocess {
+            ref mut child,
+            ref mut stdout,
+            ref mut stderr,
+            ref mut exit,
+            ref mut status,
+            lines,
+            ref mut control,
+        } = *self;
+
+        if let Some(pipe) = stdout {
+            pipe.process_events(readiness, token, lines, |data| {
+                callback(Event::Stdout(data), control)
+            })
+            .map_err(|e| SubprocessError(e.into()))?;
+        }
+        if let Some(pipe) = stderr {
+            pipe.process_events(readiness, token, lines, |data| {
+                callback(Event::Stderr(data), control)
+            })
+            .map_err(|e| SubprocessError(e.into()))?;
+        }
+        if let Some(exit) = exit {
+            let mut exited = false;
+            exit.process_events(readiness, token, |_, &mut ()| exited = true)
+                .map_err(|e| SubprocessError(e.into()))?;
+            if exited {
+                // The child has exited, this does not block.
+                *status = Some(child.wait().map_err(|e| SubprocessError(e.into()))?);
+                control.exited = true;
+            }
+        }
+        control
+            .process_events(readiness, token)
+            .map_err(|e| SubprocessError(e.into()))?;
+
+        let output_closed = stdout.as_ref().map_or(true, |pipe| pipe.closed)
+            && stderr.as_ref().map_or(true, |pipe| pipe.closed);
+        if let (Some(status), true) = (*status, output_closed) {
+            callback(Event::Exited(status), control);
+            return Ok(PostAction::Remove);
+        }
+
+        if self.needs_reregister() {
+            Ok(PostAction::Reregister)
+        } else {
+            Ok(PostAction::Continue)
+        }
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> crate::Result<()> {
+        if let Some(pipe) = &mut self.stdout {
+            pipe.fd.register(poll, token_factory)?;
+        }
+        if let Some(pipe) = &mut self.stderr {
+            pipe.fd.register(poll, token_factory)?;
+        }
+        if let Some(exit) = &mut self.exit {
+            exit.register(poll, token_factory)?;
+        }
+        let interest = self.control.stdin_interest();
+        if let Some(stdin) = &mut self.control.stdin {
+            stdin.interest = interest;
+            stdin.register(poll, token_factory)?;
+        }
+        Ok(())
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> crate::Result<()> {
+        self.close_finished(poll)?;
+        if let Some(pipe) = &mut self.stdout {
+            pipe.fd.reregister(poll, token_factory)?;
+        }
+        if let Some(pipe) = &mut self.stderr {
+            pipe.fd.reregister(poll, token_factory)?;
+        }
+        if let Some(exit) = &mut self.exit {
+            exit.reregister(poll, token_factory)?;
+        }
+        let interest = self.control.stdin_interest();
+        if let Some(stdin) = &mut self.control.stdin {
+            stdin.interest = interest;
+            stdin.reregister(poll, token_factory)?;
+        }
+        Ok(())
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> crate::Result<()> {
+        if let Some(pipe) = &mut self.stdout {
+            pipe.fd.unregister(poll)?;
+        }
+        if let Some(pipe) = &mut self.stderr {
+            pipe.fd.unregister(poll)?;
+        }
+        if let Some(exit) = &mut self.exit {
+            exit.unregister(poll)?;
+        }
+        if let Some(stdin) = &mut self.control.stdin {
+            stdin.unregister(poll)?;
+        }
+        // Everything is unregistered already, the finished parts can be closed directly.
+        self.stdout = self.stdout.take().filter(|pipe| !pipe.closed);
+        self.stderr = self.stderr.take().filter(|pipe| !pipe.closed);
+        if self.status.is_some() {
+            self.exit = None;
+        }
+        if self.control.stdin_finished() {
+            self.control.stdin = None;
+        }
+        Ok(())
+    }
+}
+
+/// An error arising from processing events for a subprocess.
+#[derive(thiserror::Error, Debug)]
+#[error(transparent)]
+pub struct SubprocessError(Box<dyn std::error::Error + Sync + Send>);
+
+/// Control over a child process run by a [`Subprocess`]
+///
+/// This is the metadata of the [`Subprocess`] source, given to its callback.
+#[derive(Debug)]
+pub struct Control {
+    pid: u32,
+    exited: bool,
+    stdin: Option<Generic<ChildStdin>>,
+    /// The data waiting for stdin to be writable.
+    stdin_buffer: Vec<u8>,
+    close_stdin: bool,
+}
+
+impl Control {
+    /// The process ID of the child
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Write to the standard input of the child
+    ///
+    /// The data the child is not ready to receive yet is buffered, and written when its standard
+    /// input becomes writable.
+    ///
+    /// Fails if the standard input of the child was not piped with [`Command::pipe_stdin()`], or
+    /// has been closed, either by [`close_stdin()`](Self::close_stdin) or by the child itself.
+    pub fn write_stdin(&mut self, data: &[u8]) -> io::Result<()> {
+        let stdin = match self.stdin {
+            Some(ref mut stdin) if !self.close_stdin => stdin,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "the standard input of the child is closed",
+                ))
+            }
+        };
+        if !self.stdin_buffer.is_empty() {
+            self.stdin_buffer.extend_from_slice(data);
+            return Ok(());
+        }
+
+        let mut written = 0;
+        while written < data.len() {
+            match stdin.file.write(&data[written..]) {
+                Ok(n) => written += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    // The child closed its standard input.
+                    self.close_stdin = true;
+                    return Err(e);
+                }
+            }
+        }
+        self.stdin_buffer.extend_from_slice(&data[written..]);
+        Ok(())
+    }
+
+    /// Close the standard input of the child
+    ///
+    /// It is closed once the data buffered by [`write_stdin()`](Self::write_stdin) has been
+    /// written.
+    pub fn close_stdin(&mut self) {
+        self.close_stdin = true;
+    }
+
+    /// Kill the child with `SIGKILL`
+    ///
+    /// Does nothing if the child has already exited.
+    pub fn kill(&mut self) -> io::Result<()> {
+        self.signal(Signal::SIGKILL)
+    }
+
+    /// Send a signal to the child
+    ///
+    /// Does nothing if the child has already exited.
+    pub fn signal(&mut self, signal: Signal) -> io::Result<()> {
+        // Once its exit status has been collected, the process ID may be reused.
+        if self.exited {
+            return Ok(());
+        }
+        nix::sys::signal::kill(Pid::from_raw(self.pid as libc::pid_t), signal)?;
+        Ok(())
+    }
+
+    fn stdin_interest(&self) -> Interest {
+        if self.stdin_buffer.is_empty() {
+            Interest::EMPTY
+        } else {
+            Interest::WRITE
+        }
+    }
+
+    fn stdin_finished(&self) -> bool {
+        self.close_stdin && self.stdin_buffer.is_empty()
+    }
+
+    fn needs_reregister(&self) -> bool {
+        match self.stdin {
+            Some(ref stdin) => {
+                self.stdin_finished() || stdin.interest.writable == self.stdin_buffer.is_empty()
+            }
+            None => false,
+        }
+    }
+
+    /// Write the buffered data to stdin, if it is writable, or close it if the child did.
+    fn process_events(&mut self, readiness: Readiness, token: Token) -> io::Result<()> {
+        let Control {
+            ref mut stdin,
+            ref mut stdin_buffer,
+            ref mut close_stdin,
+            ..
+        } = *self;
+        let stdin = match stdin {
+            Some(stdin) => stdin,
+            None => return Ok(()),
+        };
+        stdin.process_events(readiness, token, |readiness, file| {
+            if readiness.error || readiness.hangup {
+                // The child closed its standard input. This is reported even without interest,
+                // so stdin has to be closed for the loop not to be woken up over and over.
+                stdin_buffer.clear();
+                *close_stdin = true;
+                return Ok(PostAction::Continue);
+            }
+            while !stdin_buffer.is_empty() {
+                match file.write(stdin_buffer) {
+                    Ok(n) => {
+                        stdin_buffer.drain(..n);
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => {
+                        // The child closed its standard input, the rest can't be written.
+                        log::warn!("[calloop] Failed to write to the stdin of a child: {}", e);
+                        stdin_buffer.clear();
+                        *close_stdin = true;
+                    }
+                }
+            }
+            Ok(PostAction::Continue)
+        })?;
+        Ok(())
+    }
+}
+
+/// The standard output or error of a [`Subprocess`].
+#[derive(Debug)]
+struct OutputPipe<F: AsFd> {
+    fd: Generic<F>,
+    /// The incomplete line being read, in line mode.
+    buffer: Vec<u8>,
+    /// Whether the end of the pipe was reached.
+    closed: bool,
+}
+
+impl<F: AsFd + Read> OutputPipe<F> {
+    fn new(file: F) -> OutputPipe<F> {
+        OutputPipe {
+            fd: Generic::new(file, Interest::READ, Mode::Level),
+            buffer: Vec::new(),
+            closed: false,
+        }
+    }
+
+    fn process_events(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        lines: bool,
+        mut emit: impl FnMut(Vec<u8>),
+    ) -> io::Result<()> {
+        let OutputPipe {
+            ref mut fd,
+            ref mut buffer,
+            ref mut closed,
+        } = *self;
+        fd.process_events(readiness, token, |_, file| {
+            let mut chunk = [0u8; 4096];
+            loop {
+                match file.read(&mut chunk) {
+                    Ok(0) => {
+                        *closed = true;
+                        break;
+                    }
+                    Ok(n) if lines => {
+                        buffer.extend_from_slice(&chunk[..n]);
+                        while let Some(end) = buffer.iter().position(|&byte| byte == b'\n') {
+                            let mut line: Vec<u8> = buffer.drain(..=end).collect();
+                            line.pop();
+                            emit(line);
+                        }
+                    }
+                    Ok(n) => emit(chunk[..n].to_vec()),
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e),
+                }
+            }
+            Ok(PostAction::Continue)
+        })?;
+
+        if *closed && !buffer.is_empty() {
+            // The last line had no trailing newline.
+            emit(std::mem::take(buffer));
+        }
+        Ok(())
+    }
+}
+
+fn close_pipe<F: AsFd>(pipe: &mut Option<OutputPipe<F>>, poll: &mut Poll) -> crate::Result<()> {
+    if pipe.as_ref().map_or(false, |pipe| pipe.closed) {
+        if let Some(mut closed) = pipe.take() {
+            closed.fd.unregister(poll)?;
+        }
+    }
+    Ok(())
+}
+
+fn non_blocking<F: AsRawFd>(file: F) -> io::Result<F> {
+    let flags = OFlag::from_bits_truncate(fcntl(file.as_raw_fd(), FcntlArg::F_GETFL)?);
+    fcntl(
+        file.as_raw_fd(),
+        FcntlArg::F_SETFL(flags | OFlag::O_NONBLOCK),
+    )?;
+    Ok(file)
+}
+
+/// Get the exit status of a child, if it has exited, leaving it to be collected.
+fn waitid(idtype: libc::idtype_t, id: libc::id_t) -> io::Result<Option<ExitStatus>> {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+    if unsafe { libc::waitid(idtype, id, &mut info, flags) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // With `WNOHANG`, the child has not exited yet if nothing was written.
+    if unsafe { info.si_pid() } == 0 {
+        return Ok(None);
+    }
+
+    // Encode the status the way `waitpid` does, which `ExitStatus` expects.
+    let status = unsafe { info.si_status() };
+    Ok(Some(ExitStatus::from_raw(match info.si_code {
+        libc::CLD_EXITED => (status & 0xff) << 8,
+        libc::CLD_DUMPED => status | 0x80,
+        _ => status,
+    })))
+}
+
+fn pidfd_open(pid: libc::pid_t) -> io::Result<OwnedFd> {
+    // The file descriptor is always created with `O_CLOEXEC`.
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+/// The `SIGCHLD` handler shared by the sources that cannot use a `pidfd`.
+///
+/// The signal handler writes to a pipe, and a thread reading from it pings every registered
+/// source, which then checks whether its own child has exited.
+mod reaper {
+    use super::*;
+
+    static INIT: Once = Once::new();
+    static INIT_ERROR: Mutex<Option<io::ErrorKind>> = Mutex::new(None);
+    static WAITERS: Mutex<Option<Waiters>> = Mutex::new(None);
+
+    /// The write end of the pipe, for the signal handler.
+    static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+    /// The handler that was installed before ours, as a `SigHandler::Handler`.
+    static PREV_HANDLER: AtomicUsize = AtomicUsize::new(0);
+    /// The handler that was installed before ours, as a `SigHandler::SigAction`.
+    static PREV_SIGACTION: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Default)]
+    struct Waiters {
+        next_id: u64,
+        pings: HashMap<u64, Ping>,
+    }
+
+    pub(super) fn add(ping: Ping) -> io::Result<u64> {
+        INIT.call_once(|| {
+            if let Err(e) = start() {
+                log::warn!("[calloop] Failed to start the SIGCHLD reaper: {}", e);
+                *INIT_ERROR.lock().unwrap() = Some(e.kind());
+            }
+        });
+        if let Some(kind) = *INIT_ERROR.lock().unwrap() {
+            return Err(io::Error::new(
+                kind,
+                "the SIGCHLD reaper could not be started",
+            ));
+        }
+
+        let mut waiters = WAITERS.lock().unwrap();
+        let waiters = waiters.get_or_insert_with(Waiters::default);
+        let id = waiters.next_id;
+        waiters.next_id += 1;
+        waiters.pings.insert(id, ping);
+        Ok(id)
+    }
+
+    pub(super) fn remove(id: u64) {
+        if let Some(waiters) = WAITERS.lock().unwrap().as_mut() {
+            waiters.pings.remove(&id);
+        }
+    }
+
+    fn start() -> io::Result<()> {
+        let (read_fd, write_fd) = pipe2(OFlag::O_CLOEXEC)?;
+        // The signal handler must never block, the reader thread does.
+        fcntl(write_fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        SIGNAL_PIPE.store(write_fd, Ordering::Release);
+
+        std::thread::Builder::new()
+            .name("calloop-reaper".into())
+            .spawn(move || run(read_fd))?;
+
+        let action = SigAction::new(
+            SigHandler::SigAction(on_sigchld),
+            SaFlags::SA_SIGINFO | SaFlags::SA_RESTART | SaFlags::SA_NOCLDSTOP,
+            SigSet::empty(),
+        );
+        let previous = unsafe { sigaction(Signal::SIGCHLD, &action)? };
+        match previous.handler() {
+            SigHandler::Handler(handler) => {
+                PREV_HANDLER.store(handler as usize, Ordering::Release);
+            }
+            SigHandler::SigAction(handler) => {
+                PREV_SIGACTION.store(handler as usize, Ordering::Release);
+            }
+            SigHandler::SigDfl | SigHandler::SigIgn => {}
+        }
+        Ok(())
+    }
+
+    fn run(read_fd: RawFd) {
+        let mut buffer = [0u8; 64];
+        loop {
+            match read(read_fd, &mut buffer) {
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(e) => {
+                    log::warn!(
+                        "[calloop] Error reading from the SIGCHLD reaper pipe: {}",
+                        e
+                    );
+                    return;
+                }
+            }
+            if let Some(waiters) = WAITERS.lock().unwrap().as_ref() {
+                for ping in waiters.pings.values() {
+                    ping.ping();
+                }
+            }
+        }
+    }
+
+    extern "C" fn on_sigchld(signal: c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+        let errno = unsafe { *libc::__errno_location() };
+        // If the pipe is full, the reader thread has not woken up yet anyway.
+        let _ = unsafe { libc::write(SIGNAL_PIPE.load(Ordering::Acquire), [0u8].as_ptr() as _, 1) };
+        unsafe { *libc::__errno_location() = errno };
+
+        let previous = PREV_SIGACTION.load(Ordering::Acquire);
+        if previous != 0 {
+            let previous: extern "C" fn(c_int, *mut libc::siginfo_t, *mut c_void) =
+                unsafe { std::mem::transmute(previous) };
+            previous(signal, info, context);
+        }
+        let previous = PREV_HANDLER.load(Ordering::Acquire);
+        if previous != 0 {
+            let previous: extern "C" fn(c_int) = unsafe { std::mem::transmute(previous) };
+            previous(signal);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::EventLoop;
+
+    fn wait_for_exit(source: ChildExit) -> ExitStatus {
+        let mut event_loop = EventLoop::<Option<ExitStatus>>::try_new().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |status, _, result| *result = Some(status))
+            .unwrap();
+
+        let mut result = None;
+        for _ in 0..50 {
+            event_loop
+                .dispatch(Duration::from_millis(100), &mut result)
+                .unwrap();
+            if let Some(status) = result {
+                return status;
+            }
+        }
+        panic!("The child exit was not reported");
+    }
+
+    #[test]
+    fn child_exit() {
+        let mut child = std::process::Command::new("sh")
+            .args(["-c", "exit 3"])
+            .spawn()
+            .unwrap();
+        let status = wait_for_exit(ChildExit::new(&child).unwrap());
+        assert_eq!(status.code(), Some(3));
+
+        // The exit status is left for the owner of the child.
+        assert_eq!(child.try_wait().unwrap(), Some(status));
+    }
+
+    #[test]
+    fn child_killed() {
+        let mut child = std::process::Command::new("sleep")
+            .arg("10")
+            .spawn()
+            .unwrap();
+        let source = ChildExit::new(&child).unwrap();
+        child.kill().unwrap();
+        let status = wait_for_exit(source);
+        assert_eq!(status.signal(), Some(libc::SIGKILL));
+        assert_eq!(child.wait().unwrap(), status);
+    }
+
+    #[test]
+    fn child_exit_collected_by_owner() {
+        let mut child = std::process::Command::new("true").spawn().unwrap();
+        let source = ChildExit::new(&child).unwrap();
+        child.wait().unwrap();
+
+        let mut event_loop = EventLoop::<()>::try_new().unwrap();
+        event_loop
+            .handle()
+            .insert_source(source, |_, _, _| panic!("The exit was already collected"))
+            .unwrap();
+        assert!(event_loop
+            .dispatch(Duration::from_secs(5), &mut ())
+            .is_err());
+    }
+
+    #[test]
+    fn reaper_fallback() {
+        let mut fast = std::process::Command::new("true").spawn().unwrap();
+        let mut slow = std::process::Command::new("sh")
+            .args(["-c", "sleep 0.2; exit 4"])
+            .spawn()
+            .unwrap();
+        let fast_source = ChildExit::with_reaper(fast.id() as libc::pid_t).unwrap();
+        let slow_source = ChildExit::with_reaper(slow.id() as libc::pid_t).unwrap();
+
+        assert!(wait_for_exit(fast_source).success());
+        assert_eq!(wait_for_exit(slow_source).code(), Some(4));
+        assert!(fast.wait().unwrap().success());
+        assert_eq!(slow.wait().unwrap().code(), Some(4));
+    }
+
+    fn run_subprocess(subprocess: Subprocess) -> Vec<Event> {
+        let mut event_loop = EventLoop::<Vec<Event>>::try_new().unwrap();
+        event_loop
+            .handle()
+            .insert_source(subprocess, |event, _, events| events.push(event))
+            .unwrap();
+
+        let mut events = Vec::new();
+        for _ in 0..50 {
+            event_loop
+                .dispatch(Duration::from_millis(100), &mut events)
+                .unwrap();
+            if let Some(Event::Exited(_)) = events.last() {
+                return events;
+            }
+        }
+        panic!("The subprocess exit was not reported");
+    }
+
+    #[test]
+    fn subprocess_output() {
+        let subprocess = Command::new("sh")
+            .args(["-c", "echo one; echo two >&2; printf 'three\nfour'; exit 5"])
+            .lines()
+            .spawn()
+            .unwrap();
+        let events = run_subprocess(subprocess);
+
+        let stdout: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Stdout(line) => Some(line.as_slice()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stdout, [&b"one"[..], b"three", b"four"]);
+        assert!(events.contains(&Event::Stderr(b"two".to_vec())));
+        match events.last() {
+            Some(Event::Exited(status)) => assert_eq!(status.code(), Some(5)),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn subprocess_stdin() {
+        let mut subprocess = Command::new("cat").pipe_stdin().spawn().unwrap();
+        // More than a pipe can hold, so that some of it is buffered.
+        let input = vec![b'a'; 256 * 1024];
+        subprocess.control().write_stdin(&input).unwrap();
+        subprocess.control().close_stdin();
+        assert!(subprocess.control().write_stdin(b"late").is_err());
+
+        let events = run_subprocess(subprocess);
+        let output: Vec<u8> = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Stdout(data) => Some(data.as_slice()),
+                _ => None,
+            })
+            .flatten()
+            .copied()
+            .collect();
+        assert_eq!(output, input);
+        assert_eq!(events.last(), Some(&Event::Exited(ExitStatus::from_raw(0))));
+    }
+
+    #[test]
+    fn subprocess_stdin_closed_by_child() {
+        let mut event_loop = EventLoop::<Option<ExitStatus>>::try_new().unwrap();
+        let subprocess = Command::new("sh")
+            .args(["-c", "exec 0<&-; sleep 0.5"])
+            .pipe_stdin()
+            .spawn()
+            .unwrap();
+        event_loop
+            .handle()
+            .insert_source(subprocess, |event, _, result| {
+                if let Event::Exited(status) = event {
+                    *result = Some(status);
+                }
+            })
+            .unwrap();
+
+        // The closed stdin does not wake the loop up while the child keeps running.
+        let mut result = None;
+        let mut dispatches = 0;
+        while result.is_none() && dispatches < 1000 {
+            event_loop
+                .dispatch(Duration::from_millis(100), &mut result)
+                .unwrap();
+            dispatches += 1;
+        }
+        assert_eq!(result, Some(ExitStatus::from_raw(0)));
+        assert!(dispatches < 50, "{} dispatches", dispatches);
+    }
+
+    #[test]
+    fn subprocess_kill() {
+        let mut event_loop = EventLoop::<Option<ExitStatus>>::try_new().unwrap();
+        let subprocess = Command::new("sh")
+            .args(["-c", "echo ready; exec sleep 10"])
+            .spawn()
+            .unwrap();
+        event_loop
+            .handle()
+            .insert_source(subprocess, |event, control, result| match event {
+                Event::Stdout(_) => control.kill().unwrap(),
+                Event::Stderr(_) => {}
+                Event::Exited(status) => *result = Some(status),
+            })
+            .unwrap();
+
+        let mut result = None;
+        for _ in 0..50 {
+            event_loop
+                .dispatch(Duration::from_millis(100), &mut result)
+                .unwrap();
+            if result.is_some() {
+                break;
+            }
+        }
+        assert_eq!(
+            result.and_then(|status| status.signal()),
+            Some(libc::SIGKILL)
+        );
+    }
+}
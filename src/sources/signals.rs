@@ -11,6 +11,7 @@
 //! they'll inherit their parent signal mask.
 
 use std::convert::TryFrom;
+use std::num::NonZeroUsize;
 use std::os::raw::c_int;
 
 use nix::sys::signal::SigSet;
@@ -44,6 +45,7 @@ impl Event {
 pub struct Signals {
     sfd: Generic<FdWrapper<SignalFd>>,
     mask: SigSet,
+    budget: Option<NonZeroUsize>,
 }
 
 impl Signals {
@@ -62,6 +64,7 @@ impl Signals {
         Ok(Signals {
             sfd: Generic::new(unsafe { FdWrapper::new(sfd) }, Interest::READ, Mode::Level),
             mask,
+            budget: None,
         })
     }
 
@@ -136,9 +139,12 @@ impl EventSource for Signals {
     where
         C: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
     {
+        let budget = self.budget;
         self.sfd
             .process_events(readiness, token, |_, sfd| {
-                loop {
+                // The signalfd is level-triggered: signals left unread because of the
+                // budget will wake the event loop up again.
+                for _ in 0..budget.map_or(usize::MAX, NonZeroUsize::get) {
                     match sfd.read_signal() {
                         Ok(Some(info)) => callback(Event { info }, &mut ()),
                         Ok(None) => break,
@@ -168,6 +174,10 @@ impl EventSource for Signals {
     fn unregister(&mut self, poll: &mut Poll) -> crate::Result<()> {
         self.sfd.unregister(poll)
     }
+
+    fn set_event_budget(&mut self, budget: Option<NonZeroUsize>) {
+        self.budget = budget;
+    }
 }
 
 /// An error arising from processing events for a process signal.
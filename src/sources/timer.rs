@@ -16,6 +16,9 @@
 //! The callback associated with this event source is expected to return a [`TimeoutAction`], which
 //! can be used to implement self-repeating timers by telling calloop to reprogram the same timer
 //! for a later timeout after it has fired.
+//!
+//! On Linux and Android, the [`ClockTimer`] event source can be used instead to follow the
+//! boot-time or realtime clocks, at the cost of a file descriptor per timer.
 
 /*
  * This module provides two main types:
@@ -27,19 +30,47 @@
 
 use std::{
     cell::RefCell,
-    collections::BinaryHeap,
     rc::Rc,
     task::Waker,
     time::{Duration, Instant},
 };
 
-use crate::{EventSource, LoopHandle, Poll, PostAction, Readiness, Token, TokenFactory};
+use slab::Slab;
+
+use crate::{
+    clock::LoopClock, loop_logic::MAX_SOURCES_MASK, EventSource, LoopHandle, Poll, PostAction,
+    Readiness, Token, TokenFactory,
+};
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod clock;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use clock::{Clock, ClockTimeoutAction, ClockTimer, ClockTimerEvent};
 
-#[derive(Debug)]
 struct Registration {
     token: Token,
     wheel: Rc<RefCell<TimerWheel>>,
-    counter: u32,
+    clock: Rc<dyn LoopClock>,
+    timeout: TimeoutId,
+}
+
+impl std::fmt::Debug for Registration {
+    #[cfg_attr(feature = "nightly_coverage", no_coverage)]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Registration")
+            .field("token", &self.token)
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The deadline of a `Timer`
+#[derive(Copy, Clone, Debug)]
+enum Deadline {
+    /// A fixed deadline, `None` if it has overflowed
+    At(Option<Instant>),
+    /// A deadline relative to the moment the timer is registered, on the clock of the event loop
+    After(Duration),
 }
 
 /// A timer event source
@@ -47,21 +78,29 @@ struct Registration {
 /// When registered to the event loop, it will trigger an event once its deadline is reached.
 /// If the deadline is in the past relative to the moment of its insertion in the event loop,
 /// the `TImer` will trigger an event as soon as the event loop is dispatched.
+///
+/// Deadlines are measured against the clock of the event loop, see the
+/// [`clock`](crate::clock) module.
 #[derive(Debug)]
 pub struct Timer {
     registration: Option<Registration>,
-    deadline: Option<Instant>,
+    deadline: Deadline,
 }
 
 impl Timer {
     /// Create a timer that will fire immediately when inserted in the event loop
     pub fn immediate() -> Timer {
-        Self::from_deadline(Instant::now())
+        Self::from_duration(Duration::ZERO)
     }
 
     /// Create a timer that will fire after a given duration from now
+    ///
+    /// The duration is counted from the moment the timer is inserted in the event loop.
     pub fn from_duration(duration: Duration) -> Timer {
-        Self::from_deadline_inner(Instant::now().checked_add(duration))
+        Timer {
+            registration: None,
+            deadline: Deadline::After(duration),
+        }
     }
 
     /// Create a timer that will fire at a given instant
@@ -72,7 +111,7 @@ impl Timer {
     fn from_deadline_inner(deadline: Option<Instant>) -> Timer {
         Timer {
             registration: None,
-            deadline,
+            deadline: Deadline::At(deadline),
         }
     }
 
@@ -81,22 +120,30 @@ impl Timer {
     /// If the `Timer` is currently registered in the event loop, it needs to be
     /// re-registered for this change to take effect.
     pub fn set_deadline(&mut self, deadline: Instant) {
-        self.deadline = Some(deadline);
+        self.deadline = Deadline::At(Some(deadline));
     }
 
     /// Changes the deadline of this timer to a [`Duration`] from now
     ///
-    /// If the `Timer` is currently registered in the event loop, it needs to be
-    /// re-registered for this change to take effect.
+    /// The duration is counted from the moment the timer is next registered in the event loop.
+    /// If the `Timer` is currently registered, re-register it for this change to take effect
+    /// right away, for example with [`LoopHandle::update()`](crate::LoopHandle::update).
+    /// Otherwise, the duration is counted from the moment its previous deadline is reached, and
+    /// its callback is not invoked for that deadline.
     pub fn set_duration(&mut self, duration: Duration) {
-        self.deadline = Instant::now().checked_add(duration);
+        self.deadline = Deadline::After(duration);
     }
 
     /// Get the current deadline of this `Timer`
     ///
-    /// Returns `None` if the timer has overflowed.
+    /// Returns `None` if the timer has overflowed, or if it was given a duration and has not
+    /// been registered since: its deadline is only set once the event loop counts the duration
+    /// from its clock.
     pub fn current_deadline(&self) -> Option<Instant> {
-        self.deadline
+        match self.deadline {
+            Deadline::At(deadline) => deadline,
+            Deadline::After(_) => None,
+        }
     }
 }
 
@@ -115,43 +162,68 @@ impl EventSource for Timer {
     where
         F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
     {
-        if let (Some(ref registration), Some(ref deadline)) = (&self.registration, &self.deadline) {
-            if registration.token != token {
-                return Ok(PostAction::Continue);
-            }
-            let new_deadline = match callback(*deadline, &mut ()) {
+        let registration = match self.registration {
+            Some(ref mut registration) if registration.token == token => registration,
+            _ => return Ok(PostAction::Continue),
+        };
+        if let Deadline::After(duration) = self.deadline {
+            // The duration was set while the timer was registered, and it was not reregistered
+            // since: count it from now.
+            return match registration.clock.now().checked_add(duration) {
+                Some(new_deadline) => {
+                    registration.timeout = registration
+                        .wheel
+                        .borrow_mut()
+                        .insert(new_deadline, registration.token);
+                    self.deadline = Deadline::At(Some(new_deadline));
+                    Ok(PostAction::Continue)
+                }
+                None => {
+                    self.deadline = Deadline::At(None);
+                    Ok(PostAction::Remove)
+                }
+            };
+        }
+        if let Deadline::At(Some(deadline)) = self.deadline {
+            let new_deadline = match callback(deadline, &mut ()) {
                 TimeoutAction::Drop => return Ok(PostAction::Remove),
                 TimeoutAction::ToInstant(instant) => instant,
-                TimeoutAction::ToDuration(duration) => match Instant::now().checked_add(duration) {
-                    Some(new_deadline) => new_deadline,
-                    None => {
-                        // The timer has overflowed, meaning we have no choice but to drop it.
-                        self.deadline = None;
-                        return Ok(PostAction::Remove);
+                TimeoutAction::ToDuration(duration) => {
+                    match registration.clock.now().checked_add(duration) {
+                        Some(new_deadline) => new_deadline,
+                        None => {
+                            // The timer has overflowed, meaning we have no choice but to drop it.
+                            self.deadline = Deadline::At(None);
+                            return Ok(PostAction::Remove);
+                        }
                     }
-                },
+                }
             };
-            // If we received an event, we MUST have a valid counter value
-            registration.wheel.borrow_mut().insert_reuse(
-                registration.counter,
-                new_deadline,
-                registration.token,
-            );
-            self.deadline = Some(new_deadline);
+            // The expired timeout has been removed from the wheel, insert the new one
+            registration.timeout = registration
+                .wheel
+                .borrow_mut()
+                .insert(new_deadline, registration.token);
+            self.deadline = Deadline::At(Some(new_deadline));
         }
         Ok(PostAction::Continue)
     }
 
     fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> crate::Result<()> {
+        if let Deadline::After(duration) = self.deadline {
+            self.deadline = Deadline::At(poll.clock.now().checked_add(duration));
+        }
+
         // Only register a deadline if we haven't overflowed.
-        if let Some(deadline) = self.deadline {
+        if let Deadline::At(Some(deadline)) = self.deadline {
             let wheel = poll.timers.clone();
             let token = token_factory.token();
-            let counter = wheel.borrow_mut().insert(deadline, token);
+            let timeout = wheel.borrow_mut().insert(deadline, token);
             self.registration = Some(Registration {
                 token,
                 wheel,
-                counter,
+                clock: poll.clock.clone(),
+                timeout,
             });
         }
 
@@ -169,7 +241,7 @@ impl EventSource for Timer {
 
     fn unregister(&mut self, poll: &mut Poll) -> crate::Result<()> {
         if let Some(registration) = self.registration.take() {
-            poll.timers.borrow_mut().cancel(registration.counter);
+            poll.timers.borrow_mut().cancel(registration.timeout);
         }
         Ok(())
     }
@@ -186,113 +258,183 @@ pub enum TimeoutAction {
     ToDuration(Duration),
 }
 
+/// The identifier of a timeout registered in a `TimerWheel`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct TimeoutId {
+    /// The key of the timeout in the slab.
+    key: usize,
+    /// The counter of the timeout, as slab keys are reused once a timeout is removed.
+    counter: u64,
+}
+
 // Internal representation of a timeout registered in the TimerWheel
 #[derive(Debug)]
 struct TimeoutData {
+    token: Token,
+    counter: u64,
+    /// The position of this timeout in the heap.
+    index: usize,
+}
+
+// An entry of the heap of the TimerWheel, which carries what is needed to order the timeouts so
+// that sifting them does not need to look them up.
+#[derive(Debug)]
+struct HeapEntry {
     deadline: Instant,
-    token: RefCell<Option<Token>>,
-    counter: u32,
+    counter: u64,
+    key: usize,
+}
+
+impl HeapEntry {
+    // Earlier deadlines come first, and timeouts with the same deadline expire in insertion order.
+    fn precedes(&self, other: &HeapEntry) -> bool {
+        (self.deadline, self.counter) < (other.deadline, other.counter)
+    }
 }
 
 // A data structure for tracking registered timeouts
+//
+// This is a binary min-heap of timeouts, whose data is stored in a slab. Each timeout keeps track
+// of its position in the heap, so that it can be removed from the heap directly when it is
+// cancelled, rather than when it would have expired. Inserting and cancelling a timeout are thus
+// O(log n), and getting the next deadline is O(1).
 #[derive(Debug)]
 pub(crate) struct TimerWheel {
-    heap: BinaryHeap<TimeoutData>,
-    counter: u32,
+    timeouts: Slab<TimeoutData>,
+    heap: Vec<HeapEntry>,
+    counter: u64,
 }
 
 impl TimerWheel {
     pub(crate) fn new() -> TimerWheel {
         TimerWheel {
-            heap: BinaryHeap::new(),
+            timeouts: Slab::new(),
+            heap: Vec::new(),
             counter: 0,
         }
     }
 
-    pub(crate) fn insert(&mut self, deadline: Instant, token: Token) -> u32 {
-        self.heap.push(TimeoutData {
-            deadline,
-            token: RefCell::new(Some(token)),
-            counter: self.counter,
-        });
-        let ret = self.counter;
+    pub(crate) fn insert(&mut self, deadline: Instant, token: Token) -> TimeoutId {
+        let counter = self.counter;
         self.counter += 1;
-        ret
-    }
 
-    pub(crate) fn insert_reuse(&mut self, counter: u32, deadline: Instant, token: Token) {
-        self.heap.push(TimeoutData {
+        let index = self.heap.len();
+        let key = self.timeouts.insert(TimeoutData {
+            token,
+            counter,
+            index,
+        });
+        self.heap.push(HeapEntry {
             deadline,
-            token: RefCell::new(Some(token)),
             counter,
+            key,
         });
-    }
+        self.sift_up(index);
 
-    pub(crate) fn cancel(&mut self, counter: u32) {
-        self.heap
-            .iter()
-            .find(|data| data.counter == counter)
-            .map(|data| data.token.take());
+        TimeoutId { key, counter }
     }
 
-    pub(crate) fn next_expired(&mut self, now: Instant) -> Option<(u32, Token)> {
-        loop {
-            // check if there is an expired item
-            if let Some(data) = self.heap.peek() {
-                if data.deadline > now {
-                    return None;
-                }
-                // there is an expired timeout, continue the
-                // loop body
-            } else {
-                return None;
+    pub(crate) fn cancel(&mut self, id: TimeoutId) {
+        // The timeout may already have expired, and its key been reused.
+        match self.timeouts.get(id.key) {
+            Some(data) if data.counter == id.counter => {
+                self.remove_at(data.index);
             }
+            _ => {}
+        }
+    }
 
-            // There is an item in the heap, this unwrap cannot blow
-            let data = self.heap.pop().unwrap();
-            if let Some(token) = data.token.into_inner() {
-                return Some((data.counter, token));
-            }
-            // otherwise this timeout was cancelled, continue looping
+    pub(crate) fn next_expired(&mut self, now: Instant) -> Option<Token> {
+        if self.heap.first()?.deadline > now {
+            return None;
         }
+        Some(self.remove_at(0).token)
     }
 
-    pub(crate) fn next_deadline(&self) -> Option<std::time::Instant> {
-        self.heap.peek().map(|data| data.deadline)
+    pub(crate) fn next_deadline(&self) -> Option<Instant> {
+        self.heap.first().map(|entry| entry.deadline)
     }
-}
 
-// trait implementations for TimeoutData
+    // The number of pending timeouts of the source of the given ID.
+    pub(crate) fn count_for(&self, key: usize) -> usize {
+        self.timeouts
+            .iter()
+            .filter(|(_, timeout)| timeout.token.key & MAX_SOURCES_MASK == key)
+            .count()
+    }
 
-impl std::cmp::Ord for TimeoutData {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        // earlier values have priority
-        self.deadline.cmp(&other.deadline).reverse()
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.heap.len()
     }
-}
 
-impl std::cmp::PartialOrd for TimeoutData {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.cmp(other))
+    // Remove the timeout at the given position of the heap.
+    fn remove_at(&mut self, index: usize) -> TimeoutData {
+        let entry = self.heap.swap_remove(index);
+
+        if index < self.heap.len() {
+            // The timeout moved from the end of the heap may need to go either way.
+            self.timeouts[self.heap[index].key].index = index;
+            if !self.sift_up(index) {
+                self.sift_down(index);
+            }
+        }
+
+        self.timeouts.remove(entry.key)
     }
-}
 
-// This impl is required for PartialOrd but actually never used
-// and the type is private, so ignore its coverage
-impl std::cmp::PartialEq for TimeoutData {
-    #[cfg_attr(feature = "nightly_coverage", no_coverage)]
-    fn eq(&self, other: &Self) -> bool {
-        self.deadline == other.deadline
+    // Move the timeout at the given position up the heap, returns whether it moved.
+    fn sift_up(&mut self, start: usize) -> bool {
+        let mut index = start;
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if !self.heap[index].precedes(&self.heap[parent]) {
+                break;
+            }
+            self.heap.swap(index, parent);
+            self.timeouts[self.heap[index].key].index = index;
+            index = parent;
+        }
+        if index != start {
+            self.timeouts[self.heap[index].key].index = index;
+        }
+        index != start
     }
-}
 
-impl std::cmp::Eq for TimeoutData {}
+    // Move the timeout at the given position down the heap.
+    fn sift_down(&mut self, start: usize) {
+        let len = self.heap.len();
+        let mut index = start;
+        loop {
+            let left = 2 * index + 1;
+            if left >= len {
+                break;
+            }
+            let right = left + 1;
+            let child = if right < len && self.heap[right].precedes(&self.heap[left]) {
+                right
+            } else {
+                left
+            };
+            if !self.heap[child].precedes(&self.heap[index]) {
+                break;
+            }
+            self.heap.swap(index, child);
+            self.timeouts[self.heap[index].key].index = index;
+            index = child;
+        }
+        if index != start {
+            self.timeouts[self.heap[index].key].index = index;
+        }
+    }
+}
 
 // Logic for timer futures
 
 /// A future that resolves once a certain timeout is expired
 pub struct TimeoutFuture {
     deadline: Option<Instant>,
+    clock: Rc<dyn LoopClock>,
     waker: Rc<RefCell<Option<Waker>>>,
 }
 
@@ -308,7 +450,7 @@ impl std::fmt::Debug for TimeoutFuture {
 impl TimeoutFuture {
     /// Create a future that resolves after a given duration
     pub fn from_duration<Data>(handle: &LoopHandle<'_, Data>, duration: Duration) -> TimeoutFuture {
-        Self::from_deadline_inner(handle, Instant::now().checked_add(duration))
+        Self::from_deadline_inner(handle, handle.now().checked_add(duration))
     }
 
     /// Create a future that resolves at a given instant
@@ -335,7 +477,11 @@ impl TimeoutFuture {
             })
             .unwrap();
 
-        TimeoutFuture { deadline, waker }
+        TimeoutFuture {
+            deadline,
+            clock: handle.clock(),
+            waker,
+        }
     }
 }
 
@@ -350,7 +496,7 @@ impl std::future::Future for TimeoutFuture {
             None => return std::task::Poll::Pending,
 
             Some(deadline) => {
-                if Instant::now() >= deadline {
+                if self.clock.now() >= deadline {
                     return std::task::Poll::Ready(());
                 }
             }
@@ -421,6 +567,73 @@ mod tests {
         assert!(dispatched);
     }
 
+    #[test]
+    fn deadline_of_duration() {
+        let clock = crate::clock::ManualClock::new();
+        let event_loop = EventLoop::<()>::with_clock(clock.clone()).unwrap();
+
+        let timer = Timer::from_duration(Duration::from_secs(1));
+        assert_eq!(timer.current_deadline(), None);
+
+        clock.advance(Duration::from_secs(5));
+        let dispatcher = Dispatcher::new(timer, |_, &mut (), &mut ()| TimeoutAction::Drop);
+        event_loop
+            .handle()
+            .register_dispatcher(dispatcher.clone())
+            .unwrap();
+        assert_eq!(
+            dispatcher.as_source_ref().current_deadline(),
+            Some(clock.now() + Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn set_duration_of_registered_timer() {
+        let clock = crate::clock::ManualClock::new();
+        let mut event_loop = EventLoop::<u32>::with_clock(clock.clone()).unwrap();
+        let handle = event_loop.handle();
+        let dispatcher = Dispatcher::new(
+            Timer::from_duration(Duration::from_secs(1)),
+            |_, &mut (), fired: &mut u32| {
+                *fired += 1;
+                TimeoutAction::Drop
+            },
+        );
+        let token = handle.register_dispatcher(dispatcher.clone()).unwrap();
+        let mut fired = 0;
+
+        // Updated right away, the duration is counted from now.
+        clock.advance(Duration::from_millis(500));
+        dispatcher
+            .as_source_mut()
+            .set_duration(Duration::from_secs(2));
+        handle.update(&token).unwrap();
+        assert_eq!(
+            dispatcher.as_source_ref().current_deadline(),
+            Some(clock.now() + Duration::from_secs(2))
+        );
+        clock.advance(Duration::from_secs(1));
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut fired)
+            .unwrap();
+        assert_eq!(fired, 0);
+
+        // Otherwise, it is counted from the previous deadline.
+        dispatcher
+            .as_source_mut()
+            .set_duration(Duration::from_secs(3));
+        clock.advance(Duration::from_secs(1));
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut fired)
+            .unwrap();
+        assert_eq!(fired, 0);
+        clock.advance(Duration::from_secs(3));
+        event_loop
+            .dispatch(Some(Duration::ZERO), &mut fired)
+            .unwrap();
+        assert_eq!(fired, 1);
+    }
+
     #[test]
     fn immediate_timer() {
         let mut event_loop = EventLoop::try_new().unwrap();
@@ -644,4 +857,58 @@ mod tests {
             .unwrap();
         assert_eq!(dispatched, 1);
     }
+
+    #[test]
+    fn wheel_order() {
+        let mut wheel = TimerWheel::new();
+        let now = Instant::now();
+        let token = |key| Token { key };
+
+        // Insert timeouts out of order, with some sharing the same deadline.
+        let deadlines = [5, 3, 8, 3, 1, 9, 5, 2];
+        for (key, &deadline) in deadlines.iter().enumerate() {
+            wheel.insert(now + Duration::from_secs(deadline), token(key));
+        }
+        assert_eq!(wheel.next_deadline(), Some(now + Duration::from_secs(1)));
+        assert_eq!(wheel.next_expired(now), None);
+
+        let mut expired = Vec::new();
+        while let Some(token) = wheel.next_expired(now + Duration::from_secs(10)) {
+            expired.push(token.key);
+        }
+        // Timeouts with the same deadline expire in insertion order.
+        assert_eq!(expired, [4, 7, 1, 3, 0, 6, 2, 5]);
+        assert_eq!(wheel.next_deadline(), None);
+    }
+
+    #[test]
+    fn wheel_cancel() {
+        let mut wheel = TimerWheel::new();
+        let now = Instant::now();
+
+        let ids = (0..100)
+            .map(|key| wheel.insert(now + Duration::from_millis(key as u64), Token { key }))
+            .collect::<Vec<_>>();
+
+        // Cancelled timeouts are removed right away.
+        for id in ids.iter().step_by(2) {
+            wheel.cancel(*id);
+        }
+        assert_eq!(wheel.len(), 50);
+        assert_eq!(wheel.next_deadline(), Some(now + Duration::from_millis(1)));
+
+        // Cancelling an expired timeout does not cancel the one now using its slot.
+        let first = wheel.next_expired(now + Duration::from_millis(1)).unwrap();
+        assert_eq!(first.key, 1);
+        let new = wheel.insert(now, Token { key: 100 });
+        wheel.cancel(ids[1]);
+        assert_eq!(wheel.len(), 50);
+
+        wheel.cancel(new);
+        let mut expired = Vec::new();
+        while let Some(token) = wheel.next_expired(now + Duration::from_secs(1)) {
+            expired.push(token.key);
+        }
+        assert_eq!(expired, (3..100).step_by(2).collect::<Vec<_>>());
+    }
 }
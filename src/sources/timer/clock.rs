@@ -0,0 +1,360 @@
+//! Timers following a specific system clock, backed by a `timerfd`
+
+use std::{
+    convert::TryInto,
+    io,
+    os::unix::io::{AsRawFd, FromRawFd},
+    time::{Duration, SystemTime},
+};
+
+use io_lifetimes::OwnedFd;
+use nix::{errno::Errno, libc, unistd::read};
+
+use crate::{
+    generic::Generic, EventSource, Interest, Mode, Poll, PostAction, Readiness, Token, TokenFactory,
+};
+
+/// The clock a [`ClockTimer`] follows
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Clock {
+    /// The monotonic clock, which stops while the system is suspended (`CLOCK_MONOTONIC`)
+    Monotonic,
+    /// The monotonic clock, including the time the system spends suspended (`CLOCK_BOOTTIME`)
+    Boottime,
+    /// The wall clock (`CLOCK_REALTIME`)
+    Realtime,
+}
+
+impl Clock {
+    fn id(self) -> libc::clockid_t {
+        match self {
+            Clock::Monotonic => libc::CLOCK_MONOTONIC,
+            Clock::Boottime => libc::CLOCK_BOOTTIME,
+            Clock::Realtime => libc::CLOCK_REALTIME,
+        }
+    }
+}
+
+/// An event generated by a [`ClockTimer`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClockTimerEvent {
+    /// The deadline of the timer has been reached
+    ///
+    /// The timer is now inactive until it is given a new deadline.
+    Expired,
+    /// The wall clock was changed discontinuously
+    ///
+    /// Only generated for realtime timers set to an absolute time, with
+    /// [`ClockTimer::set_notify_clock_change()`] enabled. The timer is still armed for the same
+    /// time of the wall clock.
+    ClockChanged,
+}
+
+/// Action to take after an event of a [`ClockTimer`]
+#[derive(Debug)]
+pub enum ClockTimeoutAction {
+    /// Remove the timer from the event loop
+    Drop,
+    /// Keep the timer as it is
+    ///
+    /// After [`ClockTimerEvent::Expired`], this leaves the timer inactive in the event loop.
+    Continue,
+    /// Reschedule the timer to a given [`Duration`] in the future, as measured by its clock
+    ToDuration(Duration),
+    /// Reschedule the timer to a given time of the wall clock
+    ///
+    /// For timers not following [`Clock::Realtime`], this is converted to a duration relative to
+    /// the current time of the wall clock.
+    ToSystemTime(SystemTime),
+}
+
+/// A timer following a specific system clock
+///
+/// Only available on Linux and Android.
+///
+/// Unlike [`Timer`](super::Timer), which is tracked by the event loop itself and follows the
+/// monotonic clock, a `ClockTimer` lets you choose the clock its deadline is measured against:
+///
+/// - [`Clock::Monotonic`] does not count the time the system spends suspended, like [`Instant`].
+/// - [`Clock::Boottime`] keeps counting while the system is suspended.
+/// - [`Clock::Realtime`] follows the wall clock, like [`SystemTime`], which allows firing at a
+///   given time of the day. A realtime timer can also be notified when the wall clock is changed
+///   discontinuously, for example by the user or by NTP, see
+///   [`set_notify_clock_change()`](Self::set_notify_clock_change).
+///
+/// Each `ClockTimer` uses its own file descriptor.
+///
+/// [`Instant`]: std::time::Instant
+#[derive(Debug)]
+pub struct ClockTimer {
+    fd: Generic<OwnedFd>,
+    clock: Clock,
+    /// The wall clock deadline of the timer, if it is a realtime timer set to an absolute time.
+    system_time: Option<SystemTime>,
+    notify_clock_change: bool,
+}
+
+impl ClockTimer {
+    /// Create an inactive timer following the given clock
+    pub fn new(clock: Clock) -> io::Result<ClockTimer> {
+        let fd =
+            unsafe { libc::timerfd_create(clock.id(), libc::TFD_NONBLOCK | libc::TFD_CLOEXEC) };
+        let fd = Errno::result(fd)?;
+
+        Ok(ClockTimer {
+            fd: Generic::new(
+                unsafe { OwnedFd::from_raw_fd(fd) },
+                Interest::READ,
+                Mode::Level,
+            ),
+            clock,
+            system_time: None,
+            notify_clock_change: false,
+        })
+    }
+
+    /// Create a timer that fires after the given duration, as measured by the given clock
+    pub fn from_duration(clock: Clock, duration: Duration) -> io::Result<ClockTimer> {
+        let mut timer = ClockTimer::new(clock)?;
+        timer.set_duration(duration)?;
+        Ok(timer)
+    }
+
+    /// Create a realtime timer that fires at the given time of the wall clock
+    pub fn from_system_time(time: SystemTime) -> io::Result<ClockTimer> {
+        let mut timer = ClockTimer::new(Clock::Realtime)?;
+        timer.set_system_time(time)?;
+        Ok(timer)
+    }
+
+    /// The clock this timer follows
+    pub fn clock(&self) -> Clock {
+        self.clock
+    }
+
+    /// Set the timer to fire after the given duration, as measured by its clock
+    ///
+    /// This replaces the current deadline of the timer, if any.
+    pub fn set_duration(&mut self, duration: Duration) -> io::Result<()> {
+        self.system_time = None;
+        self.settime(duration, 0)
+    }
+
+    /// Set the timer to fire at the given time of the wall clock
+    ///
+    /// For timers not following [`Clock::Realtime`], this is converted to a duration relative to
+    /// the current time of the wall clock. This replaces the current deadline of the timer, if
+    /// any.
+    pub fn set_system_time(&mut self, time: SystemTime) -> io::Result<()> {
+        if self.clock != Clock::Realtime {
+            let duration = time
+                .duration_since(SystemTime::now())
+                .unwrap_or(Duration::ZERO);
+            return self.set_duration(duration);
+        }
+
+        self.system_time = Some(time);
+        let since_epoch = time
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "time before the epoch"))?;
+        let mut flags = libc::TFD_TIMER_ABSTIME;
+        if self.notify_clock_change {
+            flags |= libc::TFD_TIMER_CANCEL_ON_SET;
+        }
+        self.settime(since_epoch, flags)
+    }
+
+    /// Set whether this timer generates [`ClockTimerEvent::ClockChanged`] events
+    ///
+    /// This only has an effect on realtime timers set to an absolute time with
+    /// [`set_system_time()`](Self::set_system_time), and is disabled by default.
+    pub fn set_notify_clock_change(&mut self, notify: bool) -> io::Result<()> {
+        self.notify_clock_change = notify;
+        match self.system_time {
+            Some(time) => self.set_system_time(time),
+            None => Ok(()),
+        }
+    }
+
+    /// Make the timer inactive
+    pub fn unset(&mut self) -> io::Result<()> {
+        self.system_time = None;
+        let spec = libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            it_value: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+        };
+        self.settime_spec(&spec, 0)
+    }
+
+    fn settime(&mut self, value: Duration, flags: i32) -> io::Result<()> {
+        // A zero value disarms the timer, fire as soon as possible instead.
+        let value = value.max(Duration::from_nanos(1));
+        let spec = libc::itimerspec {
+            it_interval: libc::timespec {
+                tv_sec: 0,
+                tv_nsec: 0,
+            },
+            it_value: libc::timespec {
+                tv_sec: value.as_secs().try_into().unwrap_or(libc::time_t::MAX),
+                tv_nsec: value.subsec_nanos() as _,
+            },
+        };
+        self.settime_spec(&spec, flags)
+    }
+
+    fn settime_spec(&mut self, spec: &libc::itimerspec, flags: i32) -> io::Result<()> {
+        let fd = self.fd.file.as_raw_fd();
+        let ret = unsafe { libc::timerfd_settime(fd, flags, spec, std::ptr::null_mut()) };
+        Errno::result(ret)?;
+        Ok(())
+    }
+}
+
+impl EventSource for ClockTimer {
+    type Event = ClockTimerEvent;
+    type Metadata = ();
+    type Ret = ClockTimeoutAction;
+    type Error = io::Error;
+
+    fn process_events<F>(
+        &mut self,
+        readiness: Readiness,
+        token: Token,
+        mut callback: F,
+    ) -> Result<PostAction, Self::Error>
+    where
+        F: FnMut(Self::Event, &mut Self::Metadata) -> Self::Ret,
+    {
+        let mut event = None;
+        self.fd.process_events(readiness, token, |_, fd| {
+            let mut buf = [0u8; 8];
+            match read(fd.as_raw_fd(), &mut buf) {
+                Ok(_) => event = Some(ClockTimerEvent::Expired),
+                Err(Errno::ECANCELED) => event = Some(ClockTimerEvent::ClockChanged),
+                // Spurious wakeup, the timer may have been re-armed in the meantime.
+                Err(Errno::EAGAIN) => {}
+                Err(e) => return Err(e.into()),
+            }
+            Ok(PostAction::Continue)
+        })?;
+
+        let event = match event {
+            Some(event) => event,
+            None => return Ok(PostAction::Continue),
+        };
+        if event == ClockTimerEvent::Expired {
+            self.system_time = None;
+        }
+
+        match callback(event, &mut ()) {
+            ClockTimeoutAction::Drop => return Ok(PostAction::Remove),
+            ClockTimeoutAction::Continue => {}
+            ClockTimeoutAction::ToDuration(duration) => self.set_duration(duration)?,
+            ClockTimeoutAction::ToSystemTime(time) => self.set_system_time(time)?,
+        }
+
+        Ok(PostAction::Continue)
+    }
+
+    fn register(&mut self, poll: &mut Poll, token_factory: &mut TokenFactory) -> crate::Result<()> {
+        self.fd.register(poll, token_factory)
+    }
+
+    fn reregister(
+        &mut self,
+        poll: &mut Poll,
+        token_factory: &mut TokenFactory,
+    ) -> crate::Result<()> {
+        self.fd.reregister(poll, token_factory)
+    }
+
+    fn unregister(&mut self, poll: &mut Poll) -> crate::Result<()> {
+        self.fd.unregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::EventLoop;
+
+    #[test]
+    fn clock_timers() {
+        let mut event_loop = EventLoop::<Vec<Clock>>::try_new().unwrap();
+        let handle = event_loop.handle();
+
+        for clock in [Clock::Monotonic, Clock::Boottime] {
+            let timer = ClockTimer::from_duration(clock, Duration::from_millis(50)).unwrap();
+            handle
+                .insert_source(timer, move |event, &mut (), fired| {
+                    assert_eq!(event, ClockTimerEvent::Expired);
+                    fired.push(clock);
+                    ClockTimeoutAction::Drop
+                })
+                .unwrap();
+        }
+        let timer =
+            ClockTimer::from_system_time(SystemTime::now() + Duration::from_millis(50)).unwrap();
+        handle
+            .insert_source(timer, |event, &mut (), fired| {
+                assert_eq!(event, ClockTimerEvent::Expired);
+                fired.push(Clock::Realtime);
+                ClockTimeoutAction::Drop
+            })
+            .unwrap();
+
+        let mut fired = Vec::new();
+        event_loop.dispatch(Duration::ZERO, &mut fired).unwrap();
+        assert!(fired.is_empty());
+
+        let start = Instant::now();
+        while fired.len() < 3 && start.elapsed() < Duration::from_secs(3) {
+            event_loop
+                .dispatch(Duration::from_millis(100), &mut fired)
+                .unwrap();
+        }
+        fired.sort_by_key(|clock| *clock as u8);
+        assert_eq!(fired, [Clock::Monotonic, Clock::Boottime, Clock::Realtime]);
+        assert!(start.elapsed() >= Duration::from_millis(40));
+    }
+
+    #[test]
+    fn repeating_clock_timer() {
+        let mut event_loop = EventLoop::<u32>::try_new().unwrap();
+
+        let timer = ClockTimer::from_duration(Clock::Boottime, Duration::ZERO).unwrap();
+        event_loop
+            .handle()
+            .insert_source(timer, |_, &mut (), count| {
+                *count += 1;
+                if *count < 3 {
+                    ClockTimeoutAction::ToDuration(Duration::from_millis(10))
+                } else {
+                    ClockTimeoutAction::Continue
+                }
+            })
+            .unwrap();
+
+        let mut count = 0;
+        for _ in 0..3 {
+            event_loop
+                .dispatch(Duration::from_secs(1), &mut count)
+                .unwrap();
+        }
+        assert_eq!(count, 3);
+
+        // The timer is now inactive.
+        event_loop
+            .dispatch(Duration::from_millis(50), &mut count)
+            .unwrap();
+        assert_eq!(count, 3);
+    }
+}
@@ -124,6 +124,7 @@
 #[derive(Debug, Default)]
 pub struct TransientSource<T> {
     state: TransientSourceState<T>,
+    budget: Option<std::num::NonZeroUsize>,
 }
 
 /// This is the internal state of the [`TransientSource`], as a separate type so
@@ -152,6 +153,8 @@ enum TransientSourceState<T> {
     None,
 }
 
+// Deriving it would require `T: Default` on the MSRV
+#[allow(clippy::derivable_impls)]
 impl<T> Default for TransientSourceState<T> {
     fn default() -> Self {
         Self::None
@@ -237,6 +240,7 @@ impl<T: crate::EventSource> From<T> for TransientSource<T> {
     fn from(source: T) -> Self {
         Self {
             state: TransientSourceState::Register(source),
+            budget: None,
         }
     }
 }
@@ -304,6 +308,7 @@ impl<T: crate::EventSource> crate::EventSource for TransientSource<T> {
             TransientSourceState::Register(source)
             | TransientSourceState::Disable(source)
             | TransientSourceState::Replace { new: source, .. } => {
+                source.set_event_budget(self.budget);
                 source.register(poll, token_factory)?;
                 self.state.replace_state(TransientSourceState::Keep);
                 // Drops the disposed source in the Replace case.
@@ -324,6 +329,7 @@ impl<T: crate::EventSource> crate::EventSource for TransientSource<T> {
         match &mut self.state {
             TransientSourceState::Keep(source) => source.reregister(poll, token_factory)?,
             TransientSourceState::Register(source) => {
+                source.set_event_budget(self.budget);
                 source.register(poll, token_factory)?;
                 self.state.replace_state(TransientSourceState::Keep);
             }
@@ -336,6 +342,7 @@ impl<T: crate::EventSource> crate::EventSource for TransientSource<T> {
             }
             TransientSourceState::Replace { new, old } => {
                 old.unregister(poll)?;
+                new.set_event_budget(self.budget);
                 new.register(poll, token_factory)?;
                 self.state.replace_state(TransientSourceState::Keep);
                 // Drops 'dispose'.
@@ -363,6 +370,27 @@ impl<T: crate::EventSource> crate::EventSource for TransientSource<T> {
         }
         Ok(())
     }
+
+    fn set_event_budget(&mut self, budget: Option<std::num::NonZeroUsize>) {
+        self.budget = budget;
+        if let TransientSourceState::Keep(source) = &mut self.state {
+            source.set_event_budget(budget);
+        }
+    }
+
+    fn has_pending_events(&self) -> bool {
+        match &self.state {
+            TransientSourceState::Keep(source) => source.has_pending_events(),
+            _ => false,
+        }
+    }
+
+    fn on_shutdown(&mut self) -> bool {
+        match &mut self.state {
+            TransientSourceState::Keep(source) => source.on_shutdown(),
+            _ => true,
+        }
+    }
 }
 
 #[cfg(test)]
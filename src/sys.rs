@@ -12,11 +12,15 @@ use io_lifetimes::AsFd;
 #[cfg(windows)]
 use io_lifetimes::AsSocket;
 
-use polling::{Event, PollMode, Poller};
+use polling::{Event, Events, PollMode, Poller};
 
-use crate::loop_logic::{MAX_SOURCES, MAX_SUBSOURCES_TOTAL};
+use crate::clock::{LoopClock, SystemClock};
+use crate::loop_logic::{MAX_SOURCES, MAX_SOURCES_MASK, MAX_SUBSOURCES_TOTAL};
 use crate::sources::timer::TimerWheel;
 
+#[cfg(all(feature = "io_uring", target_os = "linux"))]
+mod uring;
+
 /// Possible modes for registering a file descriptor
 #[derive(Copy, Clone, Debug)]
 pub enum Mode {
@@ -54,6 +58,8 @@ pub enum Mode {
 }
 
 /// Interest to register regarding the file descriptor
+///
+/// Error and hang-up conditions are always reported, regardless of the registered interest.
 #[derive(Copy, Clone, Debug)]
 pub struct Interest {
     /// Wait for the FD to be readable
@@ -61,6 +67,17 @@ pub struct Interest {
 
     /// Wait for the FD to be writable
     pub writable: bool,
+
+    /// Wait for the FD to have priority data to read, like TCP out-of-band data
+    ///
+    /// Only supported on Linux/Android, and ignored on other platforms.
+    pub priority: bool,
+
+    /// Wait for the peer of the FD to shut down its writing half of the connection
+    ///
+    /// Only supported on Linux/Android: on other platforms, registering a FD with this interest
+    /// fails with an [`Unsupported`](std::io::ErrorKind) I/O error.
+    pub read_closed: bool,
 }
 
 impl Interest {
@@ -68,24 +85,32 @@ impl Interest {
     pub const EMPTY: Interest = Interest {
         readable: false,
         writable: false,
+        priority: false,
+        read_closed: false,
     };
 
     /// Shorthand for read interest
     pub const READ: Interest = Interest {
         readable: true,
         writable: false,
+        priority: false,
+        read_closed: false,
     };
 
     /// Shorthand for write interest
     pub const WRITE: Interest = Interest {
         readable: false,
         writable: true,
+        priority: false,
+        read_closed: false,
     };
 
     /// Shorthand for read and write interest
     pub const BOTH: Interest = Interest {
         readable: true,
         writable: true,
+        priority: false,
+        read_closed: false,
     };
 }
 
@@ -100,6 +125,20 @@ pub struct Readiness {
 
     /// Is the FD in an error state
     pub error: bool,
+
+    /// Has the FD been hung up
+    ///
+    /// For sockets and pipes, this means both halves of the connection have been shut down.
+    pub hangup: bool,
+
+    /// Has the peer of the FD shut down its writing half of the connection
+    ///
+    /// Only reported if requested with [`Interest::read_closed`], which is only supported on
+    /// Linux/Android.
+    pub read_closed: bool,
+
+    /// Does the FD have priority data to read
+    pub priority: bool,
 }
 
 impl Readiness {
@@ -108,6 +147,9 @@ impl Readiness {
         readable: false,
         writable: false,
         error: false,
+        hangup: false,
+        read_closed: false,
+        priority: false,
     };
 }
 
@@ -179,24 +221,49 @@ pub struct Token {
 /// And even in this case, you can often just use the [`Generic`](crate::generic::Generic) event
 /// source and delegate the implementations to it.
 pub struct Poll {
-    /// The handle to wepoll/epoll/kqueue/... used to poll for events.
-    poller: Arc<Poller>,
+    /// The system used to poll for events.
+    backend: Backend,
 
-    /// The buffer of events returned by the poller.
-    events: RefCell<Vec<Event>>,
+    pub(crate) timers: Rc<RefCell<TimerWheel>>,
 
-    /// The sources registered as level triggered.
-    ///
-    /// Some platforms that `polling` supports do not support level-triggered events. As of the time
-    /// of writing, this only includes Solaris and illumos. To work around this, we emulate level
-    /// triggered events by keeping this map of file descriptors.
-    ///
-    /// One can emulate level triggered events on top of oneshot events by just re-registering the
-    /// file descriptor every time it is polled. However, this is not ideal, as it requires a
-    /// system call every time. It's better to use the intergrated system, if available.
-    level_triggered: Option<RefCell<HashMap<usize, (Raw, polling::Event)>>>,
+    /// The clock the timers follow.
+    pub(crate) clock: Rc<dyn LoopClock>,
 
-    pub(crate) timers: Rc<RefCell<TimerWheel>>,
+    /// The ID of the source owning each registered file descriptor.
+    registered: RefCell<HashMap<Raw, usize>>,
+}
+
+enum Backend {
+    /// A poller from the `polling` crate.
+    Polling {
+        /// The handle to wepoll/epoll/kqueue/... used to poll for events.
+        poller: Arc<Poller>,
+
+        /// The buffer of events returned by the poller.
+        events: RefCell<Events>,
+
+        /// The sources registered as level triggered.
+        ///
+        /// Some platforms that `polling` supports do not support level-triggered events. As of the time
+        /// of writing, this only includes Solaris and illumos. To work around this, we emulate level
+        /// triggered events by keeping this map of file descriptors.
+        ///
+        /// One can emulate level triggered events on top of oneshot events by just re-registering the
+        /// file descriptor every time it is polled. However, this is not ideal, as it requires a
+        /// system call every time. It's better to use the intergrated system, if available.
+        level_triggered: Option<RefCell<HashMap<usize, (Raw, polling::Event)>>>,
+
+        /// The sources registered with the `read_closed` interest.
+        ///
+        /// `polling` does not support `EPOLLRDHUP`, so it is added to their registration behind
+        /// its back, and the half-close is checked for when they are reported.
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        read_closed: RefCell<HashMap<usize, (Raw, polling::Event, PollMode)>>,
+    },
+
+    /// An io_uring instance, submitting poll requests for the registered file descriptors.
+    #[cfg(all(feature = "io_uring", target_os = "linux"))]
+    IoUring(Box<uring::Ring>),
 }
 
 impl std::fmt::Debug for Poll {
@@ -208,6 +275,22 @@ impl std::fmt::Debug for Poll {
 
 impl Poll {
     pub(crate) fn new() -> crate::Result<Poll> {
+        #[cfg(all(feature = "io_uring", target_os = "linux"))]
+        match uring::Ring::new() {
+            Ok(ring) => {
+                return Ok(Poll {
+                    backend: Backend::IoUring(Box::new(ring)),
+                    timers: Rc::new(RefCell::new(TimerWheel::new())),
+                    clock: Rc::new(SystemClock),
+                    registered: RefCell::new(HashMap::new()),
+                })
+            }
+            Err(e) => log::warn!(
+                "[calloop] Failed to create an io_uring instance, falling back to epoll: {}",
+                e
+            ),
+        }
+
         Self::new_inner(false)
     }
 
@@ -220,66 +303,104 @@ impl Poll {
         };
 
         Ok(Poll {
-            poller: Arc::new(poller),
-            events: RefCell::new(Vec::new()),
+            backend: Backend::Polling {
+                poller: Arc::new(poller),
+                events: RefCell::new(Events::new()),
+                level_triggered,
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                read_closed: RefCell::new(HashMap::new()),
+            },
             timers: Rc::new(RefCell::new(TimerWheel::new())),
-            level_triggered,
+            clock: Rc::new(SystemClock),
+            registered: RefCell::new(HashMap::new()),
         })
     }
 
     pub(crate) fn poll(&self, mut timeout: Option<Duration>) -> crate::Result<Vec<PollEvent>> {
-        let now = std::time::Instant::now();
-
         // Adjust the timeout for the timers.
         if let Some(next_timeout) = self.timers.borrow().next_deadline() {
-            if next_timeout <= now {
-                timeout = Some(Duration::ZERO);
-            } else if let Some(deadline) = timeout {
-                timeout = Some(std::cmp::min(deadline, next_timeout - now));
+            let until_timer = if next_timeout <= self.clock.now() {
+                Some(Duration::ZERO)
             } else {
-                timeout = Some(next_timeout - now);
-            }
+                self.clock.duration_until(next_timeout)
+            };
+            timeout = match (timeout, until_timer) {
+                (Some(timeout), Some(until_timer)) => Some(std::cmp::min(timeout, until_timer)),
+                (timeout, until_timer) => timeout.or(until_timer),
+            };
         };
 
-        let mut events = self.events.borrow_mut();
-        self.poller.wait(&mut events, timeout)?;
-
-        // Convert `polling` events to `calloop` events.
-        let level_triggered = self.level_triggered.as_ref().map(RefCell::borrow);
-        let mut poll_events = events
-            .drain(..)
-            .map(|ev| {
-                // If we need to emulate level-triggered events...
-                if let Some(level_triggered) = level_triggered.as_ref() {
-                    // ...and this event is from a level-triggered source...
-                    if let Some((source, interest)) = level_triggered.get(&ev.key) {
-                        // ...then we need to re-register the source.
-                        self.poller.modify(source, *interest)?;
-                    }
-                }
-
-                Ok(PollEvent {
-                    readiness: Readiness {
-                        readable: ev.readable,
-                        writable: ev.writable,
-                        error: false,
-                    },
-                    token: Token { key: ev.key },
-                })
-            })
-            .collect::<std::io::Result<Vec<_>>>()?;
-
-        drop(events);
+        let mut poll_events = match &self.backend {
+            Backend::Polling {
+                poller,
+                events,
+                level_triggered,
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                read_closed,
+            } => {
+                let mut events = events.borrow_mut();
+                poller.wait(&mut events, timeout)?;
+
+                // Convert `polling` events to `calloop` events.
+                let level_triggered = level_triggered.as_ref().map(RefCell::borrow);
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                let read_closed = read_closed.borrow();
+                let poll_events = events
+                    .iter()
+                    .map(|ev| {
+                        // If we need to emulate level-triggered events...
+                        if let Some(level_triggered) = level_triggered.as_ref() {
+                            // ...and this event is from a level-triggered source...
+                            if let Some((source, interest)) = level_triggered.get(&ev.key) {
+                                // ...then we need to re-register the source.
+                                //
+                                // Safety: the source is removed from this map when it is
+                                // unregistered, so it is still open.
+                                poller.modify(unsafe { borrow_raw(*source) }, *interest)?;
+                                #[cfg(any(target_os = "linux", target_os = "android"))]
+                                if let Some(&(source, interest, mode)) = read_closed.get(&ev.key) {
+                                    add_read_closed(poller, source, interest, mode)?;
+                                }
+                            }
+                        }
+
+                        #[cfg(any(target_os = "linux", target_os = "android"))]
+                        let is_read_closed = match read_closed.get(&ev.key) {
+                            Some(&(source, _, _)) => has_read_closed(source)?,
+                            None => false,
+                        };
+                        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+                        let is_read_closed = false;
+
+                        Ok(PollEvent {
+                            readiness: Readiness {
+                                readable: ev.readable,
+                                writable: ev.writable,
+                                error: ev.is_err().unwrap_or(false),
+                                hangup: ev.is_interrupt(),
+                                read_closed: is_read_closed,
+                                priority: ev.is_priority(),
+                            },
+                            token: Token { key: ev.key },
+                        })
+                    })
+                    .collect::<std::io::Result<Vec<_>>>()?;
+                events.clear();
+
+                poll_events
+            }
+            #[cfg(all(feature = "io_uring", target_os = "linux"))]
+            Backend::IoUring(ring) => ring.poll(timeout)?,
+        };
 
         // Update 'now' as some time may have elapsed in poll()
-        let now = std::time::Instant::now();
+        let now = self.clock.now();
         let mut timers = self.timers.borrow_mut();
-        while let Some((_, token)) = timers.next_expired(now) {
+        while let Some(token) = timers.next_expired(now) {
             poll_events.push(PollEvent {
                 readiness: Readiness {
                     readable: true,
-                    writable: false,
-                    error: false,
+                    ..Readiness::EMPTY
                 },
                 token,
             });
@@ -320,17 +441,49 @@ impl Poll {
             }
         };
 
-        let ev = cvt_interest(interest, token);
-        self.poller
-            .add_with_mode(raw, ev, cvt_mode(mode, self.poller.supports_level()))?;
+        match &self.backend {
+            Backend::Polling {
+                poller,
+                level_triggered,
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                read_closed,
+                ..
+            } => {
+                check_interest(interest)?;
+                let ev = cvt_interest(interest, token);
+                let poll_mode = cvt_mode(mode, poller.supports_level());
+                // Safety: the FD is required to be unregistered before being closed, which all
+                // event sources do in their `unregister()` implementation.
+                unsafe {
+                    poller.add_with_mode(raw, ev, poll_mode)?;
+                }
+
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                if interest.read_closed {
+                    if let Err(err) = add_read_closed(poller, raw, ev, poll_mode) {
+                        let _ = poller.delete(&fd);
+                        return Err(err.into());
+                    }
+                    read_closed
+                        .borrow_mut()
+                        .insert(ev.key, (raw, ev, poll_mode));
+                }
 
-        // If this is level triggered and we're emulating level triggered mode...
-        if let (Mode::Level, Some(level_triggered)) = (mode, self.level_triggered.as_ref()) {
-            // ...then we need to keep track of the source.
-            let mut level_triggered = level_triggered.borrow_mut();
-            level_triggered.insert(ev.key, (raw, ev));
+                // If this is level triggered and we're emulating level triggered mode...
+                if let (Mode::Level, Some(level_triggered)) = (mode, level_triggered.as_ref()) {
+                    // ...then we need to keep track of the source.
+                    let mut level_triggered = level_triggered.borrow_mut();
+                    level_triggered.insert(ev.key, (raw, ev));
+                }
+            }
+            #[cfg(all(feature = "io_uring", target_os = "linux"))]
+            Backend::IoUring(ring) => ring.register(raw, interest, mode, token)?,
         }
 
+        self.registered
+            .borrow_mut()
+            .insert(raw, token.key & MAX_SOURCES_MASK);
+
         Ok(())
     }
 
@@ -360,17 +513,44 @@ impl Poll {
             }
         };
 
-        let ev = cvt_interest(interest, token);
-        self.poller
-            .modify_with_mode(raw, ev, cvt_mode(mode, self.poller.supports_level()))?;
+        match &self.backend {
+            Backend::Polling {
+                poller,
+                level_triggered,
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                read_closed,
+                ..
+            } => {
+                check_interest(interest)?;
+                let ev = cvt_interest(interest, token);
+                let poll_mode = cvt_mode(mode, poller.supports_level());
+                poller.modify_with_mode(&fd, ev, poll_mode)?;
+
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                {
+                    let mut read_closed = read_closed.borrow_mut();
+                    read_closed.retain(|_, (source, _, _)| *source != raw);
+                    if interest.read_closed {
+                        add_read_closed(poller, raw, ev, poll_mode)?;
+                        read_closed.insert(ev.key, (raw, ev, poll_mode));
+                    }
+                }
 
-        // If this is level triggered and we're emulating level triggered mode...
-        if let (Mode::Level, Some(level_triggered)) = (mode, self.level_triggered.as_ref()) {
-            // ...then we need to keep track of the source.
-            let mut level_triggered = level_triggered.borrow_mut();
-            level_triggered.insert(ev.key, (raw, ev));
+                // If this is level triggered and we're emulating level triggered mode...
+                if let (Mode::Level, Some(level_triggered)) = (mode, level_triggered.as_ref()) {
+                    // ...then we need to keep track of the source.
+                    let mut level_triggered = level_triggered.borrow_mut();
+                    level_triggered.insert(ev.key, (raw, ev));
+                }
+            }
+            #[cfg(all(feature = "io_uring", target_os = "linux"))]
+            Backend::IoUring(ring) => ring.reregister(raw, interest, mode, token)?,
         }
 
+        self.registered
+            .borrow_mut()
+            .insert(raw, token.key & MAX_SOURCES_MASK);
+
         Ok(())
     }
 
@@ -394,40 +574,174 @@ impl Poll {
                 fd.as_socket().as_raw_socket()
             }
         };
-        self.poller.delete(raw)?;
+        match &self.backend {
+            Backend::Polling {
+                poller,
+                level_triggered,
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                read_closed,
+                ..
+            } => {
+                poller.delete(&fd)?;
+
+                if let Some(level_triggered) = level_triggered.as_ref() {
+                    let mut level_triggered = level_triggered.borrow_mut();
+                    level_triggered.retain(|_, (source, _)| *source != raw);
+                }
+
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                read_closed
+                    .borrow_mut()
+                    .retain(|_, (source, _, _)| *source != raw);
+            }
+            #[cfg(all(feature = "io_uring", target_os = "linux"))]
+            Backend::IoUring(ring) => ring.unregister(raw)?,
+        }
+
+        self.registered.borrow_mut().remove(&raw);
+
+        Ok(())
+    }
 
-        if let Some(level_triggered) = self.level_triggered.as_ref() {
-            let mut level_triggered = level_triggered.borrow_mut();
-            level_triggered.retain(|_, (source, _)| *source != raw);
+    /// The number of file descriptors registered by the source of the given ID.
+    pub(crate) fn registered_fds(&self, key: usize) -> usize {
+        self.registered
+            .borrow()
+            .values()
+            .filter(|&&source| source == key)
+            .count()
+    }
+
+    /// Notify the polling system that the events it returned have been processed.
+    pub(crate) fn flush(&self) -> crate::Result<()> {
+        match &self.backend {
+            Backend::Polling { .. } => {}
+            #[cfg(all(feature = "io_uring", target_os = "linux"))]
+            Backend::IoUring(ring) => ring.flush()?,
         }
 
         Ok(())
     }
 
+    /// Get the file descriptor of the underlying polling system.
+    ///
+    /// It becomes readable when events are ready to be processed, except for timers.
+    #[cfg(unix)]
+    pub(crate) fn as_raw_fd(&self) -> Raw {
+        match &self.backend {
+            Backend::Polling { poller, .. } => poller.as_raw_fd(),
+            #[cfg(all(feature = "io_uring", target_os = "linux"))]
+            Backend::IoUring(ring) => ring.as_raw_fd(),
+        }
+    }
+
     /// Get a thread-safe handle which can be used to wake up the `Poll`.
     pub(crate) fn notifier(&self) -> Notifier {
-        Notifier(self.poller.clone())
+        match &self.backend {
+            Backend::Polling { poller, .. } => Notifier::Polling(poller.clone()),
+            #[cfg(all(feature = "io_uring", target_os = "linux"))]
+            Backend::IoUring(ring) => Notifier::IoUring(ring.waker()),
+        }
     }
 }
 
 /// Thread-safe handle which can be used to wake up the `Poll`.
 #[derive(Clone)]
-pub(crate) struct Notifier(Arc<Poller>);
+pub(crate) enum Notifier {
+    Polling(Arc<Poller>),
+    #[cfg(all(feature = "io_uring", target_os = "linux"))]
+    IoUring(Arc<io_lifetimes::OwnedFd>),
+}
 
 impl Notifier {
     pub(crate) fn notify(&self) -> crate::Result<()> {
-        self.0.notify()?;
+        match self {
+            Notifier::Polling(poller) => poller.notify()?,
+            #[cfg(all(feature = "io_uring", target_os = "linux"))]
+            Notifier::IoUring(waker) => uring::notify(waker)?,
+        }
 
         Ok(())
     }
 }
 
-fn cvt_interest(interest: Interest, tok: Token) -> Event {
-    Event {
-        readable: interest.readable,
-        writable: interest.writable,
-        key: tok.key,
+// Make sure `polling` can deliver the events of this interest.
+fn check_interest(interest: Interest) -> std::io::Result<()> {
+    if interest.read_closed && cfg!(not(any(target_os = "linux", target_os = "android"))) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "read_closed interest is only supported on Linux/Android",
+        ));
+    }
+    Ok(())
+}
+
+// Add `EPOLLRDHUP` to the registration `polling` made for this FD.
+//
+// The epoll event is built the same way as `polling` does, so that it keeps reporting it.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn add_read_closed(poller: &Poller, raw: Raw, ev: Event, mode: PollMode) -> std::io::Result<()> {
+    use nix::sys::epoll::{epoll_ctl, EpollEvent, EpollFlags, EpollOp};
+
+    let mut flags = match mode {
+        PollMode::Oneshot => EpollFlags::EPOLLONESHOT,
+        PollMode::Level => EpollFlags::empty(),
+        PollMode::Edge => EpollFlags::EPOLLET,
+        PollMode::EdgeOneshot => EpollFlags::EPOLLET | EpollFlags::EPOLLONESHOT,
+        _ => return Err(std::io::ErrorKind::Unsupported.into()),
+    };
+    if ev.readable {
+        flags |= EpollFlags::EPOLLIN
+            | EpollFlags::EPOLLHUP
+            | EpollFlags::EPOLLERR
+            | EpollFlags::EPOLLPRI;
     }
+    if ev.writable {
+        flags |= EpollFlags::EPOLLOUT | EpollFlags::EPOLLHUP | EpollFlags::EPOLLERR;
+    }
+    if ev.is_priority() {
+        flags |= EpollFlags::EPOLLPRI;
+    }
+    flags |= EpollFlags::EPOLLRDHUP;
+
+    let mut event = EpollEvent::new(flags, ev.key as u64);
+    epoll_ctl(poller.as_raw_fd(), EpollOp::EpollCtlMod, raw, &mut event)?;
+    Ok(())
+}
+
+// Check whether the peer of this FD shut down its writing half of the connection.
+//
+// `polling` does not tell which epoll flags were reported, so this is asked to the FD directly.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn has_read_closed(raw: Raw) -> std::io::Result<bool> {
+    use nix::libc;
+
+    let mut fd = libc::pollfd {
+        fd: raw,
+        events: libc::POLLRDHUP,
+        revents: 0,
+    };
+    // Safety: the pollfd is valid for the duration of the call.
+    if unsafe { libc::poll(&mut fd, 1, 0) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(fd.revents & libc::POLLRDHUP != 0)
+}
+
+fn cvt_interest(interest: Interest, tok: Token) -> Event {
+    let mut ev = Event::new(tok.key, interest.readable, interest.writable);
+    ev.set_priority(interest.priority);
+    ev
+}
+
+#[cfg(unix)]
+unsafe fn borrow_raw(raw: Raw) -> io_lifetimes::BorrowedFd<'static> {
+    io_lifetimes::BorrowedFd::borrow_raw(raw)
+}
+
+#[cfg(windows)]
+unsafe fn borrow_raw(raw: Raw) -> io_lifetimes::BorrowedSocket<'static> {
+    io_lifetimes::BorrowedSocket::borrow_raw(raw)
 }
 
 fn cvt_mode(mode: Mode, supports_other_modes: bool) -> PollMode {
@@ -490,4 +804,117 @@ mod tests {
         // Remove the source.
         src.unregister(&mut poll).unwrap();
     }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn readiness_flags() {
+        use std::io::Write;
+        use std::net::{TcpListener, TcpStream};
+        use std::os::unix::{io::FromRawFd, net::UnixStream};
+
+        use nix::sys::socket::{send, MsgFlags};
+
+        let poll = Poll::new().unwrap();
+        let timeout = Some(Duration::from_secs(3));
+
+        // The peer going away is reported as a hang-up, without asking for it.
+        let (tx, rx) = UnixStream::pair().unwrap();
+        poll.register(&rx, Interest::EMPTY, Mode::Level, Token { key: 0 })
+            .unwrap();
+        drop(tx);
+
+        let events = poll.poll(timeout).unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].readiness.hangup);
+        assert!(!events[0].readiness.error);
+        poll.unregister(&rx).unwrap();
+
+        // Urgent data is reported as priority data.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let interest = Interest {
+            priority: true,
+            ..Interest::EMPTY
+        };
+        poll.register(&server, interest, Mode::Level, Token { key: 1 })
+            .unwrap();
+        client.write_all(b"data").unwrap();
+        send(client.as_raw_fd(), b"!", MsgFlags::MSG_OOB).unwrap();
+
+        let events = poll.poll(timeout).unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].readiness.priority);
+        assert!(!events[0].readiness.hangup);
+
+        poll.unregister(&server).unwrap();
+
+        // A pipe whose reading end has been closed is in an error state.
+        let (read, write) = nix::unistd::pipe().unwrap();
+        let (read, write) = unsafe {
+            (
+                io_lifetimes::OwnedFd::from_raw_fd(read),
+                io_lifetimes::OwnedFd::from_raw_fd(write),
+            )
+        };
+        poll.register(&write, Interest::WRITE, Mode::Level, Token { key: 2 })
+            .unwrap();
+        drop(read);
+
+        let events = poll.poll(timeout).unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].readiness.error);
+        poll.unregister(&write).unwrap();
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn read_closed() {
+        use std::os::unix::net::UnixStream;
+
+        let interest = Interest {
+            read_closed: true,
+            ..Interest::EMPTY
+        };
+        let polls = [
+            Poll::new().unwrap(),
+            Poll::new_inner(false).unwrap(),
+            Poll::new_inner(true).unwrap(),
+        ];
+
+        for poll in &polls {
+            let (tx, rx) = UnixStream::pair().unwrap();
+            poll.register(&rx, interest, Mode::Level, Token { key: 0 })
+                .unwrap();
+
+            // Nothing to report yet.
+            let events = poll.poll(Some(Duration::ZERO)).unwrap();
+            assert!(events.is_empty());
+
+            tx.shutdown(std::net::Shutdown::Write).unwrap();
+
+            let events = poll.poll(Some(Duration::from_secs(3))).unwrap();
+            assert_eq!(events.len(), 1);
+            assert!(events[0].readiness.read_closed);
+            assert!(!events[0].readiness.hangup);
+
+            // The interest is kept when the source is registered again.
+            poll.reregister(&rx, interest, Mode::Level, Token { key: 1 })
+                .unwrap();
+            let events = poll.poll(Some(Duration::from_secs(3))).unwrap();
+            assert_eq!(events.len(), 1);
+            assert_eq!(events[0].token, Token { key: 1 });
+            assert!(events[0].readiness.read_closed);
+
+            // And dropped when it is no longer asked for.
+            poll.reregister(&rx, Interest::READ, Mode::Level, Token { key: 2 })
+                .unwrap();
+            let events = poll.poll(Some(Duration::from_secs(3))).unwrap();
+            assert_eq!(events.len(), 1);
+            assert!(events[0].readiness.readable);
+            assert!(!events[0].readiness.read_closed);
+
+            poll.unregister(&rx).unwrap();
+        }
+    }
 }
@@ -0,0 +1,642 @@
+//! io_uring based implementation of the polling backend.
+//!
+//! # Implementation notes
+//!
+//! Every registered file descriptor is watched by a poll request submitted to
+//! the ring. Each registration is identified by a unique id, which is used as
+//! the `user_data` of its poll requests: completions whose id does not match a
+//! current registration come from a request that has since been removed or
+//! replaced, and are ignored.
+//!
+//! The different modes are implemented as follows:
+//!
+//! - `OneShot` submits a single-shot poll request, which is only re-armed when
+//!   the file descriptor is re-registered.
+//! - `Level` submits a single-shot poll request, and re-arms it once its event
+//!   has been processed. If the file descriptor is still ready when the new
+//!   request is submitted, it completes immediately.
+//! - `Edge` submits a multishot poll request, which only completes when the
+//!   file descriptor gets woken up. It is re-armed if the kernel terminates it.
+//!
+//! Submissions are not flushed eagerly: they are accumulated in the submission
+//! queue and submitted along with the wait for completions, so that
+//! registering, modifying and polling file descriptors only costs a single
+//! system call per loop iteration. The exception is when the file descriptor of
+//! the ring is watched by another polling system, which requires the requests to
+//! be submitted for it to become readable.
+//!
+//! The ring is woken up by an eventfd that is itself watched by a poll request
+//! with a reserved id.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    io,
+    os::unix::io::{AsRawFd, FromRawFd, RawFd},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use io_lifetimes::OwnedFd;
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+use nix::{
+    fcntl::{fcntl, FcntlArg},
+    libc,
+    sys::eventfd::{eventfd, EfdFlags},
+    unistd::{read, write},
+};
+
+use super::{Interest, Mode, PollEvent, Readiness, Token};
+
+/// The `user_data` of the poll request watching the waker.
+const WAKER_ID: u64 = u64::MAX;
+
+/// The `user_data` of poll removal requests, whose completions are ignored.
+const REMOVE_ID: u64 = u64::MAX - 1;
+
+/// The number of entries of the submission queue.
+const RING_ENTRIES: u32 = 256;
+
+struct Registration {
+    id: u64,
+    token: Token,
+    interest: Interest,
+    mode: Mode,
+    armed: bool,
+}
+
+pub(crate) struct Ring {
+    ring: RefCell<IoUring>,
+
+    /// The eventfd used to wake up the ring from other threads.
+    waker: Arc<OwnedFd>,
+
+    /// The registered file descriptors.
+    registrations: RefCell<HashMap<RawFd, Registration>>,
+
+    /// The file descriptor associated with each live registration id.
+    ids: RefCell<HashMap<u64, RawFd>>,
+
+    /// The next registration id to use.
+    next_id: Cell<u64>,
+
+    /// The registrations to re-arm once their events have been processed.
+    rearm: RefCell<Vec<(RawFd, u64)>>,
+
+    /// Whether requests are submitted as soon as they are queued, rather than when polling.
+    eager_submit: Cell<bool>,
+}
+
+impl Ring {
+    /// Create a new ring, if the running kernel supports all the features it needs.
+    pub(crate) fn new() -> io::Result<Ring> {
+        let ring = IoUring::new(RING_ENTRIES)?;
+
+        // Waiting with a timeout requires the extended arguments of `io_uring_enter`,
+        // available since Linux 5.11.
+        if !ring.params().is_feature_ext_arg() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "io_uring does not support waiting with a timeout",
+            ));
+        }
+
+        let waker = eventfd(0, EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK)?;
+        let waker = Arc::new(unsafe { OwnedFd::from_raw_fd(waker) });
+
+        let ring = Ring {
+            ring: RefCell::new(ring),
+            waker,
+            registrations: RefCell::new(HashMap::new()),
+            ids: RefCell::new(HashMap::new()),
+            next_id: Cell::new(0),
+            rearm: RefCell::new(Vec::new()),
+            eager_submit: Cell::new(false),
+        };
+        ring.arm_waker()?;
+
+        Ok(ring)
+    }
+
+    pub(crate) fn register(
+        &self,
+        fd: RawFd,
+        interest: Interest,
+        mode: Mode,
+        token: Token,
+    ) -> io::Result<()> {
+        // Poll requests are only submitted on the next call to `poll()`, so we check the
+        // validity of the file descriptor right away to report errors like epoll would.
+        fcntl(fd, FcntlArg::F_GETFD)?;
+
+        let mut registrations = self.registrations.borrow_mut();
+        if registrations.contains_key(&fd) {
+            return Err(io::Error::from_raw_os_error(libc::EEXIST));
+        }
+
+        let mut registration = Registration {
+            id: self.next_id(),
+            token,
+            interest,
+            mode,
+            armed: false,
+        };
+        self.ids.borrow_mut().insert(registration.id, fd);
+        self.arm(fd, &mut registration)?;
+        registrations.insert(fd, registration);
+
+        Ok(())
+    }
+
+    pub(crate) fn reregister(
+        &self,
+        fd: RawFd,
+        interest: Interest,
+        mode: Mode,
+        token: Token,
+    ) -> io::Result<()> {
+        let mut registrations = self.registrations.borrow_mut();
+        let registration = registrations
+            .get_mut(&fd)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+
+        self.disarm(registration)?;
+
+        let mut ids = self.ids.borrow_mut();
+        ids.remove(&registration.id);
+        registration.id = self.next_id();
+        ids.insert(registration.id, fd);
+        drop(ids);
+
+        registration.token = token;
+        registration.interest = interest;
+        registration.mode = mode;
+        self.arm(fd, registration)
+    }
+
+    pub(crate) fn unregister(&self, fd: RawFd) -> io::Result<()> {
+        let mut registration = self
+            .registrations
+            .borrow_mut()
+            .remove(&fd)
+            .ok_or_else(|| io::Error::from_raw_os_error(libc::ENOENT))?;
+
+        self.ids.borrow_mut().remove(&registration.id);
+        self.disarm(&mut registration)
+    }
+
+    pub(crate) fn poll(&self, timeout: Option<Duration>) -> io::Result<Vec<PollEvent>> {
+        self.flush()?;
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut timeout = timeout;
+
+        loop {
+            self.wait(timeout)?;
+
+            let (events, woken) = self.reap()?;
+            if !events.is_empty() || woken {
+                return Ok(events);
+            }
+
+            // Only completions of removed requests were reaped, which epoll would not have
+            // woken up for: keep waiting for the rest of the timeout.
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Ok(events);
+                }
+                timeout = Some(deadline - now);
+            }
+        }
+    }
+
+    /// Re-arm the registrations whose events have been processed.
+    ///
+    /// This must not happen before the events are processed: a level-triggered file descriptor
+    /// would immediately be reported again, even if processing the event drains it.
+    pub(crate) fn flush(&self) -> io::Result<()> {
+        let rearm = std::mem::take(&mut *self.rearm.borrow_mut());
+        let mut registrations = self.registrations.borrow_mut();
+        for (fd, id) in rearm {
+            // The registration may have been changed while processing the events.
+            if let Some(registration) = registrations.get_mut(&fd) {
+                if registration.id == id && !registration.armed {
+                    self.arm(fd, registration)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Submit the pending requests, and wait for at least one completion.
+    fn wait(&self, timeout: Option<Duration>) -> io::Result<()> {
+        let ring = self.ring.borrow();
+        let submitter = ring.submitter();
+        let result = match timeout {
+            Some(timeout) => {
+                let timespec = types::Timespec::from(timeout);
+                let args = types::SubmitArgs::new().timespec(&timespec);
+                submitter.submit_with_args(1, &args)
+            }
+            None => submitter.submit_and_wait(1),
+        };
+
+        match result {
+            Ok(_) => Ok(()),
+            // The timeout expired.
+            Err(e) if e.raw_os_error() == Some(libc::ETIME) => Ok(()),
+            // The completion queue is full, it needs to be drained before submitting more.
+            Err(e) if e.raw_os_error() == Some(libc::EBUSY) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Convert the available completions to events, re-arming the requests that need it.
+    ///
+    /// Also returns whether the ring was woken up through its waker.
+    fn reap(&self) -> io::Result<(Vec<PollEvent>, bool)> {
+        let completions = {
+            let mut ring = self.ring.borrow_mut();
+            ring.completion()
+                .map(|cqe| (cqe.user_data(), cqe.result(), cqe.flags()))
+                .collect::<Vec<_>>()
+        };
+
+        let mut events = Vec::with_capacity(completions.len());
+        let mut woken = false;
+        let mut registrations = self.registrations.borrow_mut();
+        let ids = self.ids.borrow();
+
+        for (id, result, flags) in completions {
+            match id {
+                WAKER_ID => {
+                    self.drain_waker()?;
+                    self.arm_waker()?;
+                    woken = true;
+                    continue;
+                }
+                REMOVE_ID => continue,
+                _ => {}
+            }
+
+            // The request was cancelled or the registration has been replaced since.
+            if result == -libc::ECANCELED {
+                continue;
+            }
+            let fd = match ids.get(&id) {
+                Some(&fd) => fd,
+                None => continue,
+            };
+            let registration = match registrations.get_mut(&fd) {
+                Some(registration) if registration.id == id => registration,
+                _ => continue,
+            };
+
+            // Multishot requests keep watching the file descriptor until told otherwise.
+            if !cqueue::more(flags) {
+                registration.armed = false;
+            }
+
+            // A failed request is reported as an error of the file descriptor, and re-armed like
+            // any other: as with epoll, a level-triggered registration keeps reporting it.
+            let readiness = if result < 0 {
+                Readiness {
+                    error: true,
+                    ..Readiness::EMPTY
+                }
+            } else {
+                cvt_readiness(result as u32, registration.interest)
+            };
+            events.push(PollEvent {
+                readiness,
+                token: registration.token,
+            });
+
+            if !registration.armed && !matches!(registration.mode, Mode::OneShot) {
+                self.rearm.borrow_mut().push((fd, id));
+            }
+        }
+
+        Ok((events, woken))
+    }
+
+    /// Get a thread-safe handle which can be used to wake up the ring.
+    pub(crate) fn waker(&self) -> Arc<OwnedFd> {
+        self.waker.clone()
+    }
+
+    /// Get the file descriptor of the ring, which is readable when completions are available.
+    ///
+    /// The ring can only be watched from the outside if the requests are actually submitted, so
+    /// from then on they are submitted as soon as they are queued.
+    pub(crate) fn as_raw_fd(&self) -> RawFd {
+        if !self.eager_submit.replace(true) {
+            if let Err(e) = self.ring.borrow().submit() {
+                log::warn!("[calloop] Failed to submit io_uring requests: {:?}", e);
+            }
+        }
+
+        self.ring.borrow().as_raw_fd()
+    }
+
+    fn next_id(&self) -> u64 {
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        id
+    }
+
+    fn arm(&self, fd: RawFd, registration: &mut Registration) -> io::Result<()> {
+        let entry = opcode::PollAdd::new(types::Fd(fd), cvt_interest(registration.interest))
+            .multi(matches!(registration.mode, Mode::Edge))
+            .build()
+            .user_data(registration.id);
+        self.push(&entry)?;
+        registration.armed = true;
+
+        Ok(())
+    }
+
+    fn disarm(&self, registration: &mut Registration) -> io::Result<()> {
+        if registration.armed {
+            let entry = opcode::PollRemove::new(registration.id)
+                .build()
+                .user_data(REMOVE_ID);
+            self.push(&entry)?;
+            registration.armed = false;
+        }
+
+        Ok(())
+    }
+
+    fn arm_waker(&self) -> io::Result<()> {
+        let entry = opcode::PollAdd::new(types::Fd(self.waker.as_raw_fd()), libc::POLLIN as u32)
+            .build()
+            .user_data(WAKER_ID);
+        self.push(&entry)
+    }
+
+    fn drain_waker(&self) -> io::Result<()> {
+        let mut buf = [0u8; 8];
+        match read(self.waker.as_raw_fd(), &mut buf) {
+            Ok(_) | Err(nix::errno::Errno::EAGAIN) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn push(&self, entry: &squeue::Entry) -> io::Result<()> {
+        let mut ring = self.ring.borrow_mut();
+
+        // Safety: poll requests do not reference any memory owned by us.
+        let pushed = unsafe { ring.submission().push(entry) };
+        if pushed.is_err() {
+            // The submission queue is full, flush it to make room.
+            ring.submit()?;
+            let pushed = unsafe { ring.submission().push(entry) };
+            pushed.map_err(|_| {
+                io::Error::new(io::ErrorKind::Other, "io_uring submission queue is full")
+            })?;
+        }
+
+        if self.eager_submit.get() {
+            ring.submit()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Wake up the ring owning this waker.
+pub(crate) fn notify(waker: &OwnedFd) -> io::Result<()> {
+    match write(waker.as_raw_fd(), &1u64.to_ne_bytes()) {
+        // The counter hit its cap, which means the ring is already going to wake up.
+        Ok(_) | Err(nix::errno::Errno::EAGAIN) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Readable and writable interests use the same flags as the epoll backend of `polling`.
+fn cvt_interest(interest: Interest) -> u32 {
+    let mut flags = 0;
+    if interest.readable {
+        flags |= libc::POLLIN | libc::POLLPRI;
+    }
+    if interest.writable {
+        flags |= libc::POLLOUT;
+    }
+    if interest.priority {
+        flags |= libc::POLLPRI;
+    }
+    if interest.read_closed {
+        flags |= libc::POLLRDHUP;
+    }
+    flags as u32
+}
+
+// The kernel may report a half-close without being asked to, it is only surfaced on request.
+fn cvt_readiness(revents: u32, interest: Interest) -> Readiness {
+    let revents = revents as i16;
+    Readiness {
+        readable: revents
+            & (libc::POLLIN | libc::POLLPRI | libc::POLLRDHUP | libc::POLLHUP | libc::POLLERR)
+            != 0,
+        writable: revents & (libc::POLLOUT | libc::POLLHUP | libc::POLLERR) != 0,
+        error: revents & libc::POLLERR != 0,
+        hangup: revents & libc::POLLHUP != 0,
+        read_closed: interest.read_closed && revents & libc::POLLRDHUP != 0,
+        priority: revents & libc::POLLPRI != 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    use super::*;
+
+    const TIMEOUT: Option<Duration> = Some(Duration::from_millis(100));
+
+    fn tokens(events: &[PollEvent]) -> Vec<usize> {
+        events.iter().map(|event| event.token.key).collect()
+    }
+
+    #[test]
+    fn level_triggered() {
+        let ring = Ring::new().unwrap();
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+
+        ring.register(
+            rx.as_raw_fd(),
+            Interest::READ,
+            Mode::Level,
+            Token { key: 1 },
+        )
+        .unwrap();
+        assert!(ring.poll(Some(Duration::ZERO)).unwrap().is_empty());
+
+        tx.write_all(&[0]).unwrap();
+
+        // The event is reported as long as the data is not read.
+        for _ in 0..2 {
+            let events = ring.poll(TIMEOUT).unwrap();
+            assert_eq!(tokens(&events), [1]);
+            assert!(events[0].readiness.readable);
+            assert!(!events[0].readiness.writable);
+        }
+
+        (&rx).read_exact(&mut [0]).unwrap();
+        assert!(ring.poll(TIMEOUT).unwrap().is_empty());
+    }
+
+    #[test]
+    fn oneshot() {
+        let ring = Ring::new().unwrap();
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+
+        ring.register(
+            rx.as_raw_fd(),
+            Interest::READ,
+            Mode::OneShot,
+            Token { key: 1 },
+        )
+        .unwrap();
+        tx.write_all(&[0]).unwrap();
+
+        assert_eq!(tokens(&ring.poll(TIMEOUT).unwrap()), [1]);
+        assert!(ring.poll(TIMEOUT).unwrap().is_empty());
+
+        // Re-registering re-arms the file descriptor, with the new token.
+        ring.reregister(
+            rx.as_raw_fd(),
+            Interest::READ,
+            Mode::OneShot,
+            Token { key: 2 },
+        )
+        .unwrap();
+        assert_eq!(tokens(&ring.poll(TIMEOUT).unwrap()), [2]);
+    }
+
+    #[test]
+    fn edge_triggered() {
+        let ring = Ring::new().unwrap();
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+
+        ring.register(rx.as_raw_fd(), Interest::READ, Mode::Edge, Token { key: 1 })
+            .unwrap();
+        tx.write_all(&[0]).unwrap();
+
+        assert_eq!(tokens(&ring.poll(TIMEOUT).unwrap()), [1]);
+        assert!(ring.poll(TIMEOUT).unwrap().is_empty());
+
+        // New data produces a new edge.
+        tx.write_all(&[0]).unwrap();
+        assert_eq!(tokens(&ring.poll(TIMEOUT).unwrap()), [1]);
+    }
+
+    #[test]
+    fn unregister() {
+        let ring = Ring::new().unwrap();
+        let (mut tx, rx) = UnixStream::pair().unwrap();
+
+        ring.register(
+            rx.as_raw_fd(),
+            Interest::READ,
+            Mode::Level,
+            Token { key: 1 },
+        )
+        .unwrap();
+        tx.write_all(&[0]).unwrap();
+        assert_eq!(tokens(&ring.poll(TIMEOUT).unwrap()), [1]);
+
+        ring.unregister(rx.as_raw_fd()).unwrap();
+
+        // The removal of the poll request must not wake up the ring.
+        let before = Instant::now();
+        assert!(ring.poll(TIMEOUT).unwrap().is_empty());
+        assert!(before.elapsed() >= TIMEOUT.unwrap());
+    }
+
+    #[test]
+    fn read_closed() {
+        let ring = Ring::new().unwrap();
+        let (tx, rx) = UnixStream::pair().unwrap();
+
+        let interest = Interest {
+            read_closed: true,
+            ..Interest::EMPTY
+        };
+        ring.register(rx.as_raw_fd(), interest, Mode::Level, Token { key: 1 })
+            .unwrap();
+        tx.shutdown(std::net::Shutdown::Write).unwrap();
+
+        let events = ring.poll(TIMEOUT).unwrap();
+        assert_eq!(tokens(&events), [1]);
+        assert!(events[0].readiness.read_closed);
+        assert!(!events[0].readiness.hangup);
+    }
+
+    #[test]
+    fn failed_request() {
+        let ring = Ring::new().unwrap();
+        let (_tx, rx) = UnixStream::pair().unwrap();
+
+        // Close the file descriptor after its registration, but before its poll request is
+        // submitted. Use a high number, so that it is not reused by other tests meanwhile.
+        let fd = nix::unistd::dup2(rx.as_raw_fd(), 900).unwrap();
+        ring.register(fd, Interest::READ, Mode::Level, Token { key: 1 })
+            .unwrap();
+        nix::unistd::close(fd).unwrap();
+
+        // The failure is reported, and the request re-armed once the event is processed.
+        for _ in 0..2 {
+            let events = ring.poll(TIMEOUT).unwrap();
+            assert_eq!(tokens(&events), [1]);
+            assert!(events[0].readiness.error);
+            ring.flush().unwrap();
+        }
+    }
+
+    #[test]
+    fn registration_errors() {
+        let ring = Ring::new().unwrap();
+        let (_tx, rx) = UnixStream::pair().unwrap();
+        let fd = rx.as_raw_fd();
+
+        let err = ring
+            .reregister(fd, Interest::READ, Mode::Level, Token { key: 1 })
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+        let err = ring.unregister(fd).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOENT));
+
+        ring.register(fd, Interest::READ, Mode::Level, Token { key: 1 })
+            .unwrap();
+        let err = ring
+            .register(fd, Interest::READ, Mode::Level, Token { key: 1 })
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EEXIST));
+
+        drop(rx);
+        let err = ring
+            .register(fd, Interest::READ, Mode::Level, Token { key: 2 })
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EBADF));
+    }
+
+    #[test]
+    fn wake_up() {
+        let ring = Ring::new().unwrap();
+        let waker = ring.waker();
+
+        let thread = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            notify(&waker).unwrap();
+        });
+
+        // Being woken up does not produce any event.
+        assert!(ring.poll(None).unwrap().is_empty());
+        thread.join().unwrap();
+    }
+}